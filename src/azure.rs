@@ -0,0 +1,212 @@
+use crate::config::{AzureDevops as AzureDevopsCfg, Release};
+use crate::release_provider::{AssetNames, ProviderError, PublishResult, ReleaseProvider};
+use crate::templating::{tag_context, tag_version, Templates};
+use crate::utils::{group_command, spawn_tracked};
+use async_trait::async_trait;
+use camino::Utf8Path;
+use eyre::{bail, Context, Result};
+use log::{debug, info};
+use reqwest::header::{ETAG, IF_MATCH};
+use reqwest::{Client, RequestBuilder};
+use serde_json::json;
+use std::env;
+use std::process::Stdio;
+use std::sync::Arc;
+use tokio::{fs, sync::Mutex};
+
+// Env var with the personal access token, which is also the one the
+// azure-devops extension of the az CLI reads.
+const TOKEN_ENV: &str = "AZURE_DEVOPS_EXT_PAT";
+
+/// AzureDevops publishes the archives as a universal package to an azure
+/// artifacts feed with `az artifacts universal publish`, and writes the
+/// changelog to a page of the project's wiki.
+pub struct AzureDevops {
+    asset_names: AssetNames,
+    templates: Arc<Templates>,
+}
+
+impl AzureDevops {
+    pub fn new(asset_names: AssetNames, templates: Arc<Templates>) -> Self {
+        AzureDevops {
+            asset_names,
+            templates,
+        }
+    }
+
+    // Copies the archives to a folder in the dist folder, named as they're
+    // published, and publishes the folder as the package's version.
+    async fn publish_package(
+        &self,
+        release: &Release,
+        cfg: &AzureDevopsCfg,
+        feed: &str,
+        archives: &[String],
+        version: &str,
+    ) -> Result<String> {
+        let dir = Utf8Path::new(&release.dist_folder).join("azure-package");
+        if dir.exists() {
+            fs::remove_dir_all(&dir).await?;
+        }
+        fs::create_dir_all(&dir).await?;
+        for archive in archives {
+            fs::copy(archive, dir.join(self.asset_names.get(archive))).await?;
+        }
+
+        let package = cfg.package.as_deref().unwrap_or(&release.name);
+        let mut cmd = group_command("az");
+        cmd.args(["artifacts", "universal", "publish", "--organization"])
+            .arg(organization_url(cfg))
+            .args(["--feed", feed, "--name", package, "--version", version])
+            .arg("--path")
+            .arg(dir.as_str())
+            .args(["--description", &format!("{} {}", release.name, version)]);
+        // Feeds in a project are scoped to it, the others to the organization.
+        match &cfg.project {
+            Some(project) => cmd.args(["--scope", "project", "--project", project]),
+            None => cmd.args(["--scope", "organization"]),
+        };
+        info!("publishing {} {} to azure feed {}", package, version, feed);
+        let (child, _group) = spawn_tracked(cmd.stdout(Stdio::piped()).stderr(Stdio::piped()))
+            .wrap_err_with(|| "error executing az artifacts universal publish")?;
+        let output = child
+            .wait_with_output()
+            .await
+            .wrap_err_with(|| "error executing az artifacts universal publish")?;
+        debug!(
+            "az artifacts universal publish output: {}",
+            String::from_utf8_lossy(&output.stdout)
+        );
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(ProviderError::from_output(
+                &stderr,
+                format!(
+                    "error executing az artifacts universal publish: {}",
+                    stderr.trim()
+                ),
+            ));
+        }
+
+        let mut url = organization_url(cfg);
+        if let Some(project) = &cfg.project {
+            url.push_str(&format!("/{}", project));
+        }
+        Ok(format!(
+            "{}/_artifacts/feed/{}/UPack/{}/{}",
+            url, feed, package, version
+        ))
+    }
+
+    // Creates or updates the wiki page of the release with the changelog.
+    // Updating needs the page's current version, from its etag.
+    async fn publish_wiki(
+        &self,
+        cfg: &AzureDevopsCfg,
+        token: &str,
+        tag: &str,
+        changelog: &str,
+    ) -> Result<Option<String>> {
+        let (wiki, project) = match (&cfg.wiki, &cfg.project) {
+            (Some(wiki), Some(project)) => (wiki, project),
+            (Some(_), None) => bail!("the azure wiki needs the project it's in"),
+            (None, _) => return Ok(None),
+        };
+        let path = match &wiki.path {
+            Some(path) => self.templates.render(path, tag_context(tag))?,
+            None => format!("/Releases/{}", tag),
+        };
+        let url = format!(
+            "{}/{}/_apis/wiki/wikis/{}/pages",
+            organization_url(cfg),
+            project,
+            wiki.name
+        );
+        let client = Client::new();
+        let auth = |req: RequestBuilder| {
+            req.basic_auth("", Some(token))
+                .query(&[("path", path.as_str()), ("api-version", "7.1")])
+        };
+
+        let res = auth(client.get(&url))
+            .send()
+            .await
+            .map_err(ProviderError::from)?;
+        let etag = match res.status().is_success() {
+            true => res.headers().get(ETAG).cloned(),
+            false => None,
+        };
+        let mut req = auth(client.put(&url)).json(&json!({ "content": changelog }));
+        if let Some(etag) = etag {
+            debug!("updating azure wiki page {}", path);
+            req = req.header(IF_MATCH, etag);
+        }
+        let res = req.send().await.map_err(ProviderError::from)?;
+        let status = res.status();
+        if !status.is_success() {
+            return Err(ProviderError::from_status(
+                status,
+                format!(
+                    "error writing azure wiki page {}, status: {}, error: {}",
+                    path,
+                    status,
+                    res.text().await.unwrap_or_default()
+                ),
+            ));
+        }
+        let page: serde_json::Value = res.json().await.unwrap_or_default();
+        info!("wrote azure wiki page {}", path);
+        Ok(page["remoteUrl"].as_str().map(String::from))
+    }
+}
+
+#[allow(clippy::needless_arbitrary_self_type)]
+#[async_trait]
+impl ReleaseProvider for AzureDevops {
+    fn name(self: &Self) -> String {
+        "azure_devops".to_string()
+    }
+
+    async fn publish(
+        self: &Self,
+        release: &Release,
+        all_archives: Arc<Mutex<Vec<String>>>,
+        latest_tag: String,
+        changelog: String,
+    ) -> Result<PublishResult> {
+        let cfg = match &release.targets.azure_devops {
+            Some(cfg) => cfg,
+            None => bail!("azure devops config can't be empty"),
+        };
+        let token = env::var(TOKEN_ENV).unwrap_or_default();
+        if token.is_empty() {
+            return Err(ProviderError::Auth(format!(
+                "{} is blank, can't publish to azure devops",
+                TOKEN_ENV
+            ))
+            .into());
+        }
+
+        let mut result = PublishResult::new("azure_devops");
+        if let Some(feed) = &cfg.feed {
+            // Universal packages are versioned with lowercase semver.
+            let version = tag_version(&latest_tag).to_lowercase();
+            let archives = all_archives.lock().await.clone();
+            let url = self
+                .publish_package(release, cfg, feed, &archives, &version)
+                .await?;
+            result.release_url = Some(url);
+        }
+        if let Some(url) = self
+            .publish_wiki(cfg, &token, &latest_tag, &changelog)
+            .await?
+        {
+            result.release_url.get_or_insert(url);
+        }
+        Ok(result)
+    }
+}
+
+fn organization_url(cfg: &AzureDevopsCfg) -> String {
+    format!("https://dev.azure.com/{}", cfg.organization)
+}
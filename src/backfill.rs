@@ -0,0 +1,83 @@
+use crate::config::{Config, Release};
+use crate::git::GitRepo;
+use crate::github::release_exists;
+use crate::utils::checkout;
+use crate::{get_github_token, Opts, ReleasePipeline};
+use eyre::{bail, Result};
+use log::{error, info};
+
+// Creates the releases for the existing tags in the range which don't have a
+// github release yet. Each tag is checked out and released, with the
+// previous tag as the start of its changelog. HEAD is checked out again at
+// the end, even if a release fails.
+pub async fn backfill(cfg: Config, opts: Opts, range: &str) -> Result<()> {
+    let git = GitRepo::open_with(cfg.git.as_ref())?;
+    if !git.dirty_files()?.is_empty() {
+        bail!("repo has uncommitted changes, commit or stash them before backfilling");
+    }
+    let tags = git.tags_in_range(range)?;
+    if tags.is_empty() {
+        bail!("no tags found in {}", range);
+    }
+    info!("backfilling releases for tags: {}", tags.join(", "));
+
+    let head = git.head_ref()?;
+    let res = backfill_tags(&cfg, &opts, &tags).await;
+    info!("checking out {} again", head);
+    checkout(&head).await?;
+    res
+}
+
+async fn backfill_tags(cfg: &Config, opts: &Opts, tags: &[String]) -> Result<()> {
+    for tag in tags {
+        let releases = missing_releases(cfg, tag).await?;
+        if releases.is_empty() {
+            info!("releases for {} already exist, skipping", tag);
+            continue;
+        }
+
+        info!("checking out {} to release it", tag);
+        checkout(tag).await?;
+        let cfg = Config {
+            releases,
+            ..cfg.clone()
+        };
+        let opts = Opts {
+            publish: true,
+            from: None,
+            to: None,
+            from_tag: None,
+            to_tag: Some(tag.clone()),
+            since_commit: None,
+            ..opts.clone()
+        };
+        if let Err(err) = ReleasePipeline::from_config(cfg)
+            .with_opts(opts)
+            .run()
+            .await
+        {
+            error!("error releasing {}: {}", tag, err);
+            bail!("backfill stopped at {}", tag);
+        }
+    }
+    Ok(())
+}
+
+// Gets the releases which don't have a github release for the tag. Releases
+// without a github target are always released again.
+async fn missing_releases(cfg: &Config, tag: &str) -> Result<Vec<Release>> {
+    let mut releases = vec![];
+    for release in &cfg.releases {
+        if let Some(gh) = &release.targets.github {
+            if release_exists(gh, &get_github_token()?, tag).await? {
+                info!(
+                    "github release for {} already exists for {}",
+                    tag, release.name
+                );
+                continue;
+            }
+        }
+        releases.push(release.clone());
+    }
+    Ok(releases)
+}
@@ -0,0 +1,163 @@
+use crate::config::{Benchmarks, Release};
+use crate::get_github_token;
+use crate::github::release_assets;
+use crate::templating::{tag_context, Templates};
+use crate::utils::{spawn_tracked, wrapped_command};
+use camino::Utf8Path;
+use eyre::{bail, Context, Result};
+use log::{info, warn};
+use minijinja::context;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::process::Stdio;
+use tokio::fs;
+
+// Name of the results in the dist folder and in the published assets.
+const RESULTS: &str = "benchmarks.json";
+
+// Runs the benchmarks and writes their results to `benchmarks.json` in the
+// dist folder, flattened to a map of the benchmarks' names to their numbers.
+// Returns the results' path, which is published with the archives.
+pub async fn run(
+    release: &Release,
+    cfg: &Benchmarks,
+    templates: &Templates,
+    tag: &str,
+) -> Result<String> {
+    let ctx = context! { output => &cfg.output, ..tag_context(tag) };
+    let cmd = templates
+        .render(&cfg.command, ctx)
+        .wrap_err_with(|| "error rendering benchmarks command")?;
+    info!("running benchmarks: {}", cmd);
+    let (child, _group) = spawn_tracked(
+        wrapped_command(&["sh", "-c", &cmd])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit()),
+    )
+    .wrap_err_with(|| format!("error executing benchmarks command: {}", cmd))?;
+    let output = child
+        .wait_with_output()
+        .await
+        .wrap_err_with(|| format!("error executing benchmarks command: {}", cmd))?;
+    if !output.status.success() {
+        bail!("benchmarks command `{}` failed with {}", cmd, output.status);
+    }
+
+    let json = match &cfg.output {
+        Some(path) => fs::read(path)
+            .await
+            .wrap_err_with(|| format!("error reading benchmark results from {}", path))?,
+        None => output.stdout,
+    };
+    let value: Value =
+        serde_json::from_slice(&json).wrap_err_with(|| "error parsing benchmark results")?;
+    let mut results = BTreeMap::new();
+    flatten("", &value, &mut results);
+    if results.is_empty() {
+        bail!("benchmark results don't have any numbers");
+    }
+
+    fs::create_dir_all(&release.dist_folder).await?;
+    let path = Utf8Path::new(&release.dist_folder).join(RESULTS);
+    fs::write(&path, serde_json::to_string_pretty(&results)?).await?;
+    Ok(path.to_string())
+}
+
+// Compares the results in the dist folder to the ones published with the
+// previous github release and fails if any benchmark regressed more than
+// `max_regression`. Benchmarks missing from either are left out.
+pub async fn compare(release: &Release, cfg: &Benchmarks, previous_tag: &str) -> Result<()> {
+    let gh = match &release.targets.github {
+        Some(gh) => gh,
+        None => {
+            warn!("benchmarks need the github target to find the previous results");
+            return Ok(());
+        }
+    };
+    let ghtoken = get_github_token()?;
+    let asset = release_assets(gh, &ghtoken, previous_tag)
+        .await?
+        .and_then(|assets| assets.into_iter().find(|a| a.name == RESULTS));
+    let asset = match asset {
+        Some(asset) => asset,
+        None => {
+            info!(
+                "no benchmark results in the release of {}, skipping comparing them",
+                previous_tag
+            );
+            return Ok(());
+        }
+    };
+    let mut req = reqwest::Client::new().get(&asset.browser_download_url);
+    if !ghtoken.is_empty() {
+        req = req.header("Authorization", format!("token {}", ghtoken));
+    }
+    let res = req.send().await?;
+    if !res.status().is_success() {
+        bail!("error downloading {}, status: {}", asset.name, res.status());
+    }
+    let previous: BTreeMap<String, f64> = res
+        .json()
+        .await
+        .wrap_err_with(|| format!("error parsing the benchmark results of {}", previous_tag))?;
+    let path = Utf8Path::new(&release.dist_folder).join(RESULTS);
+    let current: BTreeMap<String, f64> = serde_json::from_slice(&fs::read(&path).await?)?;
+
+    let max = cfg.max_regression.unwrap_or(10.0);
+    let higher_is_better = cfg.higher_is_better.unwrap_or(false);
+    let mut lines = vec![];
+    let mut regressed = vec![];
+    for (name, result) in &current {
+        let old = match previous.get(name) {
+            Some(old) if *old != 0.0 => *old,
+            _ => {
+                lines.push(format!("  {}: {} (new)", name, result));
+                continue;
+            }
+        };
+        let change = (result - old) / old.abs() * 100.0;
+        lines.push(format!(
+            "  {}: {} ({:+.1}% from {})",
+            name, result, change, old
+        ));
+        let regression = if higher_is_better { -change } else { change };
+        if regression > max {
+            regressed.push(format!("{} by {:.1}%", name, regression));
+        }
+    }
+    println!(
+        "Benchmarks compared to {}:\n{}",
+        previous_tag,
+        lines.join("\n")
+    );
+    if !regressed.is_empty() {
+        bail!(
+            "benchmarks regressed more than {}%: {}",
+            max,
+            regressed.join(", ")
+        );
+    }
+    Ok(())
+}
+
+// Collects the numbers in the JSON, named by their keys joined with dots,
+// e.g. `parse.mean` for `{"parse": {"mean": 1.5}}`.
+fn flatten(prefix: &str, value: &Value, results: &mut BTreeMap<String, f64>) {
+    match value {
+        Value::Number(n) => {
+            if let Some(n) = n.as_f64() {
+                results.insert(prefix.to_string(), n);
+            }
+        }
+        Value::Object(map) => {
+            for (key, value) in map {
+                let name = match prefix {
+                    "" => key.clone(),
+                    _ => format!("{}.{}", prefix, key),
+                };
+                flatten(&name, value, results);
+            }
+        }
+        _ => {}
+    }
+}
@@ -0,0 +1,106 @@
+use crate::config::{ArchiveFormat, Binstall, Release};
+use crate::templating::{asset_name, tag_context, Templates};
+use crate::utils::{archive_name, build_compression};
+use camino::Utf8Path;
+use eyre::{bail, Context, Result};
+use log::{info, warn};
+use tokio::fs;
+use toml_edit::{value, DocumentMut, Item, Table};
+
+// Writes cargo-binstall metadata for the builds which have a target triple.
+// The metadata points binstall directly at the github release assets, so it
+// doesn't depend on how the archives are named. It's always written to
+// `binstall.toml` in the dist folder and optionally injected into Cargo.toml.
+pub async fn write_metadata(
+    release: &Release,
+    binstall: &Binstall,
+    templates: &Templates,
+    tag: &str,
+) -> Result<()> {
+    let gh = match &release.targets.github {
+        Some(gh) => gh,
+        None => bail!("binstall metadata needs the github target for download urls"),
+    };
+
+    let mut overrides = Table::new();
+    for build in &release.builds {
+        let target = match &build.target {
+            Some(target) => target,
+            None => {
+                warn!(
+                    "build {} doesn't have a target, skipping binstall metadata",
+                    build.name
+                );
+                continue;
+            }
+        };
+
+        let name = templates.render(&build.name, tag_context(tag))?;
+        let (filename, pkg_fmt) = match build.no_archive {
+            Some(_) => (name, "bin"),
+            None => {
+                let pkg_fmt = match build_compression(release, build).format.unwrap_or_default() {
+                    ArchiveFormat::Zip => "zip",
+                    ArchiveFormat::TarGz => "tgz",
+                    ArchiveFormat::TarZst => "tzstd",
+                };
+                (archive_name(release, build, &name), pkg_fmt)
+            }
+        };
+        let filename = asset_name(templates, release, tag, &filename, Some(target))?;
+
+        let mut entry = Table::new();
+        entry["pkg-url"] = value(format!(
+            "https://github.com/{}/{}/releases/download/{}/{}",
+            gh.owner, gh.repo, tag, filename
+        ));
+        entry["pkg-fmt"] = value(pkg_fmt);
+        // Archives have the binary at the root.
+        entry["bin-dir"] = value(&build.bin_name);
+        overrides[target.as_str()] = Item::Table(entry);
+    }
+    overrides.set_implicit(true);
+
+    let mut doc = DocumentMut::new();
+    set_metadata(&mut doc, overrides.clone());
+    let path = Utf8Path::new(&release.dist_folder).join("binstall.toml");
+    fs::create_dir_all(&release.dist_folder).await?;
+    fs::write(&path, doc.to_string())
+        .await
+        .wrap_err_with(|| format!("error writing binstall metadata to {}", path))?;
+    info!("wrote binstall metadata to {}", path);
+
+    if let Some(cargo_toml) = &binstall.cargo_toml {
+        let contents = fs::read_to_string(cargo_toml)
+            .await
+            .wrap_err_with(|| format!("error reading {}", cargo_toml))?;
+        let mut doc: DocumentMut = contents
+            .parse()
+            .wrap_err_with(|| format!("error parsing {}", cargo_toml))?;
+        set_metadata(&mut doc, overrides);
+        fs::write(cargo_toml, doc.to_string())
+            .await
+            .wrap_err_with(|| format!("error writing binstall metadata to {}", cargo_toml))?;
+        info!("injected binstall metadata into {}", cargo_toml);
+    }
+
+    Ok(())
+}
+
+// Sets `package.metadata.binstall` in the document with the given overrides.
+fn set_metadata(doc: &mut DocumentMut, overrides: Table) {
+    let mut binstall = Table::new();
+    binstall["pkg-fmt"] = value("zip");
+    binstall["bin-dir"] = value("{ bin }{ binary-ext }");
+    binstall["overrides"] = Item::Table(overrides);
+
+    let package = doc["package"].or_insert(Item::Table(implicit_table()));
+    let metadata = package["metadata"].or_insert(Item::Table(implicit_table()));
+    metadata["binstall"] = Item::Table(binstall);
+}
+
+fn implicit_table() -> Table {
+    let mut table = Table::new();
+    table.set_implicit(true);
+    table
+}
@@ -0,0 +1,116 @@
+use crate::config::Release;
+use crate::release_provider::{AssetNames, ProviderError, PublishResult, ReleaseProvider};
+use crate::templating::{tag_context, Templates};
+use async_trait::async_trait;
+use eyre::{bail, Result};
+use log::{debug, info};
+use reqwest::multipart::{Form, Part};
+use reqwest::{Client, RequestBuilder};
+use std::env;
+use std::sync::Arc;
+use tokio::{fs, sync::Mutex};
+
+const API: &str = "https://api.bitbucket.org/2.0";
+
+/// Bitbucket uploads the archives to the downloads of a bitbucket cloud
+/// repo. Bitbucket has no releases, so the changelog can be uploaded with
+/// them as a notes file named after the tag.
+pub struct Bitbucket {
+    asset_names: AssetNames,
+    templates: Arc<Templates>,
+}
+
+impl Bitbucket {
+    pub fn new(asset_names: AssetNames, templates: Arc<Templates>) -> Self {
+        Bitbucket {
+            asset_names,
+            templates,
+        }
+    }
+}
+
+#[allow(clippy::needless_arbitrary_self_type)]
+#[async_trait]
+impl ReleaseProvider for Bitbucket {
+    fn name(self: &Self) -> String {
+        "bitbucket".to_string()
+    }
+
+    async fn publish(
+        self: &Self,
+        release: &Release,
+        all_archives: Arc<Mutex<Vec<String>>>,
+        latest_tag: String,
+        changelog: String,
+    ) -> Result<PublishResult> {
+        let cfg = match &release.targets.bitbucket {
+            Some(cfg) => cfg,
+            None => bail!("bitbucket config can't be empty"),
+        };
+        let auth = auth()?;
+        let client = Client::new();
+        let url = format!(
+            "{}/repositories/{}/{}/downloads",
+            API, cfg.workspace, cfg.repo
+        );
+        let page = format!(
+            "https://bitbucket.org/{}/{}/downloads",
+            cfg.workspace, cfg.repo
+        );
+
+        let mut files = vec![];
+        for archive in all_archives.lock().await.iter() {
+            files.push((self.asset_names.get(archive), fs::read(archive).await?));
+        }
+        if let Some(notes) = &cfg.notes {
+            let name = self.templates.render(notes, tag_context(&latest_tag))?;
+            files.push((name, changelog.into_bytes()));
+        }
+
+        let mut result = PublishResult::new("bitbucket");
+        // Uploading a file with the name of an existing download replaces it.
+        for (name, contents) in files {
+            debug!("uploading {} to bitbucket downloads", name);
+            let part = Part::bytes(contents).file_name(name.clone());
+            let res = auth(client.post(&url))
+                .multipart(Form::new().part("files", part))
+                .send()
+                .await
+                .map_err(ProviderError::from)?;
+            let status = res.status();
+            if !status.is_success() {
+                return Err(ProviderError::from_status(
+                    status,
+                    format!(
+                        "error uploading {} to bitbucket, status: {}, error: {}",
+                        name,
+                        status,
+                        res.text().await.unwrap_or_default()
+                    ),
+                ));
+            }
+            info!("uploaded {} to bitbucket downloads", name);
+            result.asset_urls.push(format!("{}/{}", page, name));
+        }
+        result.release_url = Some(page);
+        Ok(result)
+    }
+}
+
+// Authenticates with `BITBUCKET_TOKEN`, a repository or workspace access
+// token, or with `BITBUCKET_USERNAME` and `BITBUCKET_APP_PASSWORD`.
+fn auth() -> Result<impl Fn(RequestBuilder) -> RequestBuilder> {
+    let var = |name| env::var(name).ok().filter(|v: &String| !v.is_empty());
+    let (token, username, password) = (
+        var("BITBUCKET_TOKEN"),
+        var("BITBUCKET_USERNAME"),
+        var("BITBUCKET_APP_PASSWORD"),
+    );
+    if token.is_none() && (username.is_none() || password.is_none()) {
+        bail!("BITBUCKET_TOKEN, or BITBUCKET_USERNAME and BITBUCKET_APP_PASSWORD, aren't set");
+    }
+    Ok(move |req: RequestBuilder| match &token {
+        Some(token) => req.bearer_auth(token),
+        None => req.basic_auth(username.as_deref().unwrap_or_default(), password.as_deref()),
+    })
+}
@@ -0,0 +1,166 @@
+use crate::config::{Build, BuildCache, Release};
+use crate::git::GitRepo;
+use crate::utils::sha256_file;
+use camino::Utf8Path;
+use eyre::{bail, Result};
+use log::{debug, info};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::env;
+use tokio::{fs, process::Command};
+
+// What the last run of a build was, in `<dist>/.rlsr-cache/<name>.json`.
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    // Hash of the build's inputs.
+    key: String,
+    // Sha256 of the artifact copied to the dist folder.
+    sha256: String,
+}
+
+// Hashes the inputs of the build: its command and artifact, the env vars and
+// the repo paths it depends on and the tag, which builds can embed.
+pub fn key(release: &Release, build: &Build, cfg: &BuildCache, tag: &str) -> Result<String> {
+    let git = GitRepo::open()?;
+    let dist = std::fs::canonicalize(&release.dist_folder).ok();
+    let workdir = git.workdir().and_then(|w| std::fs::canonicalize(w).ok());
+    // The dist folder changes with every build, so it isn't an input.
+    let exclude = match (dist, workdir) {
+        (Some(dist), Some(workdir)) => dist
+            .strip_prefix(workdir)
+            .ok()
+            .map(|d| d.to_string_lossy().to_string()),
+        _ => None,
+    };
+    let tree = git.tree_hash(
+        cfg.inputs.as_deref().unwrap_or_default(),
+        exclude.as_deref().filter(|e| !e.is_empty()),
+    )?;
+
+    let mut hasher = Sha256::new();
+    for part in [
+        env!("CARGO_PKG_VERSION"),
+        &build.command,
+        &build.artifact,
+        &build.bin_name,
+        build.target.as_deref().unwrap_or_default(),
+        build.container.as_ref().map_or("", |c| c.image.as_str()),
+        tag,
+        &tree,
+    ] {
+        hasher.update(part);
+        hasher.update("\n");
+    }
+    // Commands of the builds it needs, whose artifacts are in the dist folder
+    // and so aren't in the tree.
+    for need in build.needs.iter().flatten() {
+        if let Some(needed) = release.builds.iter().find(|b| &b.name == need) {
+            hasher.update(format!("{}={}\n", need, needed.command));
+        }
+    }
+    for var in cfg.env.as_deref().unwrap_or_default() {
+        hasher.update(format!("{}={}\n", var, env::var(var).unwrap_or_default()));
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+// Gets the artifact the last run of the build copied to the dist folder, if
+// its inputs are the same and the artifact wasn't changed since.
+pub async fn cached(release: &Release, name: &str, key: &str, path: &str) -> Option<String> {
+    let contents = fs::read(entry_path(release, name)).await.ok()?;
+    let entry: Entry = serde_json::from_slice(&contents).ok()?;
+    if entry.key != key {
+        debug!("inputs of build {} changed", name);
+        return None;
+    }
+    match sha256_file(path.to_string()).await {
+        Ok(sha256) if sha256 == entry.sha256 => Some(path.to_string()),
+        _ => {
+            debug!("artifact {} of build {} changed", path, name);
+            None
+        }
+    }
+}
+
+// Records the build's inputs and artifact for the next run.
+pub async fn save(release: &Release, name: &str, key: &str, path: &str) -> Result<()> {
+    let entry = Entry {
+        key: key.to_string(),
+        sha256: sha256_file(path.to_string()).await?,
+    };
+    let entry_path = entry_path(release, name);
+    if let Some(dir) = Utf8Path::new(&entry_path).parent() {
+        fs::create_dir_all(dir).await?;
+    }
+    fs::write(&entry_path, serde_json::to_vec(&entry)?).await?;
+    Ok(())
+}
+
+// Downloads the artifact cached with the key in the remote cache to the
+// path, and records it like a local build. Returns whether it was cached.
+pub async fn fetch(release: &Release, name: &str, remote: &str, key: &str, path: &str) -> bool {
+    let part = format!("{}.part", path);
+    if let Some(dir) = Utf8Path::new(path).parent() {
+        if fs::create_dir_all(dir).await.is_err() {
+            return false;
+        }
+    }
+    // Missing objects fail like any other error, so every error is a miss.
+    if let Err(err) = copy(&remote_path(remote, key), &part).await {
+        debug!("build {} isn't in {}: {}", name, remote, err);
+        let _ = fs::remove_file(&part).await;
+        return false;
+    }
+    if fs::rename(&part, path).await.is_err() {
+        return false;
+    }
+    info!("fetched build {} from {}", name, remote);
+    if let Err(err) = save(release, name, key, path).await {
+        debug!("error caching build {}: {}", name, err);
+    }
+    true
+}
+
+// Uploads the artifact to the remote cache with the key.
+pub async fn store(remote: &str, key: &str, path: &str) -> Result<()> {
+    copy(path, &remote_path(remote, key)).await
+}
+
+fn remote_path(remote: &str, key: &str) -> String {
+    format!("{}/{}", remote.trim_end_matches('/'), key)
+}
+
+// Copies the file from or to a bucket with the CLI of its cloud.
+async fn copy(from: &str, to: &str) -> Result<()> {
+    let bucket = if from.contains("://") { from } else { to };
+    let mut cmd = match bucket.split_once("://") {
+        Some(("s3", _)) => {
+            let mut cmd = Command::new("aws");
+            cmd.args(["s3", "cp", "--only-show-errors"]);
+            cmd
+        }
+        Some(("gs", _)) => {
+            let mut cmd = Command::new("gcloud");
+            cmd.args(["storage", "cp", "--quiet"]);
+            cmd
+        }
+        _ => bail!("remote build cache {} isn't an s3:// or gs:// url", bucket),
+    };
+    let output = cmd.args([from, to]).output().await?;
+    if !output.status.success() {
+        bail!(
+            "error copying {} to {}: {}",
+            from,
+            to,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+fn entry_path(release: &Release, name: &str) -> String {
+    Utf8Path::new(&release.dist_folder)
+        .join(".rlsr-cache")
+        .join(format!("{}.json", name))
+        .to_string()
+}
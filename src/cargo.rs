@@ -0,0 +1,178 @@
+use crate::config::{Build, Cargo as CargoCfg};
+use crate::templating::tag_version;
+use camino::Utf8Path;
+use eyre::{bail, Context, Result};
+use log::{debug, info};
+use serde::Deserialize;
+use tokio::process::Command;
+
+// Subset of `cargo metadata`'s output which is needed to find the binaries
+// and the dependencies' licenses.
+#[derive(Debug, Deserialize)]
+struct Metadata {
+    packages: Vec<Package>,
+    target_directory: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Package {
+    name: String,
+    version: String,
+    license: Option<String>,
+    targets: Vec<Target>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Target {
+    name: String,
+    kind: Vec<String>,
+}
+
+// Generates the builds for the binaries in the cargo workspace, one for every
+// binary and target triple. Builds are named `<bin>-<version>-<target>`.
+pub async fn workspace_builds(cfg: &CargoCfg) -> Result<Vec<Build>> {
+    let manifest = cfg.manifest.as_deref().unwrap_or("Cargo.toml");
+    let metadata = metadata(manifest, false).await?;
+    let targets = cfg.targets.clone().unwrap_or_default();
+
+    let mut builds = vec![];
+    for package in &metadata.packages {
+        for bin in package
+            .targets
+            .iter()
+            .filter(|t| t.kind.iter().any(|k| k == "bin"))
+        {
+            if !target_selected(cfg, &package.name, &bin.name) {
+                debug!("skipping binary {} of {}", bin.name, package.name);
+                continue;
+            }
+            let command = format!(
+                "cargo build --release --manifest-path {} -p {} --bin {}",
+                manifest, package.name, bin.name
+            );
+            if targets.is_empty() {
+                builds.push(Build {
+                    command,
+                    artifact: Utf8Path::new(&metadata.target_directory)
+                        .join("release")
+                        .join(&bin.name)
+                        .to_string(),
+                    bin_name: bin.name.clone(),
+                    name: format!("{}-{{{{ version }}}}", bin.name),
+                    target: None,
+                    no_archive: None,
+                    max_size: cfg.max_size.clone(),
+                    compression: None,
+                    additional_files: None,
+                    preset: None,
+                    container: None,
+                    needs: None,
+                });
+                continue;
+            }
+            for target in &targets {
+                builds.push(Build {
+                    command: format!("{} --target {}", command, target),
+                    artifact: Utf8Path::new(&metadata.target_directory)
+                        .join(target)
+                        .join("release")
+                        .join(&bin.name)
+                        .to_string(),
+                    bin_name: bin.name.clone(),
+                    name: format!("{}-{{{{ version }}}}-{}", bin.name, target),
+                    target: Some(target.clone()),
+                    no_archive: None,
+                    max_size: cfg.max_size.clone(),
+                    compression: None,
+                    additional_files: None,
+                    preset: None,
+                    container: None,
+                    needs: None,
+                });
+            }
+        }
+    }
+    info!("found {} build(s) in the cargo workspace", builds.len());
+    Ok(builds)
+}
+
+// Checks that the version of every crate with a selected binary is the
+// version in the tag.
+pub async fn check_versions(cfg: &CargoCfg, tag: &str) -> Result<()> {
+    let manifest = cfg.manifest.as_deref().unwrap_or("Cargo.toml");
+    let metadata = metadata(manifest, false).await?;
+    let version = tag_version(tag);
+
+    let mismatched: Vec<String> = metadata
+        .packages
+        .iter()
+        .filter(|p| {
+            p.targets.iter().any(|t| {
+                t.kind.iter().any(|k| k == "bin") && target_selected(cfg, &p.name, &t.name)
+            })
+        })
+        .filter(|p| p.version != version)
+        .map(|p| format!("  {} is {}", p.name, p.version))
+        .collect();
+    if !mismatched.is_empty() {
+        bail!(
+            "crate versions don't match the tag {}:\n{}",
+            tag,
+            mismatched.join("\n")
+        );
+    }
+    Ok(())
+}
+
+// Checks if the binary is in the configured packages and binaries. Without
+// them, all the binaries are built.
+fn target_selected(cfg: &CargoCfg, package: &str, bin: &str) -> bool {
+    let package_ok = cfg
+        .packages
+        .as_ref()
+        .is_none_or(|packages| packages.iter().any(|p| p == package));
+    let bin_ok = cfg
+        .bins
+        .as_ref()
+        .is_none_or(|bins| bins.iter().any(|b| b == bin));
+    package_ok && bin_ok
+}
+
+// Gets the name, version and license expression of every package in the
+// workspace and its dependencies.
+pub async fn licenses(manifest: &str) -> Result<Vec<(String, String, Option<String>)>> {
+    let metadata = metadata(manifest, true).await?;
+    Ok(metadata
+        .packages
+        .into_iter()
+        .map(|p| (p.name, p.version, p.license))
+        .collect())
+}
+
+// Reads the workspace's packages, and their dependencies if `deps` is given,
+// with `cargo metadata`.
+async fn metadata(manifest: &str, deps: bool) -> Result<Metadata> {
+    let mut cmd = Command::new("cargo");
+    cmd.args([
+        "metadata",
+        "--format-version",
+        "1",
+        "--manifest-path",
+        manifest,
+    ]);
+    if !deps {
+        cmd.arg("--no-deps");
+    }
+    let output = cmd
+        .output()
+        .await
+        .wrap_err_with(|| "error executing cargo metadata")?;
+    if !output.status.success() {
+        bail!(
+            "error reading cargo workspace at {}: {}",
+            manifest,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    serde_json::from_slice(&output.stdout).wrap_err_with(|| "error parsing cargo metadata")
+}
@@ -0,0 +1,559 @@
+use crate::config::{IssueTracker, Release, ReleaseNotesMode};
+use crate::git::GitRepo;
+use crate::templating::{tag_context, Templates};
+use camino::Utf8Path;
+use eyre::{Context, Result};
+use log::{debug, info, warn};
+use minijinja::{context, value::Serde};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use tokio::fs;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Commit {
+    pub hash: String,
+    pub subject: String,
+    pub email: String,
+    pub author: String,
+    // Author date in RFC 3339.
+    pub date: String,
+    // Message without the subject.
+    pub body: String,
+    pub trailers: Vec<Trailer>,
+    // Values of the `Co-authored-by` trailers.
+    pub co_authors: Vec<String>,
+    // Value of the `BREAKING CHANGE` trailer.
+    pub breaking_change: Option<String>,
+    // If the commit has a `BREAKING CHANGE` trailer or a `!` after the type
+    // in the subject.
+    pub breaking: bool,
+    // Conventional commit type of the subject, e.g. `feat` or `fix`.
+    pub kind: Option<String>,
+    // Badge of the commit's type from the changelog's `badges`.
+    pub badge: Option<String>,
+    // If the commit has more than one parent.
+    pub merge: bool,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Trailer {
+    pub key: String,
+    pub value: String,
+}
+
+impl Commit {
+    pub fn new(
+        hash: String,
+        message: &str,
+        author: String,
+        email: String,
+        date: String,
+        merge: bool,
+    ) -> Self {
+        let mut parts = message.trim().splitn(2, "\n\n");
+        let subject = parts.next().unwrap_or_default().trim().to_string();
+        let body = parts.next().unwrap_or_default().trim().to_string();
+        let trailers = parse_trailers(&body);
+
+        let co_authors = trailers
+            .iter()
+            .filter(|t| t.key.eq_ignore_ascii_case("co-authored-by"))
+            .map(|t| t.value.clone())
+            .collect();
+        let breaking_change = trailers
+            .iter()
+            .find(|t| t.key == "BREAKING CHANGE" || t.key == "BREAKING-CHANGE")
+            .map(|t| t.value.clone());
+        let caps = Regex::new(r"^(\w+)(\([^)]*\))?(!)?:")
+            .unwrap()
+            .captures(&subject);
+        let kind = caps.as_ref().map(|c| c[1].to_string());
+        let breaking = breaking_change.is_some() || caps.is_some_and(|c| c.get(3).is_some());
+
+        Commit {
+            hash,
+            subject,
+            email,
+            author,
+            date,
+            body,
+            trailers,
+            co_authors,
+            breaking_change,
+            breaking,
+            kind,
+            badge: None,
+            merge,
+        }
+    }
+}
+
+// GitHub fails to create releases with bodies longer than this.
+const GITHUB_MAX_LENGTH: usize = 125_000;
+
+// Parses the trailers (`Key: value` lines) in the last paragraph of the commit
+// body. Lines starting with whitespace continue the previous trailer's value.
+// `BREAKING CHANGE` is allowed as a key as in conventional commits.
+fn parse_trailers(body: &str) -> Vec<Trailer> {
+    let paragraph = match body.rsplit("\n\n").next() {
+        Some(paragraph) => paragraph,
+        None => return vec![],
+    };
+
+    let mut trailers: Vec<Trailer> = vec![];
+    for line in paragraph.lines() {
+        if line.starts_with(char::is_whitespace) {
+            match trailers.last_mut() {
+                Some(last) => {
+                    last.value.push(' ');
+                    last.value.push_str(line.trim());
+                    continue;
+                }
+                None => return vec![],
+            }
+        }
+
+        let (key, value) = match line.split_once(':') {
+            Some((key, value)) => (key, value.trim()),
+            None => return vec![],
+        };
+        let valid_key = key == "BREAKING CHANGE"
+            || (!key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '-'));
+        if !valid_key {
+            // Not a trailer block if any line isn't a trailer.
+            return vec![];
+        }
+        trailers.push(Trailer {
+            key: key.to_string(),
+            value: value.to_string(),
+        });
+    }
+    trailers
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct TagInfo {
+    pub message: String,
+    pub tagger: Option<String>,
+    pub tagger_email: Option<String>,
+    pub date: Option<String>,
+}
+
+// Gets the range of commits for the changelog, from the previous tag to the
+// latest tag. `from`/`to` override either end. Without a previous tag, it's
+// `to` alone, every commit up to it. No range means the whole history, when
+// there's no tag.
+pub fn changelog_range(
+    git: &GitRepo,
+    latest_tag: &str,
+    from: Option<&str>,
+    to: Option<&str>,
+) -> Result<Option<String>> {
+    let to = to.unwrap_or(latest_tag);
+    if to.is_empty() {
+        return Ok(None);
+    }
+    let from = match from {
+        Some(from) => Some(from.to_string()),
+        None => git.previous_tag(to)?,
+    };
+    Ok(Some(match from {
+        Some(from) => format!("{}..{}", from, to),
+        None => to.to_string(),
+    }))
+}
+
+// Gets the changelog for the release. If a release notes file exists for the
+// tag, it replaces or is prepended to the generated changelog.
+pub async fn get_release_changelog(
+    release: &Release,
+    templates: &Templates,
+    git: &GitRepo,
+    range: Option<&str>,
+    latest_tag: &str,
+) -> Result<String> {
+    let changelog = generate_changelog(release, templates, git, range, latest_tag)?;
+    let changelog = format!(
+        "{}{}",
+        highlight_sections(release, templates, git, range, latest_tag)?,
+        changelog
+    );
+
+    let cfg = match &release.changelog {
+        Some(cfg) => cfg,
+        None => return Ok(changelog),
+    };
+    let changelog = match &cfg.issue_tracker {
+        Some(tracker) => link_issues(tracker, &changelog)?,
+        None => changelog,
+    };
+    let path = match &cfg.release_notes_file {
+        Some(path) => templates.render(path, tag_context(latest_tag))?,
+        None => return Ok(changelog),
+    };
+    if !Utf8Path::new(&path).exists() {
+        return Ok(changelog);
+    }
+
+    info!("using release notes from {}", path);
+    let notes = fs::read_to_string(&path)
+        .await
+        .wrap_err_with(|| format!("error reading release notes file: {}", path))?;
+    match cfg.release_notes_mode {
+        Some(ReleaseNotesMode::Prepend) => Ok(format!("{}\n\n{}", notes.trim_end(), changelog)),
+        _ => Ok(notes),
+    }
+}
+
+// Renders the changelog templates of the targets, keyed by the target name.
+// The commits are read once for all the templates, which get the release's
+// changelog as `changelog` along with the commits.
+pub fn target_changelogs(
+    release: &Release,
+    templates: &Templates,
+    git: &GitRepo,
+    range: Option<&str>,
+    latest_tag: &str,
+    changelog: &str,
+) -> Result<HashMap<String, String>> {
+    let target_templates = match release
+        .changelog
+        .as_ref()
+        .and_then(|c| c.target_templates.as_ref())
+    {
+        Some(target_templates) if !target_templates.is_empty() => target_templates,
+        _ => return Ok(HashMap::new()),
+    };
+
+    let commits = with_badges(release, changelog_commits(release, git, range)?);
+    let tag_info = git.tag_info(latest_tag)?;
+    let ctx = context! {
+        commits => Serde(&commits),
+        tag_info => Serde(&tag_info),
+        changelog => changelog,
+        ..tag_context(latest_tag)
+    };
+    let mut changelogs = HashMap::new();
+    for (target, template) in target_templates {
+        let rendered = templates
+            .render(template, ctx.clone())
+            .wrap_err_with(|| format!("error rendering changelog template for {}", target))?;
+        let rendered = truncate_changelog(release, templates, range, latest_tag, rendered)?;
+        changelogs.insert(target.clone(), rendered);
+    }
+    Ok(changelogs)
+}
+
+// Cuts the changelog to the maximum length at the end of a line, with a link
+// to the full changelog at the end if there's one.
+pub fn truncate_changelog(
+    release: &Release,
+    templates: &Templates,
+    range: Option<&str>,
+    latest_tag: &str,
+    changelog: String,
+) -> Result<String> {
+    let max = match release.changelog.as_ref().and_then(|c| c.max_length) {
+        Some(max) => max,
+        None if release.targets.github.is_some() => GITHUB_MAX_LENGTH,
+        None => return Ok(changelog),
+    };
+    if changelog.chars().count() <= max {
+        return Ok(changelog);
+    }
+
+    warn!(
+        "changelog of {} is longer than {} characters, truncating it",
+        release.name, max
+    );
+    let footer = match full_changelog_url(release, templates, range, latest_tag)? {
+        Some(url) => format!("\n\n...\n\n**Full changelog**: {}", url),
+        None => "\n\n...".to_string(),
+    };
+    let keep = max.saturating_sub(footer.chars().count());
+    let end = changelog
+        .char_indices()
+        .nth(keep)
+        .map(|(i, _)| i)
+        .unwrap_or(changelog.len());
+    let cut = &changelog[..end];
+    let cut = match cut.rfind('\n') {
+        Some(i) if i > 0 => &cut[..i],
+        _ => cut,
+    };
+    Ok(format!("{}{}", cut.trim_end(), footer)
+        .trim_start()
+        .to_string())
+}
+
+// Gets the link to the full changelog, from the `full_changelog_url`
+// template or github's compare page.
+fn full_changelog_url(
+    release: &Release,
+    templates: &Templates,
+    range: Option<&str>,
+    latest_tag: &str,
+) -> Result<Option<String>> {
+    let (from, to) = match range.and_then(|r| r.split_once("..")) {
+        Some((from, to)) => (Some(from), to),
+        None => (None, latest_tag),
+    };
+    let tmpl = release
+        .changelog
+        .as_ref()
+        .and_then(|c| c.full_changelog_url.as_ref());
+    if let Some(tmpl) = tmpl {
+        let ctx = context! {
+            from => from.unwrap_or_default(),
+            ..tag_context(latest_tag)
+        };
+        return Ok(Some(templates.render(tmpl, ctx)?));
+    }
+
+    let gh = match &release.targets.github {
+        Some(gh) => gh,
+        None => return Ok(None),
+    };
+    Ok(Some(match from {
+        Some(from) => format!(
+            "https://github.com/{}/{}/compare/{}...{}",
+            gh.owner, gh.repo, from, to
+        ),
+        None => format!("https://github.com/{}/{}/commits/{}", gh.owner, gh.repo, to),
+    }))
+}
+
+// If the changelog leaves out any commits.
+fn filtered(release: &Release) -> bool {
+    release.changelog.as_ref().is_some_and(|c| {
+        c.exclude.is_some() || c.exclude_authors.is_some() || c.skip_merges.unwrap_or(false)
+    })
+}
+
+// Gets the commits in the range without the ones left out of the changelog,
+// by their subjects, authors or for being merge commits.
+fn changelog_commits(release: &Release, git: &GitRepo, range: Option<&str>) -> Result<Vec<Commit>> {
+    let commits = git.commits(range)?;
+    let cfg = match &release.changelog {
+        Some(cfg) if filtered(release) => cfg,
+        _ => return Ok(commits),
+    };
+    let regexes = |patterns: &Option<Vec<String>>, kind: &str| -> Result<Vec<Regex>> {
+        patterns
+            .iter()
+            .flatten()
+            .map(|p| {
+                Regex::new(p).wrap_err_with(|| format!("invalid changelog {} pattern: {}", kind, p))
+            })
+            .collect()
+    };
+    let subjects = regexes(&cfg.exclude, "exclude")?;
+    let authors = regexes(&cfg.exclude_authors, "author")?;
+    let skip_merges = cfg.skip_merges.unwrap_or(false);
+
+    Ok(commits
+        .into_iter()
+        .filter(|c| {
+            let excluded = (skip_merges && c.merge)
+                || subjects.iter().any(|r| r.is_match(&c.subject))
+                || authors
+                    .iter()
+                    .any(|r| r.is_match(&c.author) || r.is_match(&c.email));
+            if excluded {
+                debug!("leaving {} out of the changelog", c.hash);
+            }
+            !excluded
+        })
+        .collect())
+}
+
+// Sets the badges of the commits from their types.
+fn with_badges(release: &Release, mut commits: Vec<Commit>) -> Vec<Commit> {
+    let badges = match release.changelog.as_ref().and_then(|c| c.badges.as_ref()) {
+        Some(badges) => badges,
+        None => return commits,
+    };
+    for commit in &mut commits {
+        commit.badge = commit.kind.as_ref().and_then(|k| badges.get(k)).cloned();
+    }
+    commits
+}
+
+// Replaces the tickets in the changelog with links to the issue tracker.
+fn link_issues(tracker: &IssueTracker, changelog: &str) -> Result<String> {
+    let pattern = Regex::new(&tracker.pattern)
+        .wrap_err_with(|| format!("invalid issue tracker pattern: {}", tracker.pattern))?;
+    Ok(pattern
+        .replace_all(changelog, |caps: &regex::Captures| {
+            format!("[{}]({}{})", &caps[0], tracker.url, &caps[0])
+        })
+        .to_string())
+}
+
+// Gets the highlights, release notes and breaking changes sections which go
+// at the top of the notes, if they're enabled.
+fn highlight_sections(
+    release: &Release,
+    templates: &Templates,
+    git: &GitRepo,
+    range: Option<&str>,
+    latest_tag: &str,
+) -> Result<String> {
+    let cfg = match &release.changelog {
+        Some(cfg) => cfg,
+        None => return Ok(String::new()),
+    };
+
+    let mut sections = String::new();
+    let highlights = cfg.highlights.clone().unwrap_or_default();
+    if !highlights.is_empty() {
+        sections.push_str("## Highlights\n\n");
+        for highlight in highlights {
+            let highlight = templates.render(&highlight, tag_context(latest_tag))?;
+            sections.push_str(&format!("- {}\n", highlight));
+        }
+        sections.push('\n');
+    }
+
+    if let Some(key) = &cfg.note_trailer {
+        let notes: Vec<String> = changelog_commits(release, git, range)?
+            .into_iter()
+            .flat_map(|c| c.trailers)
+            .filter(|t| t.key.eq_ignore_ascii_case(key))
+            .map(|t| t.value)
+            .filter(|v| !v.is_empty() && !v.eq_ignore_ascii_case("none"))
+            .collect();
+        if !notes.is_empty() {
+            sections.push_str("## Release Notes\n\n");
+            for note in notes {
+                sections.push_str(&format!("- {}\n", note));
+            }
+            sections.push('\n');
+        }
+    }
+
+    if cfg.breaking_changes.unwrap_or(false) {
+        let breaking: Vec<Commit> = changelog_commits(release, git, range)?
+            .into_iter()
+            .filter(|c| c.breaking)
+            .collect();
+        if !breaking.is_empty() {
+            sections.push_str("## Breaking Changes\n\n");
+            for commit in breaking {
+                match &commit.breaking_change {
+                    Some(change) => sections.push_str(&format!(
+                        "- {}: {}\n  {}\n",
+                        commit.hash, commit.subject, change
+                    )),
+                    None => sections.push_str(&format!("- {}: {}\n", commit.hash, commit.subject)),
+                }
+            }
+            sections.push('\n');
+        }
+    }
+
+    Ok(sections)
+}
+
+// Generates the changelog from the commits in the range. If a changelog
+// template is given, it's rendered with the commits, otherwise the git log is
+// used as it is.
+fn generate_changelog(
+    release: &Release,
+    templates: &Templates,
+    git: &GitRepo,
+    range: Option<&str>,
+    latest_tag: &str,
+) -> Result<String> {
+    let tag_info = git.tag_info(latest_tag)?;
+    let use_tag_message = release
+        .changelog
+        .as_ref()
+        .and_then(|c| c.use_tag_message)
+        .unwrap_or(false);
+    if use_tag_message {
+        match &tag_info {
+            Some(info) if !info.message.is_empty() => return Ok(info.message.clone()),
+            _ => warn!(
+                "tag {} doesn't have a message, using the changelog instead",
+                latest_tag
+            ),
+        }
+    }
+
+    let badges = release.changelog.as_ref().and_then(|c| c.badges.as_ref());
+    let template = match release.changelog.as_ref().and_then(|c| c.template.as_ref()) {
+        Some(template) => template,
+        None if badges.is_some() || filtered(release) => return badged_log(release, git, range),
+        None => return git.log(range),
+    };
+    let commits = with_badges(release, changelog_commits(release, git, range)?);
+    let ctx = context! {
+        commits => Serde(&commits),
+        tag_info => Serde(&tag_info),
+        ..tag_context(latest_tag)
+    };
+    templates.render(template, ctx)
+}
+
+// Formats the commits like the git log, with the badge of each commit before
+// its message, for changelogs with badges or leaving out commits.
+fn badged_log(release: &Release, git: &GitRepo, range: Option<&str>) -> Result<String> {
+    let mut log = String::new();
+    for commit in with_badges(release, changelog_commits(release, git, range)?) {
+        let message = if commit.body.is_empty() {
+            commit.subject
+        } else {
+            format!("{}\n\n{}", commit.subject, commit.body)
+        };
+        match commit.badge {
+            Some(badge) => log.push_str(&format!("{}: {} {}\n", commit.hash, badge, message)),
+            None => log.push_str(&format!("{}: {}\n", commit.hash, message)),
+        }
+    }
+    Ok(log)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pairs(trailers: Vec<Trailer>) -> Vec<(String, String)> {
+        trailers.into_iter().map(|t| (t.key, t.value)).collect()
+    }
+
+    #[test]
+    fn parses_trailers_in_last_paragraph() {
+        let body = "Fixes the parser.\n\nCloses: #12\nCo-authored-by: A <a@example.com>";
+        assert_eq!(
+            pairs(parse_trailers(body)),
+            vec![
+                ("Closes".to_string(), "#12".to_string()),
+                (
+                    "Co-authored-by".to_string(),
+                    "A <a@example.com>".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn continues_trailer_values() {
+        let body = "Subject body.\n\nBREAKING CHANGE: the config\n  format changed";
+        assert_eq!(
+            pairs(parse_trailers(body)),
+            vec![(
+                "BREAKING CHANGE".to_string(),
+                "the config format changed".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn ignores_paragraphs_which_arent_trailers() {
+        assert!(parse_trailers("Closes: #12\nthis isn't a trailer").is_empty());
+        assert!(parse_trailers("Some key: value").is_empty());
+        assert!(parse_trailers("  continued: value").is_empty());
+    }
+}
@@ -0,0 +1,94 @@
+use crate::config::{Build, Config, Release};
+use crate::templating::{tag_context, Templates};
+use crate::utils::archive_name;
+use camino::Utf8Path;
+use eyre::{bail, Result};
+use std::collections::HashMap;
+
+// Two builds or docker targets which would write the same archive or push
+// the same image tag, overwriting each other.
+pub(crate) struct Collision<'a> {
+    // Path in the dist folder or image both of them write.
+    pub output: String,
+    pub first: (&'a Release, Option<&'a Build>),
+    pub second: (&'a Release, Option<&'a Build>),
+}
+
+// Finds the builds whose archives (or binaries with `no_archive`) have the
+// same path, and the docker targets pushing the same image and tag, with
+// the names rendered with each release's tag.
+pub(crate) fn find<'a>(
+    cfg: &'a Config,
+    templates: &Templates,
+    tag: impl Fn(&Release) -> String,
+) -> Vec<Collision<'a>> {
+    let mut outputs: HashMap<String, (&Release, Option<&Build>)> = HashMap::new();
+    let mut collisions = vec![];
+    for release in &cfg.releases {
+        let tag = tag(release);
+        let dist = release.dist_folder.trim_end_matches('/');
+        let mut add = |output: String, build: Option<&'a Build>| {
+            if let Some(first) = outputs.insert(output.clone(), (release, build)) {
+                collisions.push(Collision {
+                    output,
+                    first,
+                    second: (release, build),
+                });
+            }
+        };
+        for build in &release.builds {
+            let name = templates
+                .render(&build.name, tag_context(&tag))
+                .unwrap_or_else(|_| build.name.clone());
+            // Terraform releases archive every build.
+            let file = match build.no_archive.is_none() || release.terraform.is_some() {
+                true => archive_name(release, build, &name),
+                false => name,
+            };
+            add(Utf8Path::new(dist).join(file).to_string(), Some(build));
+        }
+        if let Some(docker) = &release.targets.docker {
+            add(format!("{}:{}", docker.image, tag), None);
+        }
+    }
+    collisions
+}
+
+// Fails if any builds or docker targets collide, with both of their
+// definitions, since they'd silently overwrite each other.
+pub(crate) fn check(
+    cfg: &Config,
+    templates: &Templates,
+    tag: impl Fn(&Release) -> String,
+) -> Result<()> {
+    let collisions = find(cfg, templates, tag);
+    if collisions.is_empty() {
+        return Ok(());
+    }
+    let mut msg = String::from("builds would overwrite each other's outputs:");
+    for collision in &collisions {
+        msg.push_str(&format!("\n  {} is written by both", collision.output));
+        for (release, build) in [collision.first, collision.second] {
+            msg.push_str(&format!("\n    {}:", describe(release, build)));
+            let definition = match build {
+                Some(build) => serde_yaml::to_string(build),
+                None => serde_yaml::to_string(&release.targets.docker),
+            };
+            // Unset options are left out.
+            for line in definition.unwrap_or_default().lines() {
+                if line != "---" && !line.ends_with(": ~") {
+                    msg.push_str(&format!("\n      {}", line));
+                }
+            }
+        }
+    }
+    bail!(msg)
+}
+
+// Names the build, or the docker target without a build.
+pub(crate) fn describe(release: &Release, build: Option<&Build>) -> String {
+    match build {
+        Some(build) => format!("build {} of {}", build.name, release.name),
+        None => format!("docker target of {}", release.name),
+    }
+}
@@ -1,11 +1,62 @@
-use eyre::{Context, Result};
+use crate::git::GitRepo;
+use crate::utils::add_secret;
+use eyre::{bail, eyre, Context, Result};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::future::Future;
+use std::path::Path;
+use std::pin::Pin;
+use std::process::Stdio;
 use tokio::fs;
+use tokio::io::{self, AsyncReadExt, AsyncWriteExt};
+use tokio::process::Command;
+use toml_edit::{DocumentMut, Item};
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 pub struct Github {
+    // Owner and repo default to the ones in the `origin` remote.
+    #[serde(default)]
     pub owner: String,
+    #[serde(default)]
     pub repo: String,
+
+    // Prunes old prereleases and drafts after publishing.
+    pub retention: Option<Retention>,
+
+    // Branch or commit github creates the tag from if it isn't pushed yet.
+    // Defaults to the commit of the local tag.
+    pub target_commitish: Option<String>,
+
+    // Deletes the release if rlsr is interrupted while its assets are
+    // uploaded, instead of leaving a partial release.
+    pub delete_on_interrupt: Option<bool>,
+
+    // Content types of the uploaded assets, keyed by asset name or by
+    // extension, e.g. `txt` or `tar.gz`. They take precedence over the
+    // built-in types and the ones guessed from the files' contents.
+    pub content_types: Option<HashMap<String, String>>,
+
+    // Comments on the merged pr of the tagged commit, or on the commit if it
+    // has no pr, after publishing.
+    pub comment: Option<GithubComment>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct GithubComment {
+    // Template of the comment, with `tag`, `version`, `release_url` and the
+    // `assets` with their `name`, `url` and `size`. Defaults to a link to
+    // the release and a table of the assets.
+    pub template: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct Retention {
+    // Deletes prereleases and drafts older than the given number of days.
+    pub max_age_days: Option<i64>,
+
+    // Keeps only the given number of latest prereleases and drafts.
+    pub keep_last: Option<usize>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
@@ -13,12 +64,204 @@ pub struct Docker {
     pub dockerfile: String,
     pub image: String,
     pub context: String,
+
+    // Tool used to build and push the image. Defaults to `docker`.
+    pub backend: Option<ContainerBackend>,
+
+    // Creates the image's ECR or Artifact Registry repository if it doesn't
+    // exist, with the `aws` or `gcloud` CLI. Defaults to false.
+    pub create_repository: Option<bool>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ContainerBackend {
+    Docker,
+    Podman,
+    Nerdctl,
+    // Kaniko's executor builds and pushes in one step, without a daemon.
+    Kaniko,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct VersionsManifest {
+    // Github repo where the manifest is kept.
+    pub owner: String,
+    pub repo: String,
+    pub branch: String,
+    // Path of the manifest in the repo, for example `versions.json`.
+    pub path: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct Oras {
+    // Repository the archives are pushed to, for example
+    // `ghcr.io/iamd3vil/rlsr-bin`. The release tag is used as the tag.
+    pub repository: String,
+
+    // Artifact type of the pushed manifest. Defaults to
+    // `application/vnd.rlsr.release`.
+    pub artifact_type: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct DownloadPage {
+    // Path the page is written to. Defaults to `download.html` in the dist
+    // folder.
+    pub output: Option<String>,
+
+    // Optional minijinja template of the page, instead of the built-in one.
+    pub template: Option<String>,
+
+    // Url the assets are downloaded from, templated with the tag. Defaults to
+    // the github release's download url.
+    pub base_url: Option<String>,
+
+    // Optional, also commits the page to a github repo, for example to the
+    // `gh-pages` branch.
+    pub pages: Option<RepoFile>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct RepoFile {
+    pub owner: String,
+    pub repo: String,
+    pub branch: String,
+    pub path: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct Custom {
+    pub name: String,
+    // Command executed with `sh -c`. It gets the release as JSON on stdin
+    // and can print a JSON publish result on stdout.
+    pub command: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct Plugin {
+    pub name: String,
+    // Path of the plugin's library. Defaults to the library named after the
+    // plugin in `plugins_dir`, e.g. `libslack.so` on linux.
+    pub path: Option<String>,
+    // Given to the plugin's hooks as `config`.
+    pub config: Option<serde_json::Value>,
+    // Given to the plugin's `announce` hook as `message`. It can be a string
+    // or structured, like slack blocks or discord embeds, and every string in
+    // it is rendered as a template.
+    pub message: Option<serde_json::Value>,
+    // Template deciding if the plugin announces the release, e.g.
+    // `{{ not prerelease }}`. It announces unless it renders to `false` or
+    // nothing.
+    pub when: Option<String>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 pub struct ReleaseTargets {
     pub github: Option<Github>,
     pub docker: Option<Docker>,
+    pub versions_manifest: Option<VersionsManifest>,
+    pub oras: Option<Oras>,
+    pub download_page: Option<DownloadPage>,
+    pub sentry: Option<Sentry>,
+    pub helm: Option<Helm>,
+    pub vsce: Option<Vsce>,
+    pub bitbucket: Option<Bitbucket>,
+    pub azure_devops: Option<AzureDevops>,
+    pub custom: Option<Vec<Custom>>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct AzureDevops {
+    // Organization, e.g. `iamd3vil` for `https://dev.azure.com/iamd3vil`.
+    pub organization: String,
+
+    // Project of the feed and wiki. Feeds without one are scoped to the
+    // organization.
+    pub project: Option<String>,
+
+    // Azure artifacts feed the archives are published to as a universal
+    // package, with the tag's version.
+    pub feed: Option<String>,
+
+    // Name of the universal package. Defaults to the release's name.
+    pub package: Option<String>,
+
+    // Wiki the changelog is written to, as a page per release.
+    pub wiki: Option<AzureWiki>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct AzureWiki {
+    // Name of the wiki, e.g. `rlsr.wiki` for the project wiki of `rlsr`.
+    pub name: String,
+
+    // Templated path of the release's page. Defaults to `/Releases/<tag>`.
+    pub path: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct Bitbucket {
+    pub workspace: String,
+    pub repo: String,
+
+    // Templated name of a file with the changelog, uploaded with the
+    // archives, e.g. `{{ tag }}-notes.md`. Bitbucket has no releases to put
+    // the changelog in.
+    pub notes: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct Vsce {
+    // Glob of the `.vsix`, e.g. packaged by a hook. Defaults to the `.vsix`
+    // among the release's assets.
+    pub vsix: Option<String>,
+
+    // Publishes to the vs code marketplace with `VSCE_PAT`. Defaults to
+    // true.
+    pub marketplace: Option<bool>,
+
+    // Publishes to open vsx with `OVSX_PAT`. Defaults to true.
+    pub open_vsx: Option<bool>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct Helm {
+    // Folder of the chart, with its `Chart.yaml`.
+    pub chart: String,
+
+    // OCI registry the packaged chart is pushed to with `helm push`, e.g.
+    // `oci://ghcr.io/iamd3vil/charts`.
+    pub oci: Option<String>,
+
+    // `index.yaml` of a chart repo in a github repo, e.g. on the `gh-pages`
+    // branch, which the chart is added to.
+    pub index: Option<RepoFile>,
+
+    // Templated url the chart is downloaded from, as it's written to the
+    // index. Defaults to the github release's download url.
+    pub base_url: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct Sentry {
+    pub org: String,
+    pub projects: Vec<String>,
+
+    // Templated version of the release. Defaults to
+    // `<release name>@<version>`.
+    pub version: Option<String>,
+
+    // Repo of the commits as it's named in sentry. Defaults to the github
+    // target's repo or the `origin` remote's.
+    pub repository: Option<String>,
+
+    // Globs of debug files uploaded to the projects, e.g. split debug info
+    // of the builds.
+    pub debug_files: Option<Vec<String>>,
+
+    // Url of self-hosted sentry. Defaults to `https://sentry.io`.
+    pub url: Option<String>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -27,6 +270,476 @@ pub struct Release {
     pub dist_folder: String,
     pub builds: Vec<Build>,
     pub targets: ReleaseTargets,
+
+    // Plugins loaded from dynamic libraries, which can package assets,
+    // publish the release like a target and announce it.
+    pub plugins: Option<Vec<Plugin>>,
+
+    pub changelog: Option<Changelog>,
+    pub binstall: Option<Binstall>,
+
+    // Generates install scripts for the builds, published with the archives.
+    pub install_script: Option<InstallScript>,
+
+    // Archives the vendored dependencies, published with the archives for
+    // packagers who build offline.
+    pub vendor: Option<Vendor>,
+
+    // Archives the builds with the names, checksums and signature the
+    // terraform registry expects of provider releases.
+    pub terraform: Option<Terraform>,
+
+    // Checks the dependencies' licenses and bundles the third party notices
+    // into every archive.
+    pub licenses: Option<Licenses>,
+
+    // Conditions which have to pass before the release is published.
+    pub gates: Option<Gates>,
+
+    // Downstream pipelines triggered with the release payload after it's
+    // published, e.g. docs or homebrew tap repos.
+    pub dispatch: Option<Vec<Dispatch>>,
+
+    // Builds the docs after the release is published and pushes them to a
+    // branch, e.g. `gh-pages`, in a folder for the tag.
+    pub docs: Option<Docs>,
+
+    // Format and compression of the builds' archives, unless a build has its
+    // own. Defaults to zip with the default level.
+    pub compression: Option<Compression>,
+
+    // Files included in the builds' archives along with the binaries, unless
+    // a build has its own preset.
+    pub preset: Option<ArchivePreset>,
+
+    // Skips the builds whose inputs didn't change since the last run and
+    // reuses their artifacts in the dist folder.
+    pub build_cache: Option<BuildCache>,
+
+    // Test commands run before the builds, which fail the release if any of
+    // them fails.
+    pub tests: Option<Vec<Test>>,
+
+    // Commands run once before the builds, e.g. building the web assets the
+    // binaries embed. Their outputs are in the template context of the
+    // builds' commands.
+    pub prepare: Option<Vec<Prepare>>,
+
+    // Generates the builds from the binaries in a cargo workspace, in
+    // addition to `builds`.
+    pub cargo: Option<Cargo>,
+
+    // Rewrites the version in source files before building.
+    pub version_files: Option<VersionFiles>,
+
+    // Compares the sizes of the assets to the previous release's.
+    pub size_report: Option<SizeReport>,
+
+    // Runs benchmarks after the builds and compares their results to the
+    // previous release's, which are published as `benchmarks.json`.
+    pub benchmarks: Option<Benchmarks>,
+
+    // Disk space the release needs, e.g. `2GB`. Defaults to the size of the
+    // files left in the dist folder by the previous run.
+    pub disk_space: Option<String>,
+
+    // Globs of existing files (e.g. installers or docs built elsewhere)
+    // published as assets along with the build archives.
+    pub extra_assets: Option<Vec<String>>,
+
+    // Publishes a `checksums.txt` with the sha256 of every asset. Defaults
+    // to true.
+    pub checksums: Option<bool>,
+
+    // Template for the names the assets are published with, so that they
+    // can differ from the files in the dist folder. Has `name` and `ext` of
+    // the file and `os`, `arch` and `target` of the build.
+    pub asset_name: Option<String>,
+
+    pub hooks: Option<Hooks>,
+
+    // What to do when HEAD isn't tagged. By default the latest tag is
+    // published.
+    pub when_untagged: Option<WhenUntagged>,
+
+    // Skips the release if there are no commits since the last release, like
+    // `--skip-if-no-changes`.
+    pub skip_if_no_changes: Option<bool>,
+
+    // Only commits changing these paths are in the changelog and count as
+    // changes, e.g. for a crate in a monorepo.
+    pub change_paths: Option<Vec<String>>,
+
+    // Regex for the release's tags (e.g. `^cli/v`) instead of
+    // `git.tag_pattern`, so that the releases of a monorepo are tagged and
+    // versioned independently.
+    pub tag_pattern: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum WhenUntagged {
+    // Builds but doesn't publish.
+    Skip,
+    // Builds with a snapshot version, e.g. `v1.2.0-SNAPSHOT-abc1234`, and
+    // doesn't publish.
+    Snapshot,
+    // Fails the release.
+    Fail,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Hooks {
+    // Commands executed before the release is published, after the
+    // confirmation. A failing command aborts publishing.
+    pub before_publish: Option<Vec<String>>,
+
+    // Commands executed after the release is published. They get the
+    // release url as `{{ release_url }}` and `RLSR_RELEASE_URL`.
+    pub after_publish: Option<Vec<String>>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SizeReport {
+    // Growth in percent from the previous release after which an asset is
+    // reported, e.g. `10`.
+    pub max_growth: Option<f64>,
+
+    // Fails publishing instead of warning when an asset grew more than
+    // `max_growth`.
+    pub fail: Option<bool>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Benchmarks {
+    // Templated command printing the results as JSON, or writing them to
+    // `output`, which is in the template context.
+    pub command: String,
+
+    // File the command writes the results to, instead of its stdout.
+    pub output: Option<String>,
+
+    // Change in percent from the previous release's results after which a
+    // benchmark regressed and publishing fails. Defaults to 10.
+    pub max_regression: Option<f64>,
+
+    // Higher results are better, e.g. for throughputs. Defaults to false,
+    // for timings.
+    pub higher_is_better: Option<bool>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct VersionFiles {
+    pub files: Vec<VersionFile>,
+
+    // What's done with the rewritten files after building: `keep` them
+    // (default), `revert` them or `commit` them.
+    pub after: Option<VersionFilesAfter>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct VersionFile {
+    pub path: String,
+
+    // Regex matching the version string, e.g. `(?m)^version = ".*"`.
+    pub pattern: String,
+
+    // Templated replacement for the matches, e.g. `version = "{{ version }}"`.
+    // Capture groups can be used as `${1}`.
+    pub replace: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum VersionFilesAfter {
+    Keep,
+    Revert,
+    Commit,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Cargo {
+    // Manifest of the workspace, defaults to `Cargo.toml`.
+    pub manifest: Option<String>,
+
+    // Target triples every binary is built for. Without targets, binaries
+    // are built for the host.
+    pub targets: Option<Vec<String>>,
+
+    // Only binaries of these packages are built, defaults to all of them.
+    pub packages: Option<Vec<String>>,
+
+    // Only these binaries are built, defaults to all of them.
+    pub bins: Option<Vec<String>>,
+
+    // Maximum size of every binary's archive, like a build's `max_size`.
+    pub max_size: Option<String>,
+
+    // Fails publishing if a crate's version isn't the tag's version.
+    // Defaults to true.
+    pub check_version: Option<bool>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Binstall {
+    // Cargo.toml to inject the metadata into. The metadata is only written to
+    // the dist folder if this isn't given.
+    pub cargo_toml: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct InstallScript {
+    // Binary the scripts install. Defaults to the first build's binary.
+    pub bin_name: Option<String>,
+
+    // Default install dir of `install.sh`. Defaults to `$HOME/.local/bin`.
+    pub install_dir: Option<String>,
+
+    // Url the assets are downloaded from, templated with the tag. Defaults to
+    // the github release's download url.
+    pub base_url: Option<String>,
+
+    // Optional minijinja templates of `install.sh` and `install.ps1`,
+    // instead of the built-in ones.
+    pub template: Option<String>,
+    pub powershell_template: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Vendor {
+    // Command vendoring the dependencies into `dir`, templated with the tag
+    // and `dir`. Defaults to `cargo vendor --locked {{ dir }}`.
+    pub command: Option<String>,
+
+    // Folder the command vendors into, relative to the repo root. Defaults
+    // to `vendor`.
+    pub dir: Option<String>,
+
+    // Templated name of the archive. Defaults to `vendor.tar.gz`.
+    pub name: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BuildCache {
+    // Paths of the repo the builds depend on, e.g. `src` and `Cargo.lock`.
+    // Defaults to the whole repo.
+    pub inputs: Option<Vec<String>>,
+
+    // Env vars the builds depend on, e.g. `RUSTFLAGS`.
+    pub env: Option<Vec<String>>,
+
+    // Bucket the artifacts are also cached in, keyed by the hash of their
+    // inputs, so that runners can reuse the builds of other runners, e.g.
+    // `s3://bucket/rlsr` or `gs://bucket/rlsr`. Uses the `aws` or `gcloud`
+    // CLI and its credentials.
+    pub remote: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Docs {
+    // Command building the docs, templated with the tag and `output`.
+    pub command: String,
+
+    // Folder the command writes the docs to.
+    pub output: String,
+
+    // Branch the docs are pushed to. Defaults to `gh-pages`.
+    pub branch: Option<String>,
+
+    // Templated folder of the branch the docs are copied to. Defaults to
+    // `{{ tag }}`.
+    pub dir: Option<String>,
+
+    // Remote the branch is pushed to. Defaults to `git.remote` or `origin`.
+    pub remote: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Terraform {
+    // Name of the provider, e.g. `example` for `terraform-provider-example`.
+    pub provider: String,
+
+    // Plugin protocol versions the provider supports, as they're written to
+    // the registry manifest. Defaults to `5.0`.
+    pub protocol_versions: Option<Vec<String>>,
+
+    // Key id or fingerprint of the gpg key signing the checksums. Defaults
+    // to `GPG_FINGERPRINT` or gpg's default key.
+    pub gpg_key: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Licenses {
+    // Command writing the third party notices to `output`, templated with
+    // `output` and the tag, e.g.
+    // `cargo about generate about.hbs -o {{ output }}`.
+    pub command: Option<String>,
+
+    // File the command writes, included at the root of every archive.
+    // Defaults to `THIRD_PARTY_NOTICES` in the dist folder.
+    pub output: Option<String>,
+
+    // SPDX ids of licenses the crates in the cargo workspace and their
+    // dependencies can't be under, e.g. `GPL-3.0`. `-only` and `-or-later`
+    // variants are denied too. Crates under several licenses are denied
+    // only if all the alternatives are.
+    pub deny: Option<Vec<String>>,
+
+    // Manifest of the cargo workspace. Defaults to `Cargo.toml`.
+    pub manifest: Option<String>,
+}
+
+// One of `repository_dispatch`, `gitlab` or `webhook` is given.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Dispatch {
+    pub repository_dispatch: Option<RepositoryDispatch>,
+    pub gitlab: Option<GitlabTrigger>,
+    pub webhook: Option<Webhook>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RepositoryDispatch {
+    // `owner/repo` receiving the event, with the payload as
+    // `client_payload`.
+    pub repo: String,
+
+    // Defaults to `release`.
+    pub event_type: Option<String>,
+
+    // Env var with a token which can dispatch to the repo. Defaults to
+    // `GITHUB_TOKEN`.
+    pub token_env: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GitlabTrigger {
+    // Id or path of the project whose pipeline is triggered. The pipeline
+    // gets `RLSR_RELEASE`, `RLSR_TAG` and `RLSR_VERSION`.
+    pub project: String,
+
+    // Ref the pipeline runs for. Defaults to `main`.
+    #[serde(rename = "ref")]
+    pub git_ref: Option<String>,
+
+    // Defaults to `https://gitlab.com`.
+    pub url: Option<String>,
+
+    // Env var with the trigger token. Defaults to `GITLAB_TRIGGER_TOKEN`.
+    pub token_env: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Webhook {
+    // Templated url the payload is posted to as JSON.
+    pub url: String,
+
+    // Templated headers of the request, e.g. for authentication.
+    pub headers: Option<HashMap<String, String>>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Gates {
+    // Waits for the github check runs of the tagged commit to succeed. An
+    // empty list waits for all of them, otherwise for the named ones.
+    pub checks: Option<Vec<String>>,
+
+    // Templated url polled until it approves the release with a success
+    // status. 202 means the approval is pending, 403 and 410 reject the
+    // release.
+    pub approval_url: Option<String>,
+
+    // Templated commands which have to succeed, run again until they do.
+    pub commands: Option<Vec<String>>,
+
+    // How long to wait for the gates to pass. Defaults to 60 minutes.
+    pub timeout_minutes: Option<u64>,
+
+    // How often pending gates are checked again. Defaults to 30 seconds.
+    pub interval_seconds: Option<u64>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Changelog {
+    // Template used to render the release body.
+    pub template: Option<String>,
+
+    // Templated path to a file with release notes, for example
+    // `docs/release-notes/{{ tag }}.md`. If the file exists, it's used as the
+    // release body.
+    pub release_notes_file: Option<String>,
+
+    // Whether the release notes replace the generated changelog (`replace`)
+    // or are prepended to it (`prepend`). Defaults to `replace`.
+    pub release_notes_mode: Option<ReleaseNotesMode>,
+
+    // Uses the annotated tag's message as the release body instead of the
+    // changelog.
+    pub use_tag_message: Option<bool>,
+
+    // Adds a section at the top of the notes with the breaking changes, i.e.
+    // commits with a `BREAKING CHANGE` trailer or a `!` after the type (e.g.
+    // `feat!: ...`).
+    pub breaking_changes: Option<bool>,
+
+    // Key of the commit trailer with user facing notes, e.g. `Release-Note`.
+    // The trailers' values are listed in a section at the top of the notes,
+    // so that commits can have notes differing from their subjects. Values
+    // of `none` are left out.
+    pub note_trailer: Option<String>,
+
+    // Highlights of the release, listed at the top of the notes. Each
+    // highlight is templated.
+    pub highlights: Option<Vec<String>>,
+
+    // Turns ticket references in the changelog into links.
+    pub issue_tracker: Option<IssueTracker>,
+
+    // Changelog templates for specific targets, keyed by the target name
+    // (`github`, `docker`, `oras`, `versions_manifest` or the name of a
+    // custom target). Other targets get the release's changelog.
+    pub target_templates: Option<HashMap<String, String>>,
+
+    // Maximum length of the changelog in characters. Longer changelogs are
+    // cut at the end of a line and end with a link to the full changelog.
+    // Defaults to github's limit of 125000 for releases with a github target.
+    pub max_length: Option<usize>,
+
+    // Templated link to the full changelog for truncated changelogs, with
+    // `from` (the start of the range) and `tag`. Defaults to the github
+    // compare url for releases with a github target.
+    pub full_changelog_url: Option<String>,
+
+    // Badges for the commit types, e.g. `feat: ✨`, set as `badge` on the
+    // commits in templates and put before the commit message otherwise.
+    pub badges: Option<HashMap<String, String>>,
+
+    // Regexes matching the subjects of commits left out of the changelog,
+    // e.g. `^chore\(deps\)`.
+    pub exclude: Option<Vec<String>>,
+
+    // Regexes matching the names or emails of authors whose commits are left
+    // out of the changelog, e.g. `dependabot\[bot\]` or `^renovate`.
+    pub exclude_authors: Option<Vec<String>>,
+
+    // Leaves the merge commits out of the changelog.
+    pub skip_merges: Option<bool>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct IssueTracker {
+    // Base url of the tickets, the ticket is appended to it, for example
+    // `https://jira.example.com/browse/`.
+    pub url: String,
+
+    // Regex matching the tickets, for example `PROJ-\d+`.
+    pub pattern: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ReleaseNotesMode {
+    Replace,
+    Prepend,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -36,19 +749,625 @@ pub struct Build {
     pub bin_name: String,
     pub name: String,
 
+    // Target triple of the build, e.g. `x86_64-unknown-linux-gnu`.
+    pub target: Option<String>,
+
     // Doesn't an archive if given true.
     pub no_archive: Option<bool>,
+
+    // Maximum size of the build's archive, e.g. `20MB`. Bigger archives fail
+    // the release.
+    pub max_size: Option<String>,
+
+    // Format and compression of the build's archive, instead of the
+    // release's.
+    pub compression: Option<Compression>,
+
+    // Globs of files included in the archive along with the binary, e.g.
+    // generated shell completions. Relative paths are kept in the archive.
+    pub additional_files: Option<Vec<String>>,
+
+    // Files included in the archive along with the binary and
+    // `additional_files`, instead of the release's preset.
+    pub preset: Option<ArchivePreset>,
+
+    // Docker container the command runs in, instead of on the machine.
+    pub container: Option<BuildContainer>,
+
+    // Names of the builds which have to finish before this one. Their
+    // artifacts in the dist folder are in the command's template context,
+    // e.g. `{{ builds.assets.artifact }}`.
+    pub needs: Option<Vec<String>>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Test {
+    // Name the test is reported with.
+    pub name: String,
+
+    // Templated command run with `sh -c`.
+    pub command: String,
+
+    // Env vars set for the command.
+    pub env: Option<HashMap<String, String>>,
+
+    // Time after which the command is killed and the test fails.
+    pub timeout_minutes: Option<u64>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Prepare {
+    // Name of the output in the template context, e.g. `web_dist` for
+    // `{{ prepared.web_dist }}`.
+    pub name: String,
+
+    // Command run with `sh -c`.
+    pub command: String,
+
+    // File or folder the command creates.
+    pub output: String,
+
+    // Repo paths the output is made from. The command is skipped if they
+    // didn't change since the last run and the output still exists.
+    // Defaults to the whole repo.
+    pub inputs: Option<Vec<String>>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BuildContainer {
+    // Image with the build's toolchain, e.g. `rust:1.80`.
+    pub image: String,
+
+    // Volumes mounted along with the workspace, e.g. `~/.cargo/registry:/usr/local/cargo/registry`.
+    pub volumes: Option<Vec<String>>,
+
+    // Env vars set in the container, `NAME=value` or `NAME` to pass the
+    // var's value from the environment.
+    pub env: Option<Vec<String>>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ArchivePreset {
+    // Includes `LICENSE*`, `README*` and `CHANGELOG*` from the repo root.
+    Standard,
+    // Only the binary and `additional_files`.
+    None,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Compression {
+    // Archive format, defaults to `zip`.
+    pub format: Option<ArchiveFormat>,
+
+    // Compression level, 0-9 for zip and tar.gz and 1-22 for tar.zst.
+    // Defaults to the format's default level.
+    pub level: Option<i32>,
+
+    // Threads zstd compresses with. Defaults to the number of CPUs.
+    pub threads: Option<u32>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum ArchiveFormat {
+    #[default]
+    #[serde(rename = "zip")]
+    Zip,
+    #[serde(rename = "tar.gz")]
+    TarGz,
+    #[serde(rename = "tar.zst")]
+    TarZst,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Config {
     pub releases: Vec<Release>,
+    pub templating: Option<Templating>,
+
+    // Folder the plugins without a `path` are loaded from. Defaults to
+    // `.rlsr/plugins`.
+    pub plugins_dir: Option<String>,
+
+    pub version: Option<Version>,
+    pub git: Option<Git>,
+    pub hooks: Option<GlobalHooks>,
+
+    // External tools which have to be installed before anything is built.
+    pub requirements: Option<Vec<Requirement>>,
+
+    // Hosts the builds for some platforms run on over ssh, e.g. a mac for
+    // the darwin builds.
+    pub runners: Option<Vec<Runner>>,
+
+    // Command the build and hook commands are run with, e.g. `nix develop -c`
+    // or `devbox run`, for the toolchains it provides.
+    pub shell_wrapper: Option<String>,
+
+    // Base config this config is merged over, a url or a path relative to
+    // this config, e.g. an organization's shared release config.
+    pub extends: Option<String>,
+
+    // Sha256 of the base config. A base which doesn't match fails the run.
+    pub extends_sha256: Option<String>,
+
+    // Branches and CI systems releases can only be published from, so that a
+    // release isn't published by accident from a feature branch.
+    pub publish_from: Option<PublishFrom>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PublishFrom {
+    // Globs of the branches, e.g. `main` or `release/*`. HEAD has to be on
+    // one of them, or be in the history of one when a tag is checked out.
+    pub branches: Option<Vec<String>>,
+
+    // CI systems publishing is allowed in: `github`, `gitlab`, `circleci`,
+    // `buildkite`, `jenkins`, `azure`, `bitbucket`, or `any` for any CI
+    // which sets `CI`. Publishing outside CI fails when it's set.
+    pub ci: Option<Vec<String>>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Runner {
+    // Ssh destination, e.g. `builder@mac-mini.local`.
+    pub host: String,
+
+    // Os of the targets of the builds which run on the host, e.g. `darwin`
+    // for `aarch64-apple-darwin`.
+    pub os: String,
+
+    // Arch of the targets, e.g. `aarch64`. Defaults to any arch.
+    pub arch: Option<String>,
+
+    // Folder the workspace is copied to, relative to the home folder.
+    // Defaults to `.cache/rlsr/<repo folder>`.
+    pub dir: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Requirement {
+    // Name or path of the tool's binary.
+    pub tool: String,
+
+    // Minimum version of the tool, e.g. `2.30`. The version is read from
+    // the first version-like string in the output of `<tool> --version`.
+    pub version: Option<String>,
+
+    // Args which print the tool's version, defaults to `--version`.
+    pub version_args: Option<Vec<String>>,
+
+    // How to install the tool, shown when it's missing or too old.
+    pub hint: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct GlobalHooks {
+    // Commands executed once before any release is built.
+    pub before: Option<Vec<String>>,
+
+    // Commands executed once after all the releases are done.
+    pub after: Option<Vec<String>>,
+
+    // Commands executed when a release fails, before rlsr exits. They get
+    // the error as `{{ error }}` and `RLSR_ERROR`.
+    pub on_error: Option<Vec<String>>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Git {
+    // Fetches the full history and tags if the repo is a shallow clone,
+    // instead of failing.
+    pub unshallow: Option<bool>,
+
+    // Regex for release tags, other tags are ignored when finding the latest
+    // and previous tags.
+    pub tag_pattern: Option<String>,
+
+    // How the previous tag is found, `time` (nearest tag in history, default)
+    // or `semver` (highest version lower than the latest tag).
+    pub tag_sort: Option<TagSort>,
+
+    // Pushes the tag to the remote before publishing if it's only local, so
+    // that the release doesn't point at a tag the remote doesn't have.
+    pub push_tag: Option<bool>,
+
+    // Remote the tag is pushed to. Defaults to `origin`.
+    pub remote: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum TagSort {
+    Time,
+    Semver,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Version {
+    // Version scheme used by the tags. Defaults to `semver`.
+    pub scheme: Option<VersionScheme>,
+
+    // Regex the tags have to match with the `regex` scheme. If the named
+    // group `prerelease` matches, the release is marked as a prerelease.
+    pub pattern: Option<String>,
 }
 
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum VersionScheme {
+    Semver,
+    Calver,
+    Regex,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Templating {
+    // Static values available as `{{ vars.<name> }}` in templates.
+    pub vars: Option<HashMap<String, String>>,
+
+    // Functions backed by shell commands, available as `{{ <name>() }}` in
+    // templates. Arguments are passed to the command as `$1`, `$2`, etc.
+    pub functions: Option<HashMap<String, String>>,
+
+    // Timezone of `date`, `now` and the `time` filter: `UTC`, `local` for
+    // the machine's timezone, or an offset like `+05:30`. Defaults to UTC.
+    pub timezone: Option<String>,
+
+    // Locale of the month and day names of the `time` filter, e.g. `de_DE`.
+    // Defaults to english names.
+    pub locale: Option<String>,
+}
+
+// Config files looked for in the current folder when `--config` isn't
+// given, in order.
+const CONFIG_FILES: &[&str] = &[
+    "rlsr.yml",
+    "rlsr.yaml",
+    "rlsr.toml",
+    "rlsr.json",
+    ".config/rlsr.yml",
+    ".config/rlsr.yaml",
+    ".config/rlsr.toml",
+    ".config/rlsr.json",
+];
+
+// Finds the config in the current folder.
+pub fn find_config() -> Result<String> {
+    CONFIG_FILES
+        .iter()
+        .find(|file| Path::new(file).is_file())
+        .map(|file| file.to_string())
+        .ok_or_else(|| eyre!("no config found, looked for {}", CONFIG_FILES.join(", ")))
+}
+
+// Parses the config file, a config at an http(s) url, or stdin for `-`.
+// TOML files are parsed by their extension, YAML parses the rest, JSON
+// included. Configs with `extends` are merged over their base config.
 pub async fn parse_config(cfg_path: &str) -> Result<Config> {
-    let cfg_str = fs::read_to_string(&cfg_path)
-        .await
-        .with_context(|| format!("error reading config file at {}", cfg_path))?;
-    let cfg: Config = serde_yaml::from_str(&cfg_str)?;
+    let cfg_str = read_config(cfg_path).await?;
+    let value = config_value(cfg_path, &cfg_str)?;
+    let encrypted = value.get("sops").is_some();
+    let value = match encrypted {
+        true => decrypt(cfg_path, &cfg_str, &value).await?,
+        false => value,
+    };
+    let mut cfg: Config = match value.get("extends") {
+        Some(_) => {
+            let value = extend(cfg_path, value, 0).await?;
+            serde_json::from_value(value)?
+        }
+        // YAML is parsed directly for the line numbers in its errors.
+        None if !is_toml(cfg_path) && !encrypted => serde_yaml::from_str(&cfg_str)?,
+        None => serde_json::from_value(value)?,
+    };
+
+    // Fill in the github owner/repo from the git remote if they're missing.
+    for release in cfg.releases.iter_mut() {
+        if let Some(gh) = release.targets.github.as_mut() {
+            if gh.owner.is_empty() || gh.repo.is_empty() {
+                let (owner, repo) = GitRepo::open()
+                    .and_then(|git| git.remote_repo())
+                    .wrap_err_with(|| "error finding github owner/repo from git remote")?;
+                if gh.owner.is_empty() {
+                    gh.owner = owner;
+                }
+                if gh.repo.is_empty() {
+                    gh.repo = repo;
+                }
+            }
+        }
+    }
+
     Ok(cfg)
 }
+
+// Maximum depth of configs extending configs, which stops cycles.
+const MAX_EXTENDS: usize = 5;
+
+async fn read_config(path: &str) -> Result<String> {
+    if path == "-" {
+        let mut cfg_str = String::new();
+        io::stdin()
+            .read_to_string(&mut cfg_str)
+            .await
+            .wrap_err_with(|| "error reading config from stdin")?;
+        return Ok(cfg_str);
+    }
+    if path.starts_with("https://") || path.starts_with("http://") {
+        let res = reqwest::get(path)
+            .await
+            .wrap_err_with(|| format!("error downloading config from {}", path))?;
+        if !res.status().is_success() {
+            bail!(
+                "error downloading config from {}, status: {}",
+                path,
+                res.status()
+            );
+        }
+        return Ok(res.text().await?);
+    }
+    fs::read_to_string(&path)
+        .await
+        .with_context(|| format!("error reading config file at {}", path))
+}
+
+fn is_toml(path: &str) -> bool {
+    path.split(['?', '#'])
+        .next()
+        .unwrap_or(path)
+        .ends_with(".toml")
+}
+
+fn config_value(path: &str, cfg_str: &str) -> Result<serde_json::Value> {
+    if is_toml(path) {
+        let doc: DocumentMut = cfg_str.parse()?;
+        return Ok(toml_to_json(doc.as_item()));
+    }
+    Ok(serde_yaml::from_str(cfg_str)?)
+}
+
+// Merges the config over the base config it extends, after the base's own
+// `extends`. The base is pinned by `extends_sha256` if it's given.
+fn extend(
+    path: &str,
+    mut value: serde_json::Value,
+    depth: usize,
+) -> Pin<Box<dyn Future<Output = Result<serde_json::Value>> + Send + '_>> {
+    Box::pin(async move {
+        let base = match value.get("extends").and_then(|e| e.as_str()) {
+            Some(base) => base_path(path, base)?,
+            None => return Ok(value),
+        };
+        if depth >= MAX_EXTENDS {
+            bail!(
+                "configs extend more than {} levels deep at {}",
+                MAX_EXTENDS,
+                base
+            );
+        }
+        let base_str = read_config(&base).await?;
+        if let Some(pinned) = value.get("extends_sha256").and_then(|s| s.as_str()) {
+            let sha = format!("{:x}", Sha256::digest(base_str.as_bytes()));
+            if !sha.eq_ignore_ascii_case(pinned) {
+                bail!(
+                    "sha256 of base config {} is {}, but `extends_sha256` is {}",
+                    base,
+                    sha,
+                    pinned
+                );
+            }
+        }
+        let base_value = config_value(&base, &base_str)
+            .wrap_err_with(|| format!("error parsing base config {}", base))?;
+        let base_value = match base_value.get("sops") {
+            Some(_) => decrypt(&base, &base_str, &base_value).await?,
+            None => base_value,
+        };
+        let mut base_value = extend(&base, base_value, depth + 1).await?;
+        if let Some(obj) = value.as_object_mut() {
+            obj.remove("extends");
+            obj.remove("extends_sha256");
+        }
+        merge(&mut base_value, value);
+        Ok(base_value)
+    })
+}
+
+// Decrypts a config encrypted with sops, which has its metadata in the
+// `sops` key, with the `sops` CLI. It finds the keys, e.g. age keys in
+// `SOPS_AGE_KEY_FILE` or a cloud KMS. The decrypted values are redacted from
+// the logs.
+async fn decrypt(
+    path: &str,
+    cfg_str: &str,
+    encrypted: &serde_json::Value,
+) -> Result<serde_json::Value> {
+    let input_type = match path.ends_with(".json") || cfg_str.trim_start().starts_with('{') {
+        true => "json",
+        false => "yaml",
+    };
+    let mut child = Command::new("sops")
+        .args([
+            "--decrypt",
+            "--input-type",
+            input_type,
+            "--output-type",
+            "json",
+        ])
+        .arg("/dev/stdin")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .wrap_err_with(|| format!("error executing sops, it's needed to decrypt {}", path))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(cfg_str.as_bytes()).await?;
+    }
+    let output = child.wait_with_output().await?;
+    if !output.status.success() {
+        bail!(
+            "error decrypting {} with sops: {}",
+            path,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    let decrypted: serde_json::Value = serde_json::from_slice(&output.stdout)
+        .wrap_err_with(|| format!("error parsing {} decrypted by sops", path))?;
+    add_secrets(encrypted, &decrypted);
+    Ok(decrypted)
+}
+
+// Redacts the values which sops encrypted, `ENC[...]` in the encrypted config.
+fn add_secrets(encrypted: &serde_json::Value, decrypted: &serde_json::Value) {
+    use serde_json::Value;
+    match (encrypted, decrypted) {
+        (Value::String(value), decrypted) if value.starts_with("ENC[") => match decrypted {
+            Value::String(secret) => add_secret(secret),
+            secret => add_secret(&secret.to_string()),
+        },
+        (Value::Object(encrypted), Value::Object(decrypted)) => {
+            for (key, value) in encrypted {
+                if let Some(secret) = decrypted.get(key) {
+                    add_secrets(value, secret);
+                }
+            }
+        }
+        (Value::Array(encrypted), Value::Array(decrypted)) => {
+            for (value, secret) in encrypted.iter().zip(decrypted) {
+                add_secrets(value, secret);
+            }
+        }
+        _ => {}
+    }
+}
+
+// Resolves the base of `extends`, which is a url or a path relative to the
+// extending config.
+fn base_path(path: &str, base: &str) -> Result<String> {
+    if base.starts_with("https://") || base.starts_with("http://") {
+        return Ok(base.to_string());
+    }
+    if path.starts_with("https://") || path.starts_with("http://") {
+        return Ok(reqwest::Url::parse(path)?.join(base)?.to_string());
+    }
+    let dir = match path {
+        "-" => Path::new("."),
+        path => Path::new(path).parent().unwrap_or(Path::new(".")),
+    };
+    Ok(dir.join(base).to_string_lossy().to_string())
+}
+
+// Merges the values over the base. Maps are merged key by key and lists are
+// replaced, except the lists of named items like `releases`, whose items are
+// merged by name.
+fn merge(base: &mut serde_json::Value, value: serde_json::Value) {
+    use serde_json::Value;
+    match (base, value) {
+        (Value::Object(base), Value::Object(value)) => {
+            for (key, value) in value {
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (Value::Array(base), Value::Array(items)) if is_named(base) && is_named(&items) => {
+            for item in items {
+                let existing = base.iter_mut().find(|b| b["name"] == item["name"]);
+                match existing {
+                    Some(existing) => merge(existing, item),
+                    None => base.push(item),
+                }
+            }
+        }
+        (base, value) => *base = value,
+    }
+}
+
+fn is_named(items: &[serde_json::Value]) -> bool {
+    items.iter().all(|item| item["name"].is_string())
+}
+
+// Converts a TOML item to JSON, which the config is deserialized from.
+// Datetimes are kept as strings.
+fn toml_to_json(item: &Item) -> serde_json::Value {
+    match item {
+        Item::None => serde_json::Value::Null,
+        Item::Value(value) => toml_value_to_json(value),
+        Item::Table(table) => table
+            .iter()
+            .map(|(key, item)| (key.to_string(), toml_to_json(item)))
+            .collect(),
+        Item::ArrayOfTables(tables) => tables
+            .iter()
+            .map(|table| toml_to_json(&Item::Table(table.clone())))
+            .collect(),
+    }
+}
+
+fn toml_value_to_json(value: &toml_edit::Value) -> serde_json::Value {
+    match value {
+        toml_edit::Value::String(s) => s.value().clone().into(),
+        toml_edit::Value::Integer(i) => (*i.value()).into(),
+        toml_edit::Value::Float(f) => (*f.value()).into(),
+        toml_edit::Value::Boolean(b) => (*b.value()).into(),
+        toml_edit::Value::Datetime(d) => d.value().to_string().into(),
+        toml_edit::Value::Array(array) => array.iter().map(toml_value_to_json).collect(),
+        toml_edit::Value::InlineTable(table) => table
+            .iter()
+            .map(|(key, value)| (key.to_string(), toml_value_to_json(value)))
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn merges_maps_by_key() {
+        let mut base =
+            json!({"git": {"remote": "origin", "unshallow": true}, "shell_wrapper": "a"});
+        merge(
+            &mut base,
+            json!({"git": {"remote": "upstream"}, "shell_wrapper": "b"}),
+        );
+        assert_eq!(
+            base,
+            json!({"git": {"remote": "upstream", "unshallow": true}, "shell_wrapper": "b"})
+        );
+    }
+
+    #[test]
+    fn merges_named_lists_by_name() {
+        let mut base = json!({"releases": [
+            {"name": "cli", "dist_folder": "dist", "targets": {"github": {"owner": "o"}}},
+            {"name": "lib", "dist_folder": "dist"},
+        ]});
+        merge(
+            &mut base,
+            json!({"releases": [
+                {"name": "cli", "targets": {"github": {"repo": "r"}}},
+                {"name": "docs", "dist_folder": "site"},
+            ]}),
+        );
+        assert_eq!(
+            base,
+            json!({"releases": [
+                {"name": "cli", "dist_folder": "dist", "targets": {"github": {"owner": "o", "repo": "r"}}},
+                {"name": "lib", "dist_folder": "dist"},
+                {"name": "docs", "dist_folder": "site"},
+            ]})
+        );
+    }
+
+    #[test]
+    fn replaces_other_lists() {
+        let mut base = json!({"extra_assets": ["a", "b"]});
+        merge(&mut base, json!({"extra_assets": ["c"]}));
+        assert_eq!(base, json!({"extra_assets": ["c"]}));
+    }
+}
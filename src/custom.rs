@@ -0,0 +1,112 @@
+use crate::config::{Custom as CustomCfg, Release};
+use crate::release_provider::{AssetNames, PublishResult, ReleaseProvider};
+use crate::utils::{group_command, sha256_file, spawn_tracked};
+use async_trait::async_trait;
+use eyre::{bail, Context, Result};
+use log::{debug, info, warn};
+use serde_json::json;
+use std::process::Stdio;
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+
+/// Custom publishes the release with a user given command, for release
+/// systems which rlsr doesn't support. The command gets the tag, changelog and
+/// archives as JSON on stdin and a non zero exit code fails the publish.
+pub struct Custom {
+    cfg: CustomCfg,
+    prerelease: bool,
+    asset_names: AssetNames,
+}
+
+impl Custom {
+    pub fn new(cfg: CustomCfg, prerelease: bool, asset_names: AssetNames) -> Self {
+        Custom {
+            cfg,
+            prerelease,
+            asset_names,
+        }
+    }
+}
+
+#[allow(clippy::needless_arbitrary_self_type)]
+#[async_trait]
+impl ReleaseProvider for Custom {
+    fn name(self: &Self) -> String {
+        self.cfg.name.clone()
+    }
+
+    async fn publish(
+        self: &Self,
+        release: &Release,
+        all_archives: Arc<Mutex<Vec<String>>>,
+        latest_tag: String,
+        changelog: String,
+    ) -> Result<PublishResult> {
+        let mut artifacts = vec![];
+        for archive in all_archives.lock().await.iter() {
+            artifacts.push(json!({
+                "path": archive,
+                "name": self.asset_names.get(archive),
+                "sha256": sha256_file(archive.clone()).await?,
+            }));
+        }
+        let payload = json!({
+            "release": release.name,
+            "tag": latest_tag,
+            "prerelease": self.prerelease,
+            "changelog": changelog,
+            "artifacts": artifacts,
+        });
+
+        info!(
+            "executing custom target {}: {}",
+            self.cfg.name, self.cfg.command
+        );
+        let (mut child, _group) = spawn_tracked(
+            group_command("sh")
+                .arg("-c")
+                .arg(&self.cfg.command)
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped()),
+        )
+        .wrap_err_with(|| format!("error executing custom target {}", self.cfg.name))?;
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(payload.to_string().as_bytes()).await?;
+        }
+        let output = child.wait_with_output().await?;
+        if !output.status.success() {
+            bail!(
+                "custom target {} failed with {}: {}",
+                self.cfg.name,
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+
+        // The result is optional, any output that isn't a JSON result is
+        // just logged.
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let mut result = match serde_json::from_str::<PublishResult>(stdout.trim()) {
+            Ok(result) => result,
+            Err(_) => {
+                if !stdout.trim().is_empty() {
+                    debug!("custom target {} output: {}", self.cfg.name, stdout.trim());
+                }
+                PublishResult::default()
+            }
+        };
+        if result.provider.is_empty() {
+            result.provider = self.cfg.name.clone();
+        }
+        if !output.stderr.is_empty() {
+            warn!(
+                "custom target {}: {}",
+                self.cfg.name,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(result)
+    }
+}
@@ -0,0 +1,143 @@
+use crate::config::{Dispatch, Release};
+use crate::get_github_token;
+use crate::release_provider::{AssetNames, ProviderError, PublishResult};
+use crate::templating::{tag_context, tag_version, Templates};
+use crate::utils::sha256_file;
+use eyre::{bail, Result};
+use log::info;
+use serde_json::{json, Value};
+use std::env;
+
+// Builds the payload sent to downstream pipelines: the release, its
+// changelog, the archives and what the targets published.
+pub async fn payload(
+    release: &Release,
+    tag: &str,
+    prerelease: bool,
+    changelog: &str,
+    archives: &[String],
+    asset_names: &AssetNames,
+    results: &[PublishResult],
+) -> Result<Value> {
+    let mut artifacts = vec![];
+    for archive in archives {
+        artifacts.push(json!({
+            "name": asset_names.get(archive),
+            "sha256": sha256_file(archive.clone()).await?,
+        }));
+    }
+    Ok(json!({
+        "release": release.name,
+        "tag": tag,
+        "version": tag_version(tag),
+        "prerelease": prerelease,
+        "changelog": changelog,
+        "release_url": results.iter().find_map(|r| r.release_url.clone()),
+        "artifacts": artifacts,
+        "results": results,
+    }))
+}
+
+// Triggers the downstream pipeline with the payload: a github
+// `repository_dispatch` event, a gitlab pipeline or a webhook.
+pub async fn dispatch(
+    cfg: &Dispatch,
+    templates: &Templates,
+    tag: &str,
+    payload: &Value,
+) -> Result<String> {
+    let client = reqwest::Client::new();
+    if let Some(gh) = &cfg.repository_dispatch {
+        let token = match &gh.token_env {
+            Some(var) => env::var(var).unwrap_or_default(),
+            None => get_github_token()?,
+        };
+        if token.is_empty() {
+            bail!("no token to dispatch to {}", gh.repo);
+        }
+        let event_type = gh.event_type.as_deref().unwrap_or("release");
+        let res = client
+            .post(format!(
+                "https://api.github.com/repos/{}/dispatches",
+                gh.repo
+            ))
+            .header("Authorization", format!("token {}", token))
+            .header("Accept", "application/vnd.github.v3+json")
+            .header("User-Agent", "rlsr")
+            .json(&json!({ "event_type": event_type, "client_payload": payload }))
+            .send()
+            .await
+            .map_err(ProviderError::from)?;
+        check_status(res, &gh.repo).await?;
+        info!("dispatched {} to {}", event_type, gh.repo);
+        return Ok(gh.repo.clone());
+    }
+
+    if let Some(gl) = &cfg.gitlab {
+        let var = gl.token_env.as_deref().unwrap_or("GITLAB_TRIGGER_TOKEN");
+        let token = env::var(var).unwrap_or_default();
+        if token.is_empty() {
+            bail!(
+                "{} isn't set, can't trigger the pipeline of {}",
+                var,
+                gl.project
+            );
+        }
+        let url = format!(
+            "{}/api/v4/projects/{}/trigger/pipeline",
+            gl.url
+                .as_deref()
+                .unwrap_or("https://gitlab.com")
+                .trim_end_matches('/'),
+            gl.project.replace('/', "%2F")
+        );
+        let form = [
+            ("token", token.as_str()),
+            ("ref", gl.git_ref.as_deref().unwrap_or("main")),
+            (
+                "variables[RLSR_RELEASE]",
+                payload["release"].as_str().unwrap_or_default(),
+            ),
+            ("variables[RLSR_TAG]", tag),
+            ("variables[RLSR_VERSION]", tag_version(tag)),
+        ];
+        let res = client
+            .post(&url)
+            .form(&form)
+            .send()
+            .await
+            .map_err(ProviderError::from)?;
+        check_status(res, &gl.project).await?;
+        info!("triggered the pipeline of {}", gl.project);
+        return Ok(gl.project.clone());
+    }
+
+    if let Some(hook) = &cfg.webhook {
+        let url = templates.render(&hook.url, tag_context(tag))?;
+        let mut req = client.post(&url).json(payload);
+        for (name, value) in hook.headers.iter().flatten() {
+            req = req.header(name, templates.render(value, tag_context(tag))?);
+        }
+        let res = req.send().await.map_err(ProviderError::from)?;
+        check_status(res, &url).await?;
+        info!("posted the release to {}", url);
+        return Ok(url);
+    }
+    bail!("dispatch needs repository_dispatch, gitlab or webhook")
+}
+
+async fn check_status(res: reqwest::Response, to: &str) -> Result<()> {
+    let status = res.status();
+    if !status.is_success() {
+        return Err(ProviderError::from_status(
+            status,
+            format!(
+                "error dispatching to {}, status: {}, error: {}",
+                to,
+                status,
+                res.text().await.unwrap_or_default()
+            ),
+        ));
+    }
+    Ok(())
+}
@@ -1,10 +1,29 @@
-use crate::config::Release;
-use crate::release_provider::ReleaseProvider;
+use crate::config::{ContainerBackend, Docker as DockerCfg, Release};
+use crate::release_provider::{ProviderError, PublishResult, ReleaseProvider};
+use crate::utils::{group_command, spawn_tracked};
 use async_trait::async_trait;
-use eyre::{bail, Context, Result};
-use log::info;
+use camino::Utf8Path;
+use eyre::{bail, eyre, Context, Result};
+use log::{debug, info, warn};
+use serde_json::Value;
+use std::env;
+use std::io::Write;
+use std::process::Stdio;
 use std::sync::Arc;
-use tokio::{process::Command, sync::Mutex};
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::{fs, process::Command, sync::Mutex};
+
+// Name of the file in the dist folder listing the pushed images.
+const IMAGES_FILE: &str = "images.txt";
+
+// Number of output lines kept for the error message of a failed command.
+const ERROR_LINES: usize = 20;
+
+// Registry of images without one.
+const DOCKER_HUB: &str = "registry-1.docker.io";
+
+// Path of the executor in the kaniko image.
+const KANIKO_EXECUTOR: &str = "/kaniko/executor";
 
 pub struct Docker {}
 
@@ -13,83 +32,550 @@ impl Docker {
         Docker {}
     }
 
-    async fn build_image(release: &Release, latest_tag: &str) -> Result<String> {
-        let docker = match &release.targets.docker {
-            Some(docker) => docker,
-            None => {
-                bail!("missing docker config in config");
-            }
-        };
-        let mut cmd = Command::new("docker");
+    // Builds the image and returns it along with the digest from the build's
+    // metadata file, if the backend wrote one. Kaniko pushes the image too.
+    async fn build_image(
+        release: &Release,
+        docker: &DockerCfg,
+        latest_tag: &str,
+    ) -> Result<(String, Option<String>)> {
+        fs::create_dir_all(&release.dist_folder).await?;
+        let dist = Utf8Path::new(&release.dist_folder);
+        let metadata_file = dist.join("docker-metadata.json").to_string();
+        let iid_file = dist.join("docker-iid").to_string();
         let image = format!("{}:{}", &docker.image, latest_tag);
-        let args: Vec<&str> = vec![
-            "build",
-            &docker.context,
-            "-t",
-            &image,
-            "-f",
-            &docker.dockerfile,
-        ];
-        cmd.args(&args);
+        let backend = docker.backend.clone().unwrap_or(ContainerBackend::Docker);
+        // Only BuildKit writes the metadata file, so docker builds with buildx
+        // when it's installed. The legacy builder and nerdctl write the image
+        // id instead.
+        let buildx = backend == ContainerBackend::Docker && has_buildx().await;
 
+        let args: Vec<&str> = match backend {
+            ContainerBackend::Docker if buildx => vec![
+                "buildx",
+                "build",
+                &docker.context,
+                "-t",
+                &image,
+                "-f",
+                &docker.dockerfile,
+                "--load",
+                "--progress",
+                "plain",
+                "--metadata-file",
+                &metadata_file,
+            ],
+            ContainerBackend::Docker | ContainerBackend::Nerdctl => vec![
+                "build",
+                &docker.context,
+                "-t",
+                &image,
+                "-f",
+                &docker.dockerfile,
+                "--iidfile",
+                &iid_file,
+            ],
+            ContainerBackend::Podman => vec![
+                "build",
+                &docker.context,
+                "-t",
+                &image,
+                "-f",
+                &docker.dockerfile,
+            ],
+            ContainerBackend::Kaniko => vec![
+                "--context",
+                &docker.context,
+                "--dockerfile",
+                &docker.dockerfile,
+                "--destination",
+                &image,
+                "--digest-file",
+                &metadata_file,
+            ],
+        };
+
+        let bin = backend_bin(&backend);
         info!(
-            "executing docker build with command: docker {}",
+            "executing image build with command: {} {}",
+            bin,
             args.join(" ")
         );
+        run_streamed(bin, &args).await?;
 
-        let child = cmd.spawn()?;
-        let output = child.wait_with_output().await?;
-        if !output.status.success() {
-            bail!(
-                "error executing docker build: {}",
-                String::from_utf8_lossy(&output.stdout).to_string()
-            );
-        }
-
-        Ok(image)
+        let digest = match backend {
+            ContainerBackend::Kaniko => read_digest(&metadata_file).await,
+            ContainerBackend::Docker if buildx => Self::metadata_digest(&metadata_file).await,
+            // The image id isn't the digest pulls resolve to, that's read
+            // after pushing.
+            ContainerBackend::Docker | ContainerBackend::Nerdctl => {
+                if let Some(id) = read_digest(&iid_file).await {
+                    debug!("built image {} with id {}", image, id);
+                }
+                None
+            }
+            ContainerBackend::Podman => None,
+        };
+        Ok((image, digest))
     }
 
-    async fn push_image(image: &str) -> Result<()> {
-        let mut cmd = Command::new("docker");
-        let args: Vec<&str> = vec!["push", image];
-        cmd.args(&args);
+    // Pushes the image and returns the digest if the backend reports it.
+    async fn push_image(
+        release: &Release,
+        backend: &ContainerBackend,
+        image: &str,
+    ) -> Result<Option<String>> {
+        let digest_file = Utf8Path::new(&release.dist_folder)
+            .join("docker-digest")
+            .to_string();
+        let args: Vec<&str> = match backend {
+            ContainerBackend::Podman => vec!["push", "--digestfile", &digest_file, image],
+            _ => vec!["push", image],
+        };
 
+        let bin = backend_bin(backend);
         info!(
-            "executing docker push with command: docker {}",
+            "executing image push with command: {} {}",
+            bin,
             args.join(" ")
         );
+        run_streamed(bin, &args).await?;
 
-        let child = cmd.spawn()?;
-        let output = child.wait_with_output().await?;
+        match backend {
+            ContainerBackend::Podman => Ok(read_digest(&digest_file).await),
+            _ => Ok(None),
+        }
+    }
+
+    // Builds and pushes the image, with the digests of the pushed image.
+    async fn build_and_push(
+        release: &Release,
+        docker: &DockerCfg,
+        latest_tag: &str,
+    ) -> Result<PublishResult> {
+        let backend = docker.backend.clone().unwrap_or(ContainerBackend::Docker);
+        if docker.create_repository.unwrap_or(false) {
+            create_repository(&docker.image).await?;
+        }
+        let (image, build_digest) = Self::build_image(release, docker, latest_tag)
+            .await
+            .wrap_err_with(|| "error building docker image")?;
+        debug!("built image {} with digest {:?}", image, build_digest);
+
+        let mut result = PublishResult::new("docker");
+        let repo = docker_repo(&image);
+
+        // Kaniko has already pushed the image while building it.
+        if backend == ContainerBackend::Kaniko {
+            if let Some(digest) = build_digest {
+                result.image_digests.push(format!("{}@{}", repo, digest));
+            }
+            return Ok(result);
+        }
+
+        if let Some(digest) = Self::push_image(release, &backend, &image).await? {
+            result.image_digests.push(format!("{}@{}", repo, digest));
+            return Ok(result);
+        }
+
+        // The pushed digest is what pulls resolve to, the build digest is only
+        // used if it can't be found.
+        match Self::image_digest(&backend, &image).await {
+            Ok(digest) => result.image_digests.push(digest),
+            Err(err) => {
+                warn!("{}", err);
+                if let Some(digest) = build_digest {
+                    result.image_digests.push(format!("{}@{}", repo, digest));
+                }
+            }
+        }
+        Ok(result)
+    }
+
+    // Gets the image digest from the metadata file written by buildx.
+    async fn metadata_digest(path: &str) -> Option<String> {
+        let contents = fs::read_to_string(path).await.ok()?;
+        let metadata: Value = serde_json::from_str(&contents).ok()?;
+        metadata["containerimage.digest"].as_str().map(String::from)
+    }
+
+    // Gets the digest of the pushed image, e.g. `image@sha256:...`.
+    async fn image_digest(backend: &ContainerBackend, image: &str) -> Result<String> {
+        let output = Command::new(backend_bin(backend))
+            .args(["inspect", "--format", "{{index .RepoDigests 0}}", image])
+            .output()
+            .await?;
         if !output.status.success() {
             bail!(
-                "error executing docker push: {}",
-                String::from_utf8_lossy(&output.stdout).to_string()
+                "error getting digest of image {}: {}",
+                image,
+                String::from_utf8_lossy(&output.stderr).trim()
             );
         }
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    }
+}
+
+// Gets the registry of an image reference. References without a registry,
+// like `iamd3vil/rlsr`, are on docker hub.
+pub(crate) fn registry_host(image: &str) -> String {
+    match image.split_once('/') {
+        Some((host, _)) if host.contains('.') || host.contains(':') || host == "localhost" => {
+            host.to_string()
+        }
+        _ => DOCKER_HUB.to_string(),
+    }
+}
 
-        Ok(())
+// Checks that the backend has credentials for the registry of the image
+// before anything is built, so that a missing login fails up front instead
+// of at `docker push`. Credentials are looked up like docker does: the
+// registry's credential helper, then the config's `auths`, then its
+// `credsStore`. Helpers like `ecr-login` and `gcloud` are run to get a
+// token.
+pub(crate) fn check_push_auth(image: &str, backend: &ContainerBackend) -> Result<()> {
+    let host = registry_host(image);
+    // Docker hub's credentials are stored under its v1 index.
+    let key = match host.as_str() {
+        DOCKER_HUB => "https://index.docker.io/v1/",
+        host => host,
+    };
+    let path = auth_file(backend);
+    let config: Value = match std::fs::read_to_string(&path) {
+        Ok(contents) => {
+            serde_json::from_str(&contents).wrap_err_with(|| format!("error parsing {}", path))?
+        }
+        Err(_) => Value::Null,
+    };
+
+    if let Some(helper) = config["credHelpers"][key].as_str() {
+        return credential_helper(helper, key)
+            .map_err(|err| eyre!("no credentials for {}, {}{}", host, err, login_hint(&host)));
+    }
+    // Logins are stored with or without the scheme and path, and podman
+    // stores docker hub's as `docker.io`.
+    let auth = config["auths"].as_object().and_then(|auths| {
+        auths.iter().find(|(server, _)| {
+            let server = server
+                .trim_start_matches("https://")
+                .trim_start_matches("http://")
+                .split('/')
+                .next()
+                .unwrap_or_default();
+            match host.as_str() {
+                DOCKER_HUB => ["index.docker.io", "docker.io", DOCKER_HUB].contains(&server),
+                host => server == host,
+            }
+        })
+    });
+    if let Some((_, auth)) = auth {
+        if auth["auth"].as_str().is_some_and(|a| !a.is_empty())
+            || auth["identitytoken"]
+                .as_str()
+                .is_some_and(|t| !t.is_empty())
+        {
+            debug!("found credentials for {} in {}", host, path);
+            return Ok(());
+        }
+    }
+    if let Some(store) = config["credsStore"].as_str() {
+        return credential_helper(store, key)
+            .map_err(|err| eyre!("no credentials for {}, {}{}", host, err, login_hint(&host)));
     }
+    bail!(
+        "no credentials for {} in {}{}",
+        host,
+        path,
+        login_hint(&host)
+    )
+}
+
+// Gets the credentials for the registry from `docker-credential-<helper>`,
+// failing if it doesn't have any.
+fn credential_helper(helper: &str, registry: &str) -> Result<()> {
+    let bin = format!("docker-credential-{}", helper);
+    let mut child = std::process::Command::new(&bin)
+        .arg("get")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .wrap_err_with(|| format!("error executing credential helper {}", bin))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(registry.as_bytes())?;
+    }
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        let out = String::from_utf8_lossy(&output.stdout);
+        let err = String::from_utf8_lossy(&output.stderr);
+        bail!(
+            "{} failed: {}",
+            bin,
+            format!("{} {}", out.trim(), err.trim()).trim()
+        );
+    }
+    let creds: Value = serde_json::from_slice(&output.stdout).unwrap_or_default();
+    if creds["Secret"].as_str().unwrap_or_default().is_empty() {
+        bail!("{} returned no secret", bin);
+    }
+    debug!("found credentials for {} with {}", registry, bin);
+    Ok(())
+}
+
+// Creates the image's repository if it doesn't exist, for registries which
+// don't create repositories on push: ECR, with `aws ecr`, and Artifact
+// Registry, with `gcloud artifacts`.
+async fn create_repository(image: &str) -> Result<()> {
+    let host = registry_host(image);
+    let path = image.split_once('/').map(|(_, path)| path).unwrap_or(image);
+    let path = docker_repo(path);
+    // ECR hosts are `<account>.dkr.ecr.<region>.amazonaws.com`.
+    if let Some(region) = host
+        .split_once(".dkr.ecr.")
+        .and_then(|(_, rest)| rest.strip_suffix(".amazonaws.com"))
+    {
+        let region = ["--region", region];
+        let describe = ["ecr", "describe-repositories", "--repository-names", path];
+        let create = ["ecr", "create-repository", "--repository-name", path];
+        return ensure_repository("aws", &[&describe, &region], &[&create, &region], path).await;
+    }
+    // Artifact Registry images are `<location>-docker.pkg.dev/<project>/<repository>/<image>`.
+    if let Some(location) = host.strip_suffix("-docker.pkg.dev") {
+        let (project, repository) = match path.split('/').collect::<Vec<_>>()[..] {
+            [project, repository, _, ..] => (project, repository),
+            _ => bail!(
+                "{} isn't an artifact registry image, it needs a project and repository",
+                image
+            ),
+        };
+        let scope = ["--project", project, "--location", location];
+        let describe = ["artifacts", "repositories", "describe", repository];
+        let create = [
+            "artifacts",
+            "repositories",
+            "create",
+            repository,
+            "--repository-format",
+            "docker",
+        ];
+        return ensure_repository(
+            "gcloud",
+            &[&describe, &scope],
+            &[&create, &scope],
+            repository,
+        )
+        .await;
+    }
+    bail!(
+        "`create_repository` only supports ECR and Artifact Registry, not {}",
+        host
+    )
+}
+
+// Creates the repository with the CLI if describing it fails because it
+// isn't found. Other failures, like missing permissions, are returned.
+async fn ensure_repository(
+    cli: &str,
+    describe: &[&[&str]],
+    create: &[&[&str]],
+    repository: &str,
+) -> Result<()> {
+    let output = Command::new(cli)
+        .args(describe.concat())
+        .output()
+        .await
+        .wrap_err_with(|| format!("error executing {}", cli))?;
+    if output.status.success() {
+        debug!("repository {} exists", repository);
+        return Ok(());
+    }
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if !stderr.contains("RepositoryNotFoundException") && !stderr.contains("NOT_FOUND") {
+        return Err(ProviderError::from_output(
+            &stderr,
+            format!(
+                "error checking repository {}: {}",
+                repository,
+                stderr.trim()
+            ),
+        ));
+    }
+
+    info!("creating repository {} with {}", repository, cli);
+    let output = Command::new(cli)
+        .args(create.concat())
+        .output()
+        .await
+        .wrap_err_with(|| format!("error executing {}", cli))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(ProviderError::from_output(
+            &stderr,
+            format!(
+                "error creating repository {}: {}",
+                repository,
+                stderr.trim()
+            ),
+        ));
+    }
+    Ok(())
+}
+
+// Gets the auth file the backend reads the registry credentials from.
+fn auth_file(backend: &ContainerBackend) -> String {
+    let home = env::var("HOME").unwrap_or_default();
+    let docker_config = match env::var("DOCKER_CONFIG") {
+        Ok(dir) => format!("{}/config.json", dir),
+        Err(_) if *backend == ContainerBackend::Kaniko => "/kaniko/.docker/config.json".into(),
+        Err(_) => format!("{}/.docker/config.json", home),
+    };
+    if *backend != ContainerBackend::Podman {
+        return docker_config;
+    }
+    // Podman falls back to docker's config without its own auth file.
+    let podman = match (env::var("REGISTRY_AUTH_FILE"), env::var("XDG_RUNTIME_DIR")) {
+        (Ok(file), _) => file,
+        (_, Ok(dir)) => format!("{}/containers/auth.json", dir),
+        _ => format!("{}/.config/containers/auth.json", home),
+    };
+    match Utf8Path::new(&podman).exists() {
+        true => podman,
+        false => docker_config,
+    }
+}
+
+// How to log in to the registry, with the cloud CLIs for ECR and GCR/GAR.
+fn login_hint(host: &str) -> String {
+    let hint = if host.contains(".dkr.ecr.") && host.ends_with(".amazonaws.com") {
+        format!(
+            "configure the `ecr-login` credential helper or run `aws ecr get-login-password | docker login --username AWS --password-stdin {}`",
+            host
+        )
+    } else if host == "gcr.io" || host.ends_with(".gcr.io") || host.ends_with("-docker.pkg.dev") {
+        format!("run `gcloud auth configure-docker {}`", host)
+    } else if host == DOCKER_HUB {
+        "run `docker login`".to_string()
+    } else {
+        format!("run `docker login {}`", host)
+    };
+    format!(", {}", hint)
+}
+
+pub fn backend_bin(backend: &ContainerBackend) -> &'static str {
+    match backend {
+        ContainerBackend::Docker => "docker",
+        ContainerBackend::Podman => "podman",
+        ContainerBackend::Nerdctl => "nerdctl",
+        ContainerBackend::Kaniko => KANIKO_EXECUTOR,
+    }
+}
+
+// Checks if docker has buildx, which builds with BuildKit.
+async fn has_buildx() -> bool {
+    Command::new("docker")
+        .args(["buildx", "version"])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .await
+        .map(|status| status.success())
+        .unwrap_or(false)
+}
+
+// Reads a digest file like the ones written by kaniko and podman.
+async fn read_digest(path: &str) -> Option<String> {
+    let digest = fs::read_to_string(path).await.ok()?;
+    Some(digest.trim().to_string()).filter(|d| !d.is_empty())
+}
+
+// Runs the command with the given args, logging its output as it comes so
+// that the progress of long builds is visible. BuildKit writes the progress
+// to stderr.
+async fn run_streamed(bin: &str, args: &[&str]) -> Result<()> {
+    let name = format!("{} {}", bin, args.first().unwrap_or(&""));
+    let (mut child, _group) = spawn_tracked(
+        group_command(bin)
+            .args(args)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped()),
+    )
+    .wrap_err_with(|| format!("error executing {}", name))?;
+
+    let (stdout, stderr) = match (child.stdout.take(), child.stderr.take()) {
+        (Some(out), Some(err)) => tokio::join!(log_lines(&name, out), log_lines(&name, err)),
+        _ => (vec![], vec![]),
+    };
+
+    let status = child.wait().await?;
+    if !status.success() {
+        let mut lines = stdout;
+        lines.extend(stderr);
+        let start = lines.len().saturating_sub(ERROR_LINES);
+        let output = lines[start..].join("\n");
+        return Err(ProviderError::from_output(
+            &output,
+            format!("error executing {}: {}", name, output),
+        ));
+    }
+    Ok(())
+}
+
+// Logs every line of the output and returns the last few lines.
+async fn log_lines<R: AsyncRead + Unpin>(name: &str, out: R) -> Vec<String> {
+    let mut tail = vec![];
+    let mut lines = BufReader::new(out).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        info!("{}: {}", name, line);
+        tail.push(line);
+        if tail.len() > ERROR_LINES {
+            tail.remove(0);
+        }
+    }
+    tail
 }
 
 #[allow(clippy::needless_arbitrary_self_type)]
 #[async_trait]
 impl ReleaseProvider for Docker {
+    fn name(self: &Self) -> String {
+        "docker".to_string()
+    }
+
     async fn publish(
         self: &Self,
         release: &Release,
-        _all_archives: Arc<Mutex<Vec<String>>>,
+        all_archives: Arc<Mutex<Vec<String>>>,
         latest_tag: String,
-    ) -> Result<()> {
-        if release.targets.docker.is_none() {
-            bail!("docker config can't be empty")
-        }
-        let image = Self::build_image(release, &latest_tag)
-            .await
-            .wrap_err_with(|| "error building docker image")?;
+        _changelog: String,
+    ) -> Result<PublishResult> {
+        let docker = match &release.targets.docker {
+            Some(docker) => docker,
+            None => bail!("docker config can't be empty"),
+        };
+        let result = Self::build_and_push(release, docker, &latest_tag).await?;
 
-        Self::push_image(&image).await?;
+        // Lists the pushed tags with their digests, so that the images can be
+        // pinned from the release page. The docker target publishes before
+        // the others, which publish it with the archives.
+        let image = format!("{}:{}", docker.image, latest_tag);
+        let mut lines = vec![];
+        for digest in &result.image_digests {
+            let digest = digest.rsplit_once('@').map_or(digest.as_str(), |(_, d)| d);
+            lines.push(format!("{} {}\n", image, digest));
+        }
+        if !lines.is_empty() {
+            let path = Utf8Path::new(&release.dist_folder).join(IMAGES_FILE);
+            fs::write(&path, lines.concat()).await?;
+            all_archives.lock().await.push(path.to_string());
+        }
+        Ok(result)
+    }
+}
 
-        Ok(())
+// Gets the image without the tag.
+fn docker_repo(image: &str) -> &str {
+    match image.rsplit_once(':') {
+        Some((repo, tag)) if !tag.contains('/') => repo,
+        _ => image,
     }
 }
@@ -0,0 +1,167 @@
+use crate::config::{Docs, Release};
+use crate::templating::{tag_context, Templates};
+use crate::utils::{group_command, spawn_tracked};
+use camino::Utf8Path;
+use eyre::{bail, Context, Result};
+use log::{debug, info};
+use minijinja::context;
+use std::fs as std_fs;
+use tokio::{fs, process::Command, task};
+
+// Builds the docs with the command and pushes them to the folder for the tag
+// in the branch, from a worktree in the dist folder. The branch is created if
+// the remote doesn't have it. Returns where the docs were pushed.
+pub async fn deploy(
+    release: &Release,
+    cfg: &Docs,
+    templates: &Templates,
+    tag: &str,
+    remote: &str,
+) -> Result<String> {
+    let ctx = context! { output => &cfg.output, ..tag_context(tag) };
+    let cmd = templates
+        .render(&cfg.command, ctx)
+        .wrap_err_with(|| "error rendering docs command")?;
+    info!("building docs: {}", cmd);
+    let (mut child, _group) = spawn_tracked(group_command("sh").arg("-c").arg(&cmd))
+        .wrap_err_with(|| format!("error executing docs command: {}", cmd))?;
+    let status = child
+        .wait()
+        .await
+        .wrap_err_with(|| format!("error executing docs command: {}", cmd))?;
+    if !status.success() {
+        bail!("docs command `{}` failed with {}", cmd, status);
+    }
+    if !Utf8Path::new(&cfg.output).is_dir() {
+        bail!("docs command `{}` didn't create {}", cmd, cfg.output);
+    }
+
+    let branch = cfg.branch.as_deref().unwrap_or("gh-pages");
+    let dir = match &cfg.dir {
+        Some(dir) => templates.render(dir, tag_context(tag))?,
+        None => tag.to_string(),
+    };
+    let worktree = Utf8Path::new(&release.dist_folder).join("docs-worktree");
+    remove_worktree(worktree.as_str()).await?;
+    fs::create_dir_all(&release.dist_folder).await?;
+
+    if git(".", &["fetch", remote, branch]).await.is_ok() {
+        git(
+            ".",
+            &[
+                "worktree",
+                "add",
+                "--detach",
+                worktree.as_str(),
+                "FETCH_HEAD",
+            ],
+        )
+        .await?;
+    } else {
+        debug!("{} doesn't have {}, creating it", remote, branch);
+        git(
+            ".",
+            &["worktree", "add", "--detach", worktree.as_str(), "HEAD"],
+        )
+        .await?;
+        git(
+            worktree.as_str(),
+            &["checkout", "--quiet", "--orphan", branch],
+        )
+        .await?;
+        git(
+            worktree.as_str(),
+            &["rm", "-r", "-q", "--ignore-unmatch", "."],
+        )
+        .await?;
+    }
+    let res = push(cfg, &worktree, &dir, tag, remote, branch).await;
+    remove_worktree(worktree.as_str()).await?;
+    let pushed = format!("{}/{}:{}", remote, branch, dir);
+    if res? {
+        info!("pushed docs to {}", pushed);
+    }
+    Ok(pushed)
+}
+
+// Replaces the tag's folder in the worktree with the docs, and commits and
+// pushes them if they changed. Returns whether they were pushed.
+async fn push(
+    cfg: &Docs,
+    worktree: &Utf8Path,
+    dir: &str,
+    tag: &str,
+    remote: &str,
+    branch: &str,
+) -> Result<bool> {
+    let dest = worktree.join(dir);
+    if dest.exists() {
+        fs::remove_dir_all(&dest).await?;
+    }
+    let (src, dst) = (cfg.output.clone(), dest.to_string());
+    task::spawn_blocking(move || copy_dir(Utf8Path::new(&src), Utf8Path::new(&dst)))
+        .await?
+        .wrap_err_with(|| format!("error copying docs to {}", dest))?;
+
+    git(worktree.as_str(), &["add", "-A"]).await?;
+    if git(worktree.as_str(), &["status", "--porcelain"])
+        .await?
+        .trim()
+        .is_empty()
+    {
+        info!("docs for {} didn't change, skipping pushing them", tag);
+        return Ok(false);
+    }
+    let message = format!("Deploy docs for {}", tag);
+    git(worktree.as_str(), &["commit", "--quiet", "-m", &message]).await?;
+    git(
+        worktree.as_str(),
+        &["push", remote, &format!("HEAD:refs/heads/{}", branch)],
+    )
+    .await?;
+    Ok(true)
+}
+
+// Removes the worktree left by this or a previous run, if any.
+async fn remove_worktree(worktree: &str) -> Result<()> {
+    if Utf8Path::new(worktree).exists() {
+        let _ = git(".", &["worktree", "remove", "--force", worktree]).await;
+        if Utf8Path::new(worktree).exists() {
+            fs::remove_dir_all(worktree).await?;
+        }
+    }
+    git(".", &["worktree", "prune"]).await?;
+    Ok(())
+}
+
+fn copy_dir(src: &Utf8Path, dest: &Utf8Path) -> Result<()> {
+    std_fs::create_dir_all(dest)?;
+    for entry in src.read_dir_utf8()? {
+        let entry = entry?;
+        let path = dest.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(entry.path(), &path)?;
+        } else {
+            std_fs::copy(entry.path(), &path)?;
+        }
+    }
+    Ok(())
+}
+
+// Executes git in the folder and returns its stdout.
+async fn git(dir: &str, args: &[&str]) -> Result<String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .output()
+        .await?;
+    if !output.status.success() {
+        bail!(
+            "error executing git {}: {}",
+            args[0],
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
@@ -0,0 +1,355 @@
+use crate::config::{parse_config, Config, ContainerBackend};
+use crate::docker::{backend_bin, check_push_auth, registry_host};
+use crate::failure::Failure;
+use crate::git::GitRepo;
+use crate::preflight::{check_publish_from, check_requirement, check_shell_wrapper};
+use crate::utils::find_binary;
+use eyre::{eyre, Result};
+use std::env;
+use std::process::Command;
+use std::time::Duration;
+
+// Timeout of the requests checking that the providers are reachable.
+const NETWORK_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Outcome of one check. Warnings are shown but don't fail `rlsr doctor`.
+enum Status {
+    Ok,
+    Warn,
+    Fail,
+}
+
+struct Check {
+    name: String,
+    status: Status,
+    detail: String,
+}
+
+impl Check {
+    fn new(name: &str, status: Status, detail: impl Into<String>) -> Self {
+        Check {
+            name: name.to_string(),
+            status,
+            detail: detail.into(),
+        }
+    }
+}
+
+// Inspects the environment the releases in the config need: the config
+// itself, git and the repo, the github token, the container and oras tools,
+// the requirements and whether the providers are reachable. Prints a report
+// and fails if any check failed.
+pub async fn doctor(config: &str) -> Result<()> {
+    let mut checks = vec![];
+    let cfg = match parse_config(config).await {
+        Ok(cfg) => {
+            checks.push(Check::new(
+                "config",
+                Status::Ok,
+                format!("{} has {} release(s)", config, cfg.releases.len()),
+            ));
+            Some(cfg)
+        }
+        Err(err) => {
+            checks.push(Check::new("config", Status::Fail, format!("{:#}", err)));
+            None
+        }
+    };
+
+    checks.extend(git_checks(cfg.as_ref()));
+    if let Some(cfg) = &cfg {
+        checks.extend(github_checks(cfg).await);
+        checks.extend(tool_checks(cfg));
+        checks.extend(registry_checks(cfg).await);
+        if let Some(wrapper) = &cfg.shell_wrapper {
+            checks.push(match check_shell_wrapper(cfg) {
+                Some(err) => Check::new("shell_wrapper", Status::Fail, err),
+                None => Check::new("shell_wrapper", Status::Ok, wrapper),
+            });
+        }
+        for requirement in cfg.requirements.as_deref().unwrap_or_default() {
+            checks.push(match check_requirement(requirement) {
+                Ok(()) => Check::new(&requirement.tool, Status::Ok, "installed"),
+                Err(err) => Check::new(&requirement.tool, Status::Fail, err.to_string()),
+            });
+        }
+    }
+
+    let mut failed = 0;
+    println!("rlsr doctor:");
+    for check in &checks {
+        let status = match check.status {
+            Status::Ok => "ok",
+            Status::Warn => "warn",
+            Status::Fail => {
+                failed += 1;
+                "fail"
+            }
+        };
+        println!("  [{:<4}] {}: {}", status, check.name, check.detail);
+    }
+    if failed > 0 {
+        return Err(Failure::Config.wrap(eyre!("{} check(s) failed", failed)));
+    }
+    Ok(())
+}
+
+fn git_checks(cfg: Option<&Config>) -> Vec<Check> {
+    let mut checks = vec![];
+    match tool_version("git", &["--version"]) {
+        Some(version) => checks.push(Check::new("git", Status::Ok, version)),
+        None => {
+            checks.push(Check::new("git", Status::Fail, "isn't installed"));
+            return checks;
+        }
+    }
+
+    let git = match GitRepo::open_with(cfg.and_then(|c| c.git.as_ref())) {
+        Ok(git) => git,
+        Err(err) => {
+            checks.push(Check::new("repo", Status::Fail, err.to_string()));
+            return checks;
+        }
+    };
+    let head = git.head_ref().unwrap_or_else(|_| "unknown".to_string());
+    checks.push(match git.dirty_files() {
+        Ok(dirty) if dirty.is_empty() => {
+            Check::new("repo", Status::Ok, format!("clean, on {}", head))
+        }
+        Ok(dirty) => Check::new(
+            "repo",
+            Status::Warn,
+            format!("{} uncommitted file(s), on {}", dirty.len(), head),
+        ),
+        Err(err) => Check::new("repo", Status::Fail, err.to_string()),
+    });
+    if git.is_shallow() {
+        checks.push(Check::new(
+            "history",
+            Status::Warn,
+            "shallow clone, set `git.unshallow` or fetch the full history",
+        ));
+    }
+    checks.push(match git.latest_tag() {
+        Ok(tag) => Check::new("tags", Status::Ok, format!("latest tag is {}", tag)),
+        Err(_) => Check::new("tags", Status::Warn, "no release tag found"),
+    });
+    // Only a warning, since doctor is usually run outside the allowed
+    // branches and CI.
+    if let Some(publish_from) = cfg.and_then(|c| c.publish_from.as_ref()) {
+        checks.push(match check_publish_from(publish_from, &git) {
+            Ok(()) => Check::new("publish_from", Status::Ok, "publishing is allowed here"),
+            Err(err) => Check::new("publish_from", Status::Warn, err.to_string()),
+        });
+    }
+    checks
+}
+
+// Checks that GITHUB_TOKEN is set and valid with the rate limit API, which
+// doesn't count against the rate limit.
+async fn github_checks(cfg: &Config) -> Vec<Check> {
+    let needs_github = cfg.releases.iter().any(|r| {
+        r.targets.github.is_some()
+            || r.targets.versions_manifest.is_some()
+            || r.targets
+                .download_page
+                .as_ref()
+                .is_some_and(|p| p.pages.is_some())
+            || r.targets.helm.as_ref().is_some_and(|h| h.index.is_some())
+    });
+    if !needs_github {
+        return vec![];
+    }
+    let token = match env::var("GITHUB_TOKEN") {
+        Ok(token) if !token.is_empty() => token,
+        _ => return vec![Check::new("GITHUB_TOKEN", Status::Fail, "isn't set")],
+    };
+
+    let res = client()
+        .get("https://api.github.com/rate_limit")
+        .header("Authorization", format!("token {}", token))
+        .header("User-Agent", "rlsr")
+        .send()
+        .await;
+    let check = match res {
+        Ok(res) if res.status().is_success() => {
+            let body: serde_json::Value = res.json().await.unwrap_or_default();
+            let remaining = &body["resources"]["core"]["remaining"];
+            Check::new(
+                "GITHUB_TOKEN",
+                Status::Ok,
+                format!("valid, {} requests left", remaining),
+            )
+        }
+        Ok(res) if res.status() == reqwest::StatusCode::UNAUTHORIZED => {
+            Check::new("GITHUB_TOKEN", Status::Fail, "github rejected the token")
+        }
+        Ok(res) => Check::new(
+            "GITHUB_TOKEN",
+            Status::Fail,
+            format!("github responded with {}", res.status()),
+        ),
+        Err(err) => Check::new(
+            "GITHUB_TOKEN",
+            Status::Fail,
+            format!("couldn't reach api.github.com: {}", err),
+        ),
+    };
+    vec![check]
+}
+
+// Checks the container backends and the CLIs the targets use. Docker
+// builds use buildx for the image metadata.
+fn tool_checks(cfg: &Config) -> Vec<Check> {
+    let mut checks = vec![];
+    let mut backends = vec![];
+    for release in &cfg.releases {
+        if let Some(docker) = &release.targets.docker {
+            let backend = docker.backend.clone().unwrap_or(ContainerBackend::Docker);
+            if !backends.contains(&backend) {
+                backends.push(backend);
+            }
+        }
+        // Builds with a container run in docker.
+        let containers = release.builds.iter().any(|b| b.container.is_some());
+        if containers && !backends.contains(&ContainerBackend::Docker) {
+            backends.push(ContainerBackend::Docker);
+        }
+    }
+    for backend in backends {
+        let bin = backend_bin(&backend);
+        let check = match backend {
+            ContainerBackend::Kaniko if find_binary(bin) => {
+                Check::new(bin, Status::Ok, "installed")
+            }
+            ContainerBackend::Kaniko => Check::new(bin, Status::Fail, "isn't installed"),
+            _ => match tool_version(bin, &["version", "--format", "{{.Server.Version}}"]) {
+                Some(version) => Check::new(bin, Status::Ok, format!("server {}", version)),
+                None if find_binary(bin) => Check::new(
+                    bin,
+                    Status::Fail,
+                    "installed, but the daemon isn't reachable",
+                ),
+                None => Check::new(bin, Status::Fail, "isn't installed"),
+            },
+        };
+        checks.push(check);
+        if backend == ContainerBackend::Docker {
+            checks.push(match tool_version("docker", &["buildx", "version"]) {
+                Some(version) => Check::new("buildx", Status::Ok, version),
+                None => Check::new(
+                    "buildx",
+                    Status::Warn,
+                    "isn't installed, images are built with the legacy builder and their digests are read after pushing",
+                ),
+            });
+        }
+    }
+
+    if cfg.releases.iter().any(|r| r.targets.helm.is_some()) {
+        checks.push(match tool_version("helm", &["version", "--short"]) {
+            Some(version) => Check::new("helm", Status::Ok, version),
+            None => Check::new("helm", Status::Fail, "isn't installed"),
+        });
+    }
+    for release in &cfg.releases {
+        let vsce = match &release.targets.vsce {
+            Some(vsce) => vsce,
+            None => continue,
+        };
+        for (cli, enabled) in [("vsce", vsce.marketplace), ("ovsx", vsce.open_vsx)] {
+            if enabled.unwrap_or(true) && !checks.iter().any(|c: &Check| c.name == cli) {
+                checks.push(match tool_version(cli, &["--version"]) {
+                    Some(version) => Check::new(cli, Status::Ok, version),
+                    None => Check::new(cli, Status::Fail, "isn't installed"),
+                });
+            }
+        }
+    }
+    let azure_feed = cfg.releases.iter().any(|r| {
+        r.targets
+            .azure_devops
+            .as_ref()
+            .is_some_and(|a| a.feed.is_some())
+    });
+    if azure_feed {
+        checks.push(match tool_version("az", &["--version"]) {
+            Some(version) => Check::new("az", Status::Ok, version),
+            None => Check::new("az", Status::Fail, "isn't installed"),
+        });
+    }
+    if cfg.releases.iter().any(|r| r.targets.oras.is_some()) {
+        checks.push(match tool_version("oras", &["version"]) {
+            Some(version) => Check::new("oras", Status::Ok, version),
+            None => Check::new("oras", Status::Fail, "isn't installed"),
+        });
+    }
+    checks
+}
+
+// Checks that the registries of the docker and oras targets respond. Any
+// response to `/v2/`, even unauthorized, means the registry is reachable.
+async fn registry_checks(cfg: &Config) -> Vec<Check> {
+    let mut registries = vec![];
+    for release in &cfg.releases {
+        if let Some(docker) = &release.targets.docker {
+            registries.push(registry_host(&docker.image));
+        }
+        if let Some(oras) = &release.targets.oras {
+            registries.push(registry_host(&oras.repository));
+        }
+    }
+    registries.sort();
+    registries.dedup();
+
+    let mut checks = vec![];
+    for registry in registries {
+        let url = format!("https://{}/v2/", registry);
+        checks.push(match client().get(&url).send().await {
+            Ok(res) => Check::new(
+                &registry,
+                Status::Ok,
+                format!("reachable, {}", res.status()),
+            ),
+            Err(err) => Check::new(&registry, Status::Fail, format!("unreachable: {}", err)),
+        });
+    }
+
+    // Oras reads docker's credentials.
+    for release in &cfg.releases {
+        let mut images = vec![];
+        if let Some(docker) = &release.targets.docker {
+            let backend = docker.backend.clone().unwrap_or(ContainerBackend::Docker);
+            images.push((docker.image.as_str(), backend));
+        }
+        if let Some(oras) = &release.targets.oras {
+            images.push((oras.repository.as_str(), ContainerBackend::Docker));
+        }
+        for (image, backend) in images {
+            let name = format!("{} credentials", registry_host(image));
+            checks.push(match check_push_auth(image, &backend) {
+                Ok(()) => Check::new(&name, Status::Ok, "found"),
+                Err(err) => Check::new(&name, Status::Fail, format!("{:#}", err)),
+            });
+        }
+    }
+    checks
+}
+
+// Gets the first line of the tool's output for the args, or `None` if it
+// isn't installed or fails.
+fn tool_version(tool: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(tool).args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let out = String::from_utf8_lossy(&output.stdout);
+    Some(out.lines().next().unwrap_or_default().trim().to_string())
+}
+
+fn client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .timeout(NETWORK_TIMEOUT)
+        .build()
+        .unwrap_or_default()
+}
@@ -0,0 +1,228 @@
+use crate::config::Release;
+use crate::get_github_token;
+use crate::github::commit_file;
+use crate::release_provider::{AssetNames, PublishResult, ReleaseProvider};
+use crate::templating::{tag_context, target_os_arch, Templates};
+use crate::utils::{format_size, sha256_file};
+use async_trait::async_trait;
+use camino::Utf8Path;
+use eyre::{bail, Context, Result};
+use log::info;
+use minijinja::{context, value::Serde};
+use serde::Serialize;
+use std::sync::Arc;
+use tokio::{fs, sync::Mutex};
+
+const DEFAULT_TEMPLATE: &str = r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+  <meta charset="utf-8">
+  <meta name="viewport" content="width=device-width, initial-scale=1">
+  <title>Download {{ release | e }} {{ version | e }}</title>
+  <style>
+    body { font-family: sans-serif; max-width: 60rem; margin: 2rem auto; padding: 0 1rem; }
+    table { border-collapse: collapse; width: 100%; margin-bottom: 1rem; }
+    th, td { text-align: left; padding: 0.4rem; border-bottom: 1px solid #ddd; }
+    code, pre { font-size: 0.85rem; }
+    pre { background: #f4f4f4; padding: 0.6rem; overflow-x: auto; }
+  </style>
+</head>
+<body>
+  <h1>{{ release | e }} {{ tag | e }}</h1>
+  {% for platform in platforms %}
+  <h2>{{ platform.label | e }}</h2>
+  <table>
+    <tr><th>File</th><th>Size</th><th>SHA256</th></tr>
+    {% for asset in platform.assets %}
+    <tr>
+      <td><a href="{{ asset.url }}">{{ asset.name | e }}</a></td>
+      <td>{{ asset.size }}</td>
+      <td><code>{{ asset.sha256 }}</code></td>
+    </tr>
+    {% endfor %}
+  </table>
+  {% for asset in platform.assets %}
+  <pre>{{ asset.install }}</pre>
+  {% endfor %}
+  {% endfor %}
+</body>
+</html>
+"#;
+
+/// DownloadPage renders a static HTML page listing the release's assets per
+/// platform with their checksums and install snippets. The page is written to
+/// a file, which custom targets or hooks can publish, and can be committed to
+/// a github repo, e.g. to the `gh-pages` branch.
+pub struct DownloadPage {
+    asset_names: AssetNames,
+    templates: Arc<Templates>,
+}
+
+// Asset as it's listed on the page.
+#[derive(Serialize)]
+struct PageAsset {
+    name: String,
+    url: String,
+    sha256: String,
+    size: String,
+    bytes: u64,
+    install: String,
+}
+
+// Assets of one os and arch. Assets of builds without a target are in the
+// `other` platform.
+#[derive(Serialize)]
+struct Platform {
+    os: String,
+    arch: String,
+    target: String,
+    label: String,
+    assets: Vec<PageAsset>,
+}
+
+impl DownloadPage {
+    pub fn new(asset_names: AssetNames, templates: Arc<Templates>) -> Self {
+        DownloadPage {
+            asset_names,
+            templates,
+        }
+    }
+
+    // Groups the archives by the os and arch of their build's targets.
+    async fn platforms(&self, archives: &[String], base_url: &str) -> Result<Vec<Platform>> {
+        let mut platforms: Vec<Platform> = vec![];
+        for archive in archives {
+            let name = self.asset_names.get(archive);
+            let url = format!("{}/{}", base_url.trim_end_matches('/'), name);
+            let bytes = fs::metadata(archive).await?.len();
+            let target = self.asset_names.target(archive).unwrap_or_default();
+            let (os, arch) = target_os_arch(target);
+            let asset = PageAsset {
+                install: install_snippet(&os, &name, &url),
+                sha256: sha256_file(archive.clone()).await?,
+                size: format_size(bytes),
+                name,
+                url,
+                bytes,
+            };
+
+            match platforms.iter_mut().find(|p| p.os == os && p.arch == arch) {
+                Some(platform) => platform.assets.push(asset),
+                None => platforms.push(Platform {
+                    label: if target.is_empty() {
+                        "other".to_string()
+                    } else {
+                        format!("{} {}", os, arch)
+                    },
+                    target: target.to_string(),
+                    os,
+                    arch,
+                    assets: vec![asset],
+                }),
+            }
+        }
+        // Assets without a target are listed last.
+        platforms.sort_by(|a, b| {
+            (a.target.is_empty(), &a.os, &a.arch).cmp(&(b.target.is_empty(), &b.os, &b.arch))
+        });
+        Ok(platforms)
+    }
+}
+
+#[allow(clippy::needless_arbitrary_self_type)]
+#[async_trait]
+impl ReleaseProvider for DownloadPage {
+    fn name(self: &Self) -> String {
+        "download_page".to_string()
+    }
+
+    async fn publish(
+        self: &Self,
+        release: &Release,
+        all_archives: Arc<Mutex<Vec<String>>>,
+        latest_tag: String,
+        changelog: String,
+    ) -> Result<PublishResult> {
+        let page = match &release.targets.download_page {
+            Some(page) => page,
+            None => bail!("download page config can't be empty"),
+        };
+        let base_url = match (&page.base_url, &release.targets.github) {
+            (Some(base_url), _) => self.templates.render(base_url, tag_context(&latest_tag))?,
+            (None, Some(gh)) => format!(
+                "https://github.com/{}/{}/releases/download/{}",
+                gh.owner, gh.repo, latest_tag
+            ),
+            (None, None) => bail!("download page needs a base_url without the github target"),
+        };
+
+        let archives = all_archives.lock().await.to_vec();
+        let platforms = self.platforms(&archives, &base_url).await?;
+        let assets: Vec<&PageAsset> = platforms.iter().flat_map(|p| &p.assets).collect();
+
+        let template = match &page.template {
+            Some(path) => fs::read_to_string(path)
+                .await
+                .wrap_err_with(|| format!("error reading download page template {}", path))?,
+            None => DEFAULT_TEMPLATE.to_string(),
+        };
+        let ctx = context! {
+            release => &release.name,
+            changelog => changelog,
+            base_url => base_url,
+            platforms => Serde(&platforms),
+            assets => Serde(&assets),
+            ..tag_context(&latest_tag)
+        };
+        let html = self
+            .templates
+            .render(&template, ctx)
+            .wrap_err_with(|| "error rendering download page")?;
+
+        let output = page
+            .output
+            .clone()
+            .unwrap_or_else(|| format!("{}/download.html", release.dist_folder));
+        if let Some(dir) = Utf8Path::new(&output).parent() {
+            if !dir.as_str().is_empty() {
+                fs::create_dir_all(dir).await?;
+            }
+        }
+        fs::write(&output, &html)
+            .await
+            .wrap_err_with(|| format!("error writing download page {}", output))?;
+        info!("wrote download page to {}", output);
+
+        let mut result = PublishResult::new("download_page");
+        result.release_url = Some(output);
+        if let Some(pages) = &page.pages {
+            let message = format!("Update download page for {}", latest_tag);
+            let url = commit_file(pages, &get_github_token()?, html.as_bytes(), &message).await?;
+            info!("committed download page to {}", url);
+            result.release_url = Some(url);
+        }
+        Ok(result)
+    }
+}
+
+// Gets the commands downloading and extracting the asset, with powershell for
+// windows.
+fn install_snippet(os: &str, name: &str, url: &str) -> String {
+    if os == "windows" {
+        let mut snippet = format!("Invoke-WebRequest -Uri {} -OutFile {}", url, name);
+        if name.ends_with(".zip") {
+            snippet.push_str(&format!("\nExpand-Archive {}", name));
+        }
+        return snippet;
+    }
+    let extract = if name.ends_with(".zip") {
+        format!("unzip {}", name)
+    } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+        format!("tar xzf {}", name)
+    } else if name.ends_with(".tar.zst") {
+        format!("tar --zstd -xf {}", name)
+    } else {
+        format!("chmod +x {}", name)
+    };
+    format!("curl -LO {}\n{}", url, extract)
+}
@@ -0,0 +1,70 @@
+use crate::release_provider::PublishResult;
+use std::sync::Arc;
+use tokio::sync::mpsc::UnboundedSender;
+
+/// Event is emitted by the pipeline as the release progresses, so that
+/// progress UIs or metrics exporters can follow along.
+#[derive(Clone, Debug)]
+pub enum Event {
+    TestFinished {
+        release: String,
+        test: String,
+        success: bool,
+    },
+    BuildStarted {
+        release: String,
+        build: String,
+    },
+    BuildFinished {
+        release: String,
+        build: String,
+        success: bool,
+    },
+    ArtifactArchived {
+        release: String,
+        path: String,
+    },
+    AssetUploaded {
+        release: String,
+        provider: String,
+        url: String,
+    },
+    ReleasePublished {
+        release: String,
+        tag: String,
+        result: PublishResult,
+    },
+}
+
+/// EventHandler gets all the events of a pipeline. Handlers are called from
+/// the build tasks too, so they shouldn't block.
+pub trait EventHandler: Send + Sync {
+    fn on_event(&self, event: &Event);
+}
+
+// Sends the events on a channel, for consumers which prefer receiving them
+// in their own task.
+impl EventHandler for UnboundedSender<Event> {
+    fn on_event(&self, event: &Event) {
+        // A dropped receiver just means nobody is listening anymore.
+        let _ = self.send(event.clone());
+    }
+}
+
+// Handlers subscribed to a pipeline.
+#[derive(Clone, Default)]
+pub struct Events {
+    handlers: Vec<Arc<dyn EventHandler>>,
+}
+
+impl Events {
+    pub fn subscribe(&mut self, handler: Arc<dyn EventHandler>) {
+        self.handlers.push(handler);
+    }
+
+    pub fn emit(&self, event: Event) {
+        for handler in &self.handlers {
+            handler.on_event(&event);
+        }
+    }
+}
@@ -0,0 +1,73 @@
+use eyre::Report;
+use std::fmt;
+
+/// Failure is the class of a failed run, so that CI pipelines can branch on
+/// rlsr's exit code. Unclassified errors exit with 1.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Failure {
+    // The config is invalid or the environment isn't ready, e.g. failed
+    // preflight checks.
+    Config,
+    // The build stage failed.
+    Build,
+    // Publishing failed before any target published the release, or a
+    // target failed in a way which aborts publishing.
+    Publish,
+    // The rebuilt archives aren't the published ones, or uploaded assets
+    // don't match the files.
+    Verification,
+    // The releases were published, but some targets failed.
+    Partial,
+}
+
+impl Failure {
+    pub fn exit_code(self) -> i32 {
+        match self {
+            Failure::Config => 2,
+            Failure::Build => 3,
+            Failure::Publish => 4,
+            Failure::Verification => 5,
+            Failure::Partial => 6,
+        }
+    }
+
+    // Gets the class of the error, if it's classified.
+    pub(crate) fn of(err: &Report) -> Option<Failure> {
+        err.downcast_ref::<Failed>().map(|failed| failed.failure)
+    }
+
+    // Classifies the error, unless it's already classified.
+    pub(crate) fn wrap(self, err: Report) -> Report {
+        if err.downcast_ref::<Failed>().is_some() {
+            return err;
+        }
+        Report::new(Failed {
+            failure: self,
+            source: err,
+        })
+    }
+}
+
+// Error with its failure class. It's shown as the error it wraps.
+#[derive(Debug)]
+struct Failed {
+    failure: Failure,
+    source: Report,
+}
+
+impl fmt::Display for Failed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+impl std::error::Error for Failed {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.source.source()
+    }
+}
+
+// Gets the exit code for the error returned by a run.
+pub fn exit_code(err: &Report) -> i32 {
+    Failure::of(err).map_or(1, Failure::exit_code)
+}
@@ -0,0 +1,177 @@
+use crate::config::{Gates, Release};
+use crate::get_github_token;
+use crate::git::GitRepo;
+use crate::hooks::run_hooks;
+use crate::templating::{tag_context, Templates};
+use eyre::{bail, Result};
+use log::info;
+use serde::Deserialize;
+use std::time::{Duration, Instant};
+
+// State of a gate when it's checked.
+enum Gate {
+    Passed,
+    Pending(String),
+    Failed(String),
+}
+
+#[derive(Deserialize)]
+struct CheckRuns {
+    check_runs: Vec<CheckRun>,
+}
+
+#[derive(Deserialize)]
+struct CheckRun {
+    name: String,
+    status: String,
+    conclusion: Option<String>,
+}
+
+// Waits until all the release's gates pass, checking the pending ones every
+// interval. Fails as soon as a gate fails, or when the gates are still
+// pending at the timeout.
+pub async fn wait(
+    release: &Release,
+    gates: &Gates,
+    templates: &Templates,
+    git: &GitRepo,
+    tag: &str,
+) -> Result<()> {
+    let timeout = Duration::from_secs(gates.timeout_minutes.unwrap_or(60) * 60);
+    let interval = Duration::from_secs(gates.interval_seconds.unwrap_or(30));
+    let start = Instant::now();
+    loop {
+        let mut pending = vec![];
+        if gates.checks.is_some() {
+            match checks_gate(release, gates, git, tag).await? {
+                Gate::Passed => {}
+                Gate::Pending(reason) => pending.push(reason),
+                Gate::Failed(reason) => bail!("gate failed: {}", reason),
+            }
+        }
+        if let Some(url) = &gates.approval_url {
+            let url = templates.render(url, tag_context(tag))?;
+            match approval_gate(&url).await? {
+                Gate::Passed => {}
+                Gate::Pending(reason) => pending.push(reason),
+                Gate::Failed(reason) => bail!("gate failed: {}", reason),
+            }
+        }
+        for cmd in gates.commands.iter().flatten() {
+            let res = run_hooks(
+                "gate",
+                std::slice::from_ref(cmd),
+                templates,
+                tag_context(tag),
+                &[],
+            )
+            .await;
+            if let Err(err) = res {
+                pending.push(err.to_string());
+            }
+        }
+
+        if pending.is_empty() {
+            info!("all the gates of {} passed", release.name);
+            return Ok(());
+        }
+        if start.elapsed() >= timeout {
+            bail!(
+                "gates of {} didn't pass in {} minutes:\n  {}",
+                release.name,
+                timeout.as_secs() / 60,
+                pending.join("\n  ")
+            );
+        }
+        info!("waiting for gates: {}", pending.join(", "));
+        tokio::time::sleep(interval).await;
+    }
+}
+
+// Checks the github check runs of the tagged commit. The gate passes when the
+// required checks, or all of them, completed successfully.
+async fn checks_gate(release: &Release, gates: &Gates, git: &GitRepo, tag: &str) -> Result<Gate> {
+    let (owner, repo) = match &release.targets.github {
+        Some(gh) => (gh.owner.clone(), gh.repo.clone()),
+        None => git.remote_repo()?,
+    };
+    let sha = git.tag_commit(tag)?;
+    let url = format!(
+        "https://api.github.com/repos/{}/{}/commits/{}/check-runs?per_page=100",
+        owner, repo, sha
+    );
+    let mut req = reqwest::Client::new()
+        .get(&url)
+        .header("User-Agent", "rlsr")
+        .header("Accept", "application/vnd.github.v3+json");
+    let ghtoken = get_github_token()?;
+    if !ghtoken.is_empty() {
+        req = req.header("Authorization", format!("token {}", ghtoken));
+    }
+    let res = req.send().await?;
+    if !res.status().is_success() {
+        bail!(
+            "error getting the checks of {}, status: {}",
+            sha,
+            res.status()
+        );
+    }
+    let runs: CheckRuns = res.json().await?;
+
+    let required = gates.checks.clone().unwrap_or_default();
+    let runs: Vec<&CheckRun> = runs
+        .check_runs
+        .iter()
+        .filter(|r| required.is_empty() || required.contains(&r.name))
+        .collect();
+    if let Some(name) = required
+        .iter()
+        .find(|n| !runs.iter().any(|r| &r.name == *n))
+    {
+        return Ok(Gate::Pending(format!("check {} hasn't started", name)));
+    }
+    if runs.is_empty() {
+        return Ok(Gate::Pending(format!("no checks for {} yet", sha)));
+    }
+    for run in &runs {
+        match run.conclusion.as_deref() {
+            _ if run.status != "completed" => {
+                return Ok(Gate::Pending(format!(
+                    "check {} is {}",
+                    run.name, run.status
+                )))
+            }
+            Some("success" | "neutral" | "skipped") => {}
+            conclusion => {
+                return Ok(Gate::Failed(format!(
+                    "check {} concluded {}",
+                    run.name,
+                    conclusion.unwrap_or("without a conclusion")
+                )))
+            }
+        }
+    }
+    Ok(Gate::Passed)
+}
+
+// Checks the approval url. A success status approves the release, except for
+// 202 which means it's pending, 403 and 410 reject it and other statuses
+// mean it's pending.
+async fn approval_gate(url: &str) -> Result<Gate> {
+    let res = match reqwest::Client::new().get(url).send().await {
+        Ok(res) => res,
+        Err(err) => {
+            return Ok(Gate::Pending(format!(
+                "approval url isn't reachable: {}",
+                err
+            )))
+        }
+    };
+    let status = res.status();
+    Ok(match status.as_u16() {
+        202 => Gate::Pending("approval is pending".to_string()),
+        200..=299 => Gate::Passed,
+        403 | 410 => Gate::Failed(format!("approval was rejected with {}", status)),
+        _ => Gate::Pending(format!("approval url responded with {}", status)),
+    })
+}
@@ -0,0 +1,528 @@
+use crate::changelog::{Commit, TagInfo};
+use crate::config::{Git, TagSort};
+use chrono::{DateTime, FixedOffset};
+use eyre::{bail, Context, Result};
+use git2::{BranchType, DiffOptions, Oid, Repository, Sort, StatusOptions, Time};
+use log::debug;
+use regex::Regex;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// GitRepo has all the git operations used by rlsr, backed by libgit2 so that
+/// they don't depend on the `git` binary in PATH or its locale. Operations
+/// which need the network (fetching, pushing) still use the git CLI since it
+/// has the user's credentials configured.
+pub struct GitRepo {
+    repo: Repository,
+    // Only tags matching this are considered as release tags.
+    tag_pattern: Option<Regex>,
+    tag_sort: TagSort,
+    // Only commits changing these paths are in logs and ranges.
+    paths: Vec<String>,
+    // Caches the parsed commits of ranges in the git dir.
+    cache: bool,
+}
+
+impl GitRepo {
+    // Opens the repo which the current directory is in.
+    pub fn open() -> Result<Self> {
+        Self::open_with(None)
+    }
+
+    // Opens the repo which the current directory is in, with the tag
+    // filtering and sorting from the config.
+    pub fn open_with(cfg: Option<&Git>) -> Result<Self> {
+        Self::open_scoped(cfg, None, &[])
+    }
+
+    // Opens the repo for one release of a monorepo, with its own tag pattern
+    // instead of the config's and only the commits changing its paths.
+    pub fn open_scoped(
+        cfg: Option<&Git>,
+        tag_pattern: Option<&String>,
+        paths: &[String],
+    ) -> Result<Self> {
+        let repo = Repository::discover(".").wrap_err_with(|| "error opening git repo")?;
+        let tag_pattern = match tag_pattern.or(cfg.and_then(|c| c.tag_pattern.as_ref())) {
+            Some(pattern) => Some(
+                Regex::new(pattern)
+                    .wrap_err_with(|| format!("invalid tag pattern: {}", pattern))?,
+            ),
+            None => None,
+        };
+        let tag_sort = cfg
+            .and_then(|c| c.tag_sort.clone())
+            .unwrap_or(TagSort::Time);
+        Ok(GitRepo {
+            repo,
+            tag_pattern,
+            tag_sort,
+            paths: paths.to_vec(),
+            cache: false,
+        })
+    }
+
+    // Caches the parsed commits of the ranges, so that repeated runs (e.g. a
+    // dry run and then the real one) don't walk the history again.
+    pub fn with_cache(mut self, cache: bool) -> Self {
+        self.cache = cache;
+        self
+    }
+
+    // Gets the latest tag reachable from HEAD, annotated or lightweight, from
+    // the same tags as `previous_tag` so that the release and its changelog
+    // range agree.
+    pub fn latest_tag(&self) -> Result<String> {
+        let head = self.repo.head()?.peel_to_commit()?.id();
+        match self.nearest_tag(head, None)? {
+            Some(tag) => Ok(tag),
+            None if self.tag_pattern.is_some() => {
+                bail!("error getting latest tag: no tags match the tag pattern")
+            }
+            None => bail!("error getting latest tag: no tags found"),
+        }
+    }
+
+    // Gets all the tags in the repo which match the tag pattern.
+    pub fn all_tags(&self) -> Result<Vec<String>> {
+        let tags = self
+            .repo
+            .tag_names(None)
+            .wrap_err_with(|| "error getting all tags")?;
+        Ok(tags
+            .iter()
+            .flatten()
+            .flatten()
+            .filter(|tag| self.tag_matches(tag))
+            .map(String::from)
+            .collect())
+    }
+
+    // Gets the release tag before the given tag, if there's one. With semver
+    // sorting, it's the highest version lower than the tag. Otherwise it's the
+    // nearest tag in the tag's history which is on a different commit, so that
+    // multiple tags on the same commit don't end up in an empty range.
+    pub fn previous_tag(&self, tag: &str) -> Result<Option<String>> {
+        if self.tag_sort == TagSort::Semver {
+            let current = match tag_semver(tag) {
+                Some(current) => current,
+                None => bail!("tag {} isn't a semver version", tag),
+            };
+            return Ok(self
+                .all_tags()?
+                .into_iter()
+                .filter_map(|t| tag_semver(&t).map(|v| (v, t)))
+                .filter(|(v, _)| *v < current)
+                .max_by(|(a, _), (b, _)| a.cmp(b))
+                .map(|(_, t)| t));
+        }
+
+        let commit = self
+            .repo
+            .revparse_single(&format!("refs/tags/{}", tag))
+            .and_then(|obj| obj.peel_to_commit())
+            .wrap_err_with(|| format!("error finding commit for tag {}", tag))?
+            .id();
+        self.nearest_tag(commit, Some(commit))
+    }
+
+    // Gets the full hash of the commit the tag points to.
+    pub fn tag_commit(&self, tag: &str) -> Result<String> {
+        let commit = self
+            .repo
+            .revparse_single(&format!("refs/tags/{}", tag))
+            .and_then(|obj| obj.peel_to_commit())
+            .wrap_err_with(|| format!("error finding commit for tag {}", tag))?;
+        Ok(commit.id().to_string())
+    }
+
+    // Gets the message, tagger and date of an annotated tag. Lightweight tags
+    // don't have any of these, so they return `None`.
+    pub fn tag_info(&self, tag: &str) -> Result<Option<TagInfo>> {
+        let obj = self
+            .repo
+            .revparse_single(&format!("refs/tags/{}", tag))
+            .wrap_err_with(|| format!("error finding tag {}", tag))?;
+        let annotated = match obj.as_tag() {
+            Some(annotated) => annotated,
+            None => return Ok(None),
+        };
+
+        let tagger = annotated.tagger();
+        Ok(Some(TagInfo {
+            message: String::from_utf8_lossy(annotated.message_bytes().unwrap_or_default())
+                .trim()
+                .to_string(),
+            tagger: tagger
+                .as_ref()
+                .map(|t| String::from_utf8_lossy(t.name_bytes()).to_string()),
+            tagger_email: tagger
+                .as_ref()
+                .map(|t| String::from_utf8_lossy(t.email_bytes()).to_string()),
+            date: tagger.as_ref().and_then(|t| format_time(t.when())),
+        }))
+    }
+
+    // Gets the git log in the given range (all commits from HEAD if there's
+    // no range) with each commit formatted as `<short hash>: <message>`.
+    pub fn log(&self, range: Option<&str>) -> Result<String> {
+        let mut log = String::new();
+        for commit in self.walk(range)? {
+            let commit = self.repo.find_commit(commit)?;
+            let hash = commit.as_object().short_id()?;
+            log.push_str(&format!(
+                "{}: {}\n",
+                String::from_utf8_lossy(&hash),
+                String::from_utf8_lossy(commit.message_bytes())
+            ));
+        }
+        Ok(log)
+    }
+
+    // Gets the commits in the given range, or all the commits from HEAD if
+    // there's no range.
+    pub fn commits(&self, range: Option<&str>) -> Result<Vec<Commit>> {
+        let cache_file = match self.cache {
+            true => self.commits_cache_file(range).ok(),
+            false => None,
+        };
+        if let Some(file) = &cache_file {
+            let cached = fs::read(file)
+                .ok()
+                .and_then(|contents| serde_json::from_slice(&contents).ok());
+            if let Some(commits) = cached {
+                debug!("using cached commits of {}", range.unwrap_or("HEAD"));
+                return Ok(commits);
+            }
+        }
+
+        let mut commits = vec![];
+        for commit in self.walk(range)? {
+            let commit = self.repo.find_commit(commit)?;
+            let hash = commit.as_object().short_id()?;
+            let author = commit.author();
+            commits.push(Commit::new(
+                String::from_utf8_lossy(&hash).to_string(),
+                &String::from_utf8_lossy(commit.message_bytes()),
+                String::from_utf8_lossy(author.name_bytes()).to_string(),
+                String::from_utf8_lossy(author.email_bytes()).to_string(),
+                format_time(author.when()).unwrap_or_default(),
+                commit.parent_count() > 1,
+            ));
+        }
+        // Failing to write the cache only makes the next run slower.
+        if let Some(file) = cache_file {
+            let res = fs::create_dir_all(file.parent().unwrap_or(&file))
+                .and_then(|_| fs::write(&file, serde_json::to_vec(&commits)?));
+            if let Err(err) = res {
+                debug!("error caching commits to {}: {}", file.display(), err);
+            }
+        }
+        Ok(commits)
+    }
+
+    // Gets the cache file of the range's commits. It's keyed by the commits
+    // the range resolves to, so moved tags and new commits miss the cache,
+    // and by the paths and rlsr's version.
+    fn commits_cache_file(&self, range: Option<&str>) -> Result<PathBuf> {
+        let resolved = match range {
+            Some(range) => {
+                let spec = self.repo.revparse(range)?;
+                let id = |obj: Option<&git2::Object>| obj.map(|o| o.id().to_string());
+                format!(
+                    "{}..{}",
+                    id(spec.from()).unwrap_or_default(),
+                    id(spec.to()).unwrap_or_default()
+                )
+            }
+            None => self.repo.head()?.peel_to_commit()?.id().to_string(),
+        };
+        let key = format!(
+            "{}:{}:{}",
+            env!("CARGO_PKG_VERSION"),
+            resolved,
+            self.paths.join(",")
+        );
+        let hash = format!("{:x}", Sha256::digest(key.as_bytes()));
+        Ok(self
+            .cache_dir()
+            .join("commits")
+            .join(format!("{}.json", hash)))
+    }
+
+    // Checks if there are any commits in the range. When the repo is scoped
+    // to paths, only commits changing them count.
+    pub fn has_changes(&self, range: Option<&str>) -> Result<bool> {
+        Ok(!self.walk(range)?.is_empty())
+    }
+
+    // Gets the owner and repo from the `origin` remote's url. Handles both
+    // ssh (`git@github.com:owner/repo.git`) and https urls.
+    pub fn remote_repo(&self) -> Result<(String, String)> {
+        let remote = self
+            .repo
+            .find_remote("origin")
+            .wrap_err_with(|| "error getting origin remote")?;
+        let url = remote.url().unwrap_or_default();
+        let url = url.trim().trim_end_matches('/').trim_end_matches(".git");
+
+        let mut parts = url.rsplit(['/', ':']);
+        match (parts.next(), parts.next()) {
+            (Some(repo), Some(owner)) if !repo.is_empty() && !owner.is_empty() => {
+                Ok((owner.to_string(), repo.to_string()))
+            }
+            _ => bail!("couldn't parse owner/repo from remote url: {}", url),
+        }
+    }
+
+    // Hashes the paths as they're in the working tree: their trees in HEAD
+    // and the contents of their uncommitted changes. Changes in `exclude`
+    // (e.g. the dist folder) are left out. No paths hash the whole repo.
+    pub fn tree_hash(&self, paths: &[String], exclude: Option<&str>) -> Result<String> {
+        let tree = self.repo.head()?.peel_to_tree()?;
+        let mut hasher = Sha256::new();
+        let mut opts = StatusOptions::new();
+        opts.include_untracked(true).recurse_untracked_dirs(true);
+        if paths.is_empty() {
+            hasher.update(format!(".:{}\n", tree.id()));
+        }
+        for path in paths {
+            let path = path.trim_start_matches("./").trim_end_matches('/');
+            let id = match path {
+                "" | "." => Some(tree.id()),
+                _ => tree.get_path(Path::new(path)).ok().map(|entry| entry.id()),
+            };
+            hasher.update(format!("{}:{}\n", path, id.unwrap_or(Oid::ZERO_SHA1)));
+            opts.pathspec(path);
+        }
+
+        let statuses = self
+            .repo
+            .statuses(Some(&mut opts))
+            .wrap_err_with(|| "error getting repo status")?;
+        let workdir = self.repo.workdir().unwrap_or_else(|| self.repo.path());
+        for entry in statuses.iter() {
+            let path = String::from_utf8_lossy(entry.path_bytes()).to_string();
+            if exclude.is_some_and(|exclude| path.starts_with(exclude)) {
+                continue;
+            }
+            hasher.update(format!("{}\n", path));
+            // Deleted files only change the hash by their path.
+            if let Ok(contents) = fs::read(workdir.join(&path)) {
+                hasher.update(&contents);
+            }
+        }
+        Ok(format!("{:x}", hasher.finalize()))
+    }
+
+    // Gets the repo's working tree.
+    pub fn workdir(&self) -> Option<&Path> {
+        self.repo.workdir()
+    }
+
+    // Gets the folder rlsr caches things in, `.git/rlsr-cache`.
+    pub fn cache_dir(&self) -> PathBuf {
+        self.repo.path().join("rlsr-cache")
+    }
+
+    // Gets the files with uncommitted changes, including untracked files
+    // which aren't ignored.
+    pub fn dirty_files(&self) -> Result<Vec<String>> {
+        let mut opts = StatusOptions::new();
+        opts.include_untracked(true).include_ignored(false);
+        let statuses = self
+            .repo
+            .statuses(Some(&mut opts))
+            .wrap_err_with(|| "error getting repo status")?;
+        Ok(statuses
+            .iter()
+            .map(|entry| String::from_utf8_lossy(entry.path_bytes()).to_string())
+            .collect())
+    }
+
+    // Checks if HEAD is the commit the tag points to.
+    pub fn is_head_tagged(&self, tag: &str) -> Result<bool> {
+        let head = self.repo.head()?.peel_to_commit()?.id().to_string();
+        Ok(self.tag_commit(tag)? == head)
+    }
+
+    // Gets the tags in the range `<from>..<to>` in version order, including
+    // both ends. Tags which aren't semver versions are ignored.
+    pub fn tags_in_range(&self, range: &str) -> Result<Vec<String>> {
+        let (from, to) = match range.split_once("..") {
+            Some((from, to)) => (from, to),
+            None => bail!("tag range should be like v1.0.0..v1.5.0, got {}", range),
+        };
+        let (from, to) = match (tag_semver(from), tag_semver(to)) {
+            (Some(from), Some(to)) => (from, to),
+            _ => bail!("tags in range {} aren't semver versions", range),
+        };
+        let mut tags: Vec<_> = self
+            .all_tags()?
+            .into_iter()
+            .filter_map(|t| tag_semver(&t).map(|v| (v, t)))
+            .filter(|(v, _)| *v >= from && *v <= to)
+            .collect();
+        tags.sort();
+        Ok(tags.into_iter().map(|(_, t)| t).collect())
+    }
+
+    // Gets the branch HEAD is on, or the commit if HEAD is detached, so that
+    // it can be checked out again.
+    pub fn head_ref(&self) -> Result<String> {
+        let head = self.repo.head()?;
+        if head.is_branch() {
+            return Ok(String::from_utf8_lossy(head.shorthand_bytes()).to_string());
+        }
+        Ok(head.peel_to_commit()?.id().to_string())
+    }
+
+    // Gets the local and remote branches which have HEAD in their history,
+    // with the remote branches named without the remote, e.g. `main` for
+    // `origin/main`.
+    pub fn branches_with_head(&self) -> Result<Vec<String>> {
+        let head = self.repo.head()?.peel_to_commit()?.id();
+        let mut names = vec![];
+        for branch in self.repo.branches(None)? {
+            let (branch, kind) = branch?;
+            let (name, tip) = match (branch.name()?, branch.get().target()) {
+                (Some(name), Some(tip)) => (name, tip),
+                _ => continue,
+            };
+            if tip != head && !self.repo.graph_descendant_of(tip, head)? {
+                continue;
+            }
+            let name = match kind {
+                BranchType::Remote => name.split_once('/').map_or(name, |(_, name)| name),
+                BranchType::Local => name,
+            };
+            if name != "HEAD" {
+                names.push(name.to_string());
+            }
+        }
+        names.sort_unstable();
+        names.dedup();
+        Ok(names)
+    }
+
+    // Gets the short hash of HEAD.
+    pub fn head_short(&self) -> Result<String> {
+        let head = self.repo.head()?.peel_to_commit()?;
+        let hash = head.as_object().short_id()?;
+        Ok(String::from_utf8_lossy(&hash).to_string())
+    }
+
+    // Checks if the repo is a shallow clone.
+    pub fn is_shallow(&self) -> bool {
+        self.repo.is_shallow()
+    }
+
+    // Walks the history from the given commit and returns the tag of the
+    // first commit which has a release tag, ignoring the `skip` commit.
+    fn nearest_tag(&self, from: Oid, skip: Option<Oid>) -> Result<Option<String>> {
+        let tags = self.tags_by_commit()?;
+        let mut walk = self.repo.revwalk()?;
+        walk.set_sorting(Sort::TOPOLOGICAL | Sort::TIME)?;
+        walk.push(from)?;
+        for oid in walk {
+            let oid = oid?;
+            if Some(oid) == skip {
+                continue;
+            }
+            if let Some(names) = tags.get(&oid) {
+                // Prefer the highest version if a commit has multiple tags.
+                let mut names = names.clone();
+                names.sort_by(|a, b| tag_semver(a).cmp(&tag_semver(b)).then(a.cmp(b)));
+                return Ok(names.pop());
+            }
+        }
+        Ok(None)
+    }
+
+    // Gets the release tags grouped by the commit they point to.
+    fn tags_by_commit(&self) -> Result<HashMap<Oid, Vec<String>>> {
+        let mut tags: HashMap<Oid, Vec<String>> = HashMap::new();
+        for tag in self.all_tags()? {
+            let commit = self
+                .repo
+                .revparse_single(&format!("refs/tags/{}", tag))
+                .and_then(|obj| obj.peel_to_commit());
+            if let Ok(commit) = commit {
+                tags.entry(commit.id()).or_default().push(tag);
+            }
+        }
+        Ok(tags)
+    }
+
+    fn tag_matches(&self, tag: &str) -> bool {
+        match &self.tag_pattern {
+            Some(pattern) => pattern.is_match(tag),
+            None => true,
+        }
+    }
+
+    fn walk(&self, range: Option<&str>) -> Result<Vec<Oid>> {
+        let mut walk = self.repo.revwalk()?;
+        match range {
+            Some(range) if range.contains("..") => walk
+                .push_range(range)
+                .wrap_err_with(|| format!("error getting commits in range {}", range))?,
+            // A single revision is every commit up to it, e.g. the first tag.
+            Some(rev) => {
+                let commit = self
+                    .repo
+                    .revparse_single(rev)
+                    .and_then(|obj| obj.peel_to_commit())
+                    .wrap_err_with(|| format!("error getting commits up to {}", rev))?;
+                walk.push(commit.id())?;
+            }
+            None => walk.push_head()?,
+        }
+        let commits = walk.collect::<Result<Vec<_>, _>>()?;
+        if self.paths.is_empty() {
+            return Ok(commits);
+        }
+
+        let mut scoped = vec![];
+        for oid in commits {
+            if self.changes_paths(oid)? {
+                scoped.push(oid);
+            }
+        }
+        Ok(scoped)
+    }
+
+    // Checks if the commit changed any of the repo's paths, compared to its
+    // first parent.
+    fn changes_paths(&self, oid: Oid) -> Result<bool> {
+        let mut opts = DiffOptions::new();
+        for path in &self.paths {
+            opts.pathspec(path);
+        }
+        let commit = self.repo.find_commit(oid)?;
+        let parent = match commit.parent(0) {
+            Ok(parent) => Some(parent.tree()?),
+            Err(_) => None,
+        };
+        let diff =
+            self.repo
+                .diff_tree_to_tree(parent.as_ref(), Some(&commit.tree()?), Some(&mut opts))?;
+        Ok(diff.deltas().next().is_some())
+    }
+}
+
+// Parses the version in a tag, ignoring any prefix before the first digit
+// (e.g. `v` or `cli/v`).
+fn tag_semver(tag: &str) -> Option<semver::Version> {
+    let start = tag.find(|c: char| c.is_ascii_digit())?;
+    semver::Version::parse(&tag[start..]).ok()
+}
+
+// Formats a git time as RFC 3339 in its original timezone.
+fn format_time(time: Time) -> Option<String> {
+    let offset = FixedOffset::east_opt(time.offset_minutes() * 60)?;
+    let date = DateTime::from_timestamp(time.seconds(), 0)?.with_timezone(&offset);
+    Some(date.to_rfc3339())
+}
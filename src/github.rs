@@ -1,11 +1,19 @@
-use crate::config::Release;
-use crate::release_provider::ReleaseProvider;
-use crate::utils::{get_all_git_log, get_all_tags, get_changelog};
+use crate::config::{Github as GithubCfg, GithubComment, Release, RepoFile, Retention};
+use crate::failure::Failure;
+use crate::git::GitRepo;
+use crate::release_provider::{AssetNames, ProviderError, PublishResult, ReleaseProvider};
+use crate::templating::{tag_context, Templates};
+use crate::utils::{format_size, sha256_file};
 use async_trait::async_trait;
-use camino::Utf8Path;
-use eyre::{bail, Result};
-use log::{debug, error, info};
-use reqwest::{Body, Client};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::{Duration, Utc};
+use eyre::{bail, eyre, Context, Report, Result};
+use log::{debug, error, info, warn};
+use minijinja::context;
+use octocrab::Octocrab;
+use reqwest::{Body, Client, Url};
+use serde::Deserialize;
+use std::collections::HashMap;
 use std::sync::Arc;
 use tokio::fs;
 use tokio::sync::Mutex;
@@ -13,27 +21,204 @@ use tokio_util::codec::{BytesCodec, FramedRead};
 
 const MEDIA_TYPE: &str = "application/vnd.github.v3+json";
 
+// Content types of assets by extension. `infer` only detects binary formats
+// from their contents, so text files and scripts need these.
+const CONTENT_TYPES: &[(&str, &str)] = &[
+    ("txt", "text/plain"),
+    ("md", "text/markdown"),
+    ("sh", "text/x-shellscript"),
+    ("ps1", "text/plain"),
+    ("html", "text/html"),
+    ("json", "application/json"),
+    ("yml", "application/yaml"),
+    ("yaml", "application/yaml"),
+    ("asc", "application/pgp-signature"),
+    ("sig", "application/pgp-signature"),
+    ("pem", "application/x-pem-file"),
+];
+
+// Asset as returned by github after uploading. `digest` is only sent by
+// newer versions of the API.
+#[derive(Deserialize)]
+struct UploadedAsset {
+    url: String,
+    browser_download_url: String,
+    size: u64,
+    digest: Option<String>,
+}
+
+// Release which is being uploaded to with `delete_on_interrupt`.
+#[derive(Clone, PartialEq)]
+struct PartialRelease {
+    owner: String,
+    repo: String,
+    id: u64,
+    ghtoken: String,
+}
+
+static PARTIAL_RELEASES: std::sync::Mutex<Vec<PartialRelease>> = std::sync::Mutex::new(Vec::new());
+
 #[allow(clippy::needless_arbitrary_self_type)]
 #[async_trait]
 impl ReleaseProvider for Github {
+    fn name(self: &Self) -> String {
+        "github".to_string()
+    }
+
     async fn publish(
         self: &Self,
         release: &Release,
         all_archives: Arc<Mutex<Vec<String>>>,
         latest_tag: String,
-    ) -> Result<()> {
-        Self::publish_build(release, all_archives, self.ghtoken.clone(), latest_tag).await?;
-        Ok(())
+        changelog: String,
+    ) -> Result<PublishResult> {
+        let result = Self::publish_build(
+            release,
+            all_archives.clone(),
+            self.ghtoken.clone(),
+            latest_tag.clone(),
+            changelog,
+            self.prerelease,
+            &self.asset_names,
+        )
+        .await?;
+        let comment = release
+            .targets
+            .github
+            .as_ref()
+            .and_then(|g| g.comment.as_ref());
+        if let Some(comment) = comment {
+            let archives = all_archives.lock().await.to_vec();
+            if let Err(err) = self
+                .comment(release, comment, &latest_tag, &result, &archives)
+                .await
+            {
+                error!("error commenting on the release's pr: {}", err);
+            }
+        }
+        Ok(result)
     }
 }
 
+// Comment posted on the tag's pr without a template.
+const COMMENT_TEMPLATE: &str = "Released in [{{ tag }}]({{ release_url }}).
+
+| Asset | Size |
+| ----- | ---- |
+{% for asset in assets %}| [{{ asset.name }}]({{ asset.url }}) | {{ asset.size }} |
+{% endfor %}";
+
 pub struct Github {
     ghtoken: String,
+    prerelease: bool,
+    asset_names: AssetNames,
+    templates: Arc<Templates>,
 }
 
 impl Github {
-    pub fn new(ghtoken: String) -> Self {
-        Github { ghtoken }
+    pub fn new(
+        ghtoken: String,
+        prerelease: bool,
+        asset_names: AssetNames,
+        templates: Arc<Templates>,
+    ) -> Self {
+        Github {
+            ghtoken,
+            prerelease,
+            asset_names,
+            templates,
+        }
+    }
+
+    // Comments on the merged pr of the tagged commit with the release's
+    // link and assets, or on the commit if no pr has it.
+    async fn comment(
+        &self,
+        release: &Release,
+        cfg: &GithubComment,
+        tag: &str,
+        result: &PublishResult,
+        archives: &[String],
+    ) -> Result<()> {
+        let gh = match &release.targets.github {
+            Some(gh) => gh,
+            None => return Ok(()),
+        };
+        let mut assets = vec![];
+        for archive in archives {
+            let name = self.asset_names.get(archive);
+            let size = fs::metadata(archive).await.map(|m| m.len()).unwrap_or(0);
+            let url = result
+                .asset_urls
+                .iter()
+                .find(|u| u.ends_with(&format!("/{}", name)))
+                .cloned()
+                .unwrap_or_default();
+            assets.push(context! { name => name, url => url, size => format_size(size) });
+        }
+        let ctx = context! {
+            release_url => &result.release_url,
+            assets => assets,
+            ..tag_context(tag)
+        };
+        let template = cfg.template.as_deref().unwrap_or(COMMENT_TEMPLATE);
+        let body = self
+            .templates
+            .render(template, ctx)
+            .wrap_err_with(|| "error rendering the release comment")?;
+
+        let sha = GitRepo::open()?.tag_commit(tag)?;
+        let ghclient = Octocrab::builder()
+            .personal_token(self.ghtoken.clone())
+            .build()?;
+        let res = ghclient
+            ._get(
+                ghclient.absolute_url(format!(
+                    "/repos/{}/{}/commits/{}/pulls",
+                    gh.owner, gh.repo, sha
+                ))?,
+                None::<&()>,
+            )
+            .await?;
+        let prs: Vec<serde_json::Value> = match res.status().is_success() {
+            true => res.json().await?,
+            false => vec![],
+        };
+        let pr = prs
+            .iter()
+            .find(|pr| !pr["merged_at"].is_null())
+            .or_else(|| prs.first())
+            .and_then(|pr| pr["number"].as_u64());
+        let (route, on) = match pr {
+            Some(number) => (
+                format!("/repos/{}/{}/issues/{}/comments", gh.owner, gh.repo, number),
+                format!("pr #{}", number),
+            ),
+            None => (
+                format!("/repos/{}/{}/commits/{}/comments", gh.owner, gh.repo, sha),
+                format!("commit {}", sha),
+            ),
+        };
+        let res = ghclient
+            ._post(
+                ghclient.absolute_url(&route)?,
+                Some(&serde_json::json!({ "body": body })),
+            )
+            .await?;
+        let status = res.status();
+        if !status.is_success() {
+            return Err(ProviderError::from_status(
+                status,
+                format!(
+                    "error commenting on {}, status: {}, error: {}",
+                    on,
+                    status,
+                    res.text().await?
+                ),
+            ));
+        }
+        info!("commented on {} of {}/{}", on, gh.owner, gh.repo);
+        Ok(())
     }
 
     async fn publish_build(
@@ -41,7 +226,10 @@ impl Github {
         all_archives: Arc<Mutex<Vec<String>>>,
         ghtoken: String,
         latest_tag: String,
-    ) -> Result<()> {
+        changelog: String,
+        prerelease: bool,
+        asset_names: &AssetNames,
+    ) -> Result<PublishResult> {
         let gh = match &release.targets.github {
             Some(gh) => gh,
             None => {
@@ -61,21 +249,20 @@ impl Github {
 
         let ghclient = octocrab::instance();
 
-        // Get changelog.
-        let tags = get_all_tags().await?;
-        let changelog = if tags.len() == 1 {
-            get_all_git_log().await?
-        } else {
-            get_changelog().await?
-        };
-
-        let res = ghclient
-            .repos(&gh.owner, &gh.repo)
-            .releases()
+        let commitish = Self::target_commitish(&ghclient, gh, &latest_tag).await?;
+        let repos = ghclient.repos(&gh.owner, &gh.repo);
+        let releases = repos.releases();
+        let mut create = releases
             .create(&latest_tag)
             .body(&changelog)
+            .prerelease(prerelease);
+        if let Some(commitish) = &commitish {
+            create = create.target_commitish(commitish);
+        }
+        let res = create
             .send()
-            .await?;
+            .await
+            .map_err(|err| octocrab_error(err, "error creating github release"))?;
 
         let release_id = res.id.0;
         let github = release.targets.github.clone();
@@ -83,28 +270,147 @@ impl Github {
             Some(gh) => (gh.owner, gh.repo),
             None => bail!("couldn't find github details to publish release"),
         };
+        let partial = PartialRelease {
+            owner: owner.clone(),
+            repo: repo.clone(),
+            id: release_id,
+            ghtoken: ghtoken.clone(),
+        };
+        if gh.delete_on_interrupt.unwrap_or(false) {
+            PARTIAL_RELEASES.lock().unwrap().push(partial.clone());
+        }
         let ghtoken = ghtoken.clone();
         // Upload all archives.
-        Self::upload_archives(
+        let uploaded = Self::upload_archives(
             all_archives.lock().await.to_vec(),
+            asset_names,
+            gh.content_types.as_ref(),
             release_id,
             owner,
             repo,
             ghtoken,
         )
-        .await?;
+        .await;
+        PARTIAL_RELEASES.lock().unwrap().retain(|p| *p != partial);
+        let asset_urls = uploaded?;
 
         info!("release created");
+
+        if let Some(retention) = &gh.retention {
+            if let Err(err) =
+                Self::prune_releases(&ghclient, &gh.owner, &gh.repo, retention, &latest_tag).await
+            {
+                error!("error pruning old releases: {}", err);
+            }
+        }
+
+        let mut result = PublishResult::new("github");
+        result.release_url = Some(res.html_url.to_string());
+        result.release_id = Some(release_id.to_string());
+        result.asset_urls = asset_urls;
+        Ok(result)
+    }
+
+    // Gets the commitish github should create the tag from, if the tag
+    // doesn't exist in the github repo. Existing tags are used as they are.
+    async fn target_commitish(
+        ghclient: &Octocrab,
+        gh: &GithubCfg,
+        tag: &str,
+    ) -> Result<Option<String>> {
+        let url = ghclient.absolute_url(format!(
+            "/repos/{}/{}/git/ref/tags/{}",
+            gh.owner, gh.repo, tag
+        ))?;
+        let res = ghclient
+            ._get(url, None::<&()>)
+            .await
+            .map_err(|err| octocrab_error(err, "error checking tag on github"))?;
+        if res.status().is_success() {
+            return Ok(None);
+        }
+        if res.status() != reqwest::StatusCode::NOT_FOUND {
+            return Err(ProviderError::from_status(
+                res.status(),
+                format!(
+                    "error checking tag {} on github, status: {}",
+                    tag,
+                    res.status()
+                ),
+            ));
+        }
+
+        let commitish = match &gh.target_commitish {
+            Some(commitish) => commitish.clone(),
+            None => GitRepo::open()?.tag_commit(tag)?,
+        };
+        warn!(
+            "tag {} isn't in {}/{}, creating it from {}",
+            tag, gh.owner, gh.repo, commitish
+        );
+        Ok(Some(commitish))
+    }
+
+    // Deletes the prereleases and drafts which fall outside the given
+    // retention. The release which was just published is never deleted.
+    async fn prune_releases(
+        ghclient: &Octocrab,
+        owner: &str,
+        repo: &str,
+        retention: &Retention,
+        latest_tag: &str,
+    ) -> Result<()> {
+        let page = ghclient
+            .repos(owner, repo)
+            .releases()
+            .list()
+            .per_page(100u8)
+            .send()
+            .await?;
+        let mut releases: Vec<_> = ghclient
+            .all_pages(page)
+            .await?
+            .into_iter()
+            .filter(|r| (r.prerelease || r.draft) && r.tag_name != latest_tag)
+            .collect();
+        releases.sort_by_key(|r| std::cmp::Reverse(r.created_at));
+
+        let now = Utc::now();
+        for (idx, rel) in releases.iter().enumerate() {
+            let over_limit = retention.keep_last.is_some_and(|keep| idx >= keep);
+            let too_old = match (retention.max_age_days, rel.created_at) {
+                (Some(days), Some(created)) => now - created > Duration::days(days),
+                _ => false,
+            };
+            if !over_limit && !too_old {
+                continue;
+            }
+
+            info!("deleting old release: {}", rel.tag_name);
+            let url = ghclient
+                .absolute_url(format!("/repos/{}/{}/releases/{}", owner, repo, rel.id.0))?;
+            let res = ghclient._delete(url, None::<&()>).await?;
+            if res.status() != reqwest::StatusCode::NO_CONTENT {
+                warn!(
+                    "error deleting release {}, status: {}",
+                    rel.tag_name,
+                    res.status()
+                );
+            }
+        }
+
         Ok(())
     }
 
     async fn upload_archives(
         archives: Vec<String>,
+        asset_names: &AssetNames,
+        content_types: Option<&HashMap<String, String>>,
         release_id: u64,
         owner: String,
         repo: String,
         ghtoken: String,
-    ) -> Result<()> {
+    ) -> Result<Vec<String>> {
         let client = reqwest::Client::builder()
             .redirect(reqwest::redirect::Policy::limited(100))
             .build()?;
@@ -114,68 +420,433 @@ impl Github {
         let archives = Arc::new(archives);
         for i in 0..num {
             let archives = archives.clone();
-            let filename = String::from(Utf8Path::new(&archives[i]).file_name().unwrap());
-            let upload_url = format!(
-                "https://uploads.github.com/repos/{}/{}/releases/{}/assets?name={}",
-                owner, repo, release_id, filename
-            );
+            let filename = asset_names.get(&archives[i]);
+            let content_type = content_type(&archives[i], &filename, content_types)?;
+            // The name is encoded, since templated names can have spaces,
+            // `+`, `&` or `#`.
+            let upload_url = Url::parse_with_params(
+                &format!(
+                    "https://uploads.github.com/repos/{}/{}/releases/{}/assets",
+                    owner, repo, release_id
+                ),
+                &[("name", &filename)],
+            )?
+            .to_string();
             let ghclient = client.clone();
             let ghtoken = ghtoken.clone();
             let owner = owner.clone();
             all_uploads.push(tokio::spawn(async move {
                 debug!("uploading to url: {}", upload_url);
-                let res =
-                    Self::upload_file(upload_url, archives[i].clone(), ghclient, owner, ghtoken)
-                        .await;
-                if let Err(err) = res {
-                    error!("error uploading archive {}: {}", archives[i], err);
-                    std::process::exit(1);
-                }
+                Self::upload_file(
+                    upload_url,
+                    archives[i].clone(),
+                    content_type,
+                    ghclient,
+                    owner,
+                    ghtoken,
+                )
+                .await
             }));
         }
 
-        futures::future::join_all(all_uploads).await;
-        Ok(())
+        // All the uploads are waited for, so that every failed upload is
+        // reported and none is left running.
+        let mut urls = vec![];
+        let mut failed = vec![];
+        let mut errors = vec![];
+        let mut mismatched = false;
+        for (i, res) in futures::future::join_all(all_uploads)
+            .await
+            .into_iter()
+            .enumerate()
+        {
+            match res {
+                Ok(Ok(url)) => urls.push(url),
+                Ok(Err(err)) => {
+                    error!("error uploading archive {}: {}", archives[i], err);
+                    mismatched |= Failure::of(&err) == Some(Failure::Verification);
+                    failed.push(format!("  {}: {}", archives[i], err));
+                    errors.push(err);
+                }
+                Err(err) => {
+                    error!("upload of archive {} panicked: {}", archives[i], err);
+                    failed.push(format!("  {}: {}", archives[i], err));
+                    errors.push(eyre!(err));
+                }
+            }
+        }
+        if !failed.is_empty() {
+            // The combined error keeps the kind of the worst upload error, so
+            // that auth errors stop publishing and network errors are retried.
+            let err = ProviderError::combine(
+                &errors,
+                format!(
+                    "{} of {} archive(s) failed to upload:\n{}",
+                    failed.len(),
+                    num,
+                    failed.join("\n")
+                ),
+            );
+            // Uploads which don't match the files fail as a verification.
+            return Err(match mismatched {
+                true => Failure::Verification.wrap(err),
+                false => err,
+            });
+        }
+        Ok(urls)
     }
 
     async fn upload_file(
         url: String,
         filepath: String,
+        content_type: String,
         ghclient: Arc<Client>,
         owner: String,
         ghtoken: String,
-    ) -> Result<()> {
+    ) -> Result<String> {
         // Stat the file to get the size of the file.
         let meta = fs::metadata(&filepath).await?;
         let size = meta.len();
 
-        // Guess mime.
-        let mime_type = infer::get_from_path(&filepath)?.unwrap().mime_type();
-
         // Open file.
         let f = tokio::fs::File::open(&filepath).await?;
         let res = ghclient
             .post(url)
-            .basic_auth(owner, Some(ghtoken))
+            .basic_auth(&owner, Some(&ghtoken))
             .body(file_to_body(f))
             .header("Content-Length", size)
-            .header("Content-Type", mime_type)
+            .header("Content-Type", content_type)
             .header("Accept", MEDIA_TYPE)
             .send()
-            .await?;
-        if res.status() != reqwest::StatusCode::CREATED {
-            bail!(
-                "error uploading to github, status: {}, error: {}",
+            .await
+            .map_err(ProviderError::from)?;
+        let status = res.status();
+        if status != reqwest::StatusCode::CREATED {
+            return Err(ProviderError::from_status(
+                status,
+                format!(
+                    "error uploading to github, status: {}, error: {}",
+                    status,
+                    res.text().await?
+                ),
+            ));
+        }
+
+        let asset: UploadedAsset = res.json().await?;
+        Self::verify_asset(&asset.url, &filepath, size, &ghclient, owner, ghtoken).await?;
+        Ok(asset.browser_download_url)
+    }
+
+    // Fetches the uploaded asset's metadata again and checks that the size and
+    // the checksum (if github gives one) match the local file, so that a
+    // truncated upload fails the release.
+    async fn verify_asset(
+        url: &str,
+        filepath: &str,
+        size: u64,
+        ghclient: &Client,
+        owner: String,
+        ghtoken: String,
+    ) -> Result<()> {
+        let res = ghclient
+            .get(url)
+            .basic_auth(owner, Some(ghtoken))
+            .header("Accept", MEDIA_TYPE)
+            .send()
+            .await
+            .map_err(ProviderError::from)?;
+        if !res.status().is_success() {
+            return Err(ProviderError::from_status(
                 res.status(),
-                res.text().await?
-            );
+                format!(
+                    "error fetching uploaded asset {}, status: {}",
+                    url,
+                    res.status()
+                ),
+            ));
         }
+        let asset: UploadedAsset = res.json().await?;
 
+        if asset.size != size {
+            return Err(Failure::Verification.wrap(eyre!(
+                "size mismatch for uploaded asset {}: expected {}, got {}",
+                filepath,
+                size,
+                asset.size
+            )));
+        }
+
+        if let Some(digest) = asset.digest {
+            let sum = sha256_file(filepath.to_string()).await?;
+            if digest != format!("sha256:{}", sum) {
+                return Err(Failure::Verification.wrap(eyre!(
+                    "checksum mismatch for uploaded asset {}: expected sha256:{}, got {}",
+                    filepath,
+                    sum,
+                    digest
+                )));
+            }
+        }
+
+        debug!("verified uploaded asset: {}", filepath);
         Ok(())
     }
 }
 
+// Checks if the github repo already has a release for the tag.
+pub async fn release_exists(gh: &GithubCfg, ghtoken: &str, tag: &str) -> Result<bool> {
+    let ghclient = Octocrab::builder()
+        .personal_token(ghtoken.to_string())
+        .build()?;
+    let url = ghclient.absolute_url(format!(
+        "/repos/{}/{}/releases/tags/{}",
+        gh.owner, gh.repo, tag
+    ))?;
+    let res = ghclient._get(url, None::<&()>).await?;
+    match res.status() {
+        status if status.is_success() => Ok(true),
+        reqwest::StatusCode::NOT_FOUND => Ok(false),
+        status => bail!(
+            "error checking release for {} on github, status: {}",
+            tag,
+            status
+        ),
+    }
+}
+
+#[derive(Deserialize)]
+struct ReleaseAssets {
+    assets: Vec<ReleaseAsset>,
+}
+
+// Asset of a published release. `digest` is only sent by newer versions of
+// the API.
+#[derive(Deserialize)]
+pub struct ReleaseAsset {
+    pub name: String,
+    pub size: u64,
+    pub digest: Option<String>,
+    pub browser_download_url: String,
+}
+
+// Gets the assets of the github release for the tag, or `None` if there's no
+// release for it.
+pub async fn release_assets(
+    gh: &GithubCfg,
+    ghtoken: &str,
+    tag: &str,
+) -> Result<Option<Vec<ReleaseAsset>>> {
+    let ghclient = Octocrab::builder()
+        .personal_token(ghtoken.to_string())
+        .build()?;
+    let url = ghclient.absolute_url(format!(
+        "/repos/{}/{}/releases/tags/{}",
+        gh.owner, gh.repo, tag
+    ))?;
+    let res = ghclient._get(url, None::<&()>).await?;
+    match res.status() {
+        status if status.is_success() => {
+            let release: ReleaseAssets = res.json().await?;
+            Ok(Some(release.assets))
+        }
+        reqwest::StatusCode::NOT_FOUND => Ok(None),
+        status => bail!(
+            "error getting release for {} from github, status: {}",
+            tag,
+            status
+        ),
+    }
+}
+
+// Classifies octocrab's errors by github's message, as octocrab doesn't keep
+// the status of the response.
+fn octocrab_error(err: octocrab::Error, what: &str) -> Report {
+    match &err {
+        octocrab::Error::GitHub { source, .. } => {
+            let message = format!("{}: {}", what, source);
+            let lower = source.message.to_lowercase();
+            let exists = source
+                .errors
+                .iter()
+                .flatten()
+                .any(|e| e["code"] == "already_exists");
+            if lower.contains("rate limit") {
+                ProviderError::RateLimited(message).into()
+            } else if lower.contains("bad credentials")
+                || lower.contains("requires authentication")
+                || lower.contains("resource not accessible")
+            {
+                ProviderError::Auth(message).into()
+            } else if lower == "not found" {
+                ProviderError::NotFound(message).into()
+            } else if exists {
+                ProviderError::Conflict(message).into()
+            } else {
+                eyre!(message)
+            }
+        }
+        octocrab::Error::Http { source, .. } => {
+            ProviderError::Network(format!("{}: {}", what, source)).into()
+        }
+        _ => eyre!("{}: {}", what, err),
+    }
+}
+
 fn file_to_body(file: tokio::fs::File) -> Body {
     let stream = FramedRead::new(file, BytesCodec::new());
     Body::wrap_stream(stream)
 }
+
+// Gets the content type the asset is uploaded with: the configured one for
+// its name or longest matching extension, the built-in one for its
+// extension, the one guessed from its contents, or `application/octet-stream`.
+fn content_type(
+    path: &str,
+    name: &str,
+    overrides: Option<&HashMap<String, String>>,
+) -> Result<String> {
+    let matches_ext = |ext: &str| name.ends_with(&format!(".{}", ext.trim_start_matches('.')));
+    if let Some(overrides) = overrides {
+        if let Some(content_type) = overrides.get(name) {
+            return Ok(content_type.clone());
+        }
+        let ext = overrides
+            .keys()
+            .filter(|ext| matches_ext(ext))
+            .max_by_key(|ext| ext.trim_start_matches('.').len());
+        if let Some(ext) = ext {
+            return Ok(overrides[ext].clone());
+        }
+    }
+    if let Some((_, content_type)) = CONTENT_TYPES.iter().find(|(ext, _)| matches_ext(ext)) {
+        return Ok(content_type.to_string());
+    }
+    Ok(match infer::get_from_path(path)? {
+        Some(kind) => kind.mime_type().to_string(),
+        None => "application/octet-stream".to_string(),
+    })
+}
+
+// Gets the contents of the file in the github repo's branch, or `None` if it
+// doesn't exist.
+pub async fn fetch_file(file: &RepoFile, ghtoken: &str) -> Result<Option<Vec<u8>>> {
+    let ghclient = Octocrab::builder()
+        .personal_token(ghtoken.to_string())
+        .build()?;
+    let route = format!("/repos/{}/{}/contents/{}", file.owner, file.repo, file.path);
+    let res = ghclient
+        ._get(
+            ghclient.absolute_url(&route)?,
+            Some(&[("ref", &file.branch)]),
+        )
+        .await?;
+    if res.status() == reqwest::StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+    if !res.status().is_success() {
+        return Err(ProviderError::from_status(
+            res.status(),
+            format!("error fetching {}, status: {}", file.path, res.status()),
+        ));
+    }
+    let existing: serde_json::Value = res.json().await?;
+    let content = existing["content"]
+        .as_str()
+        .unwrap_or_default()
+        .replace('\n', "");
+    let content = STANDARD
+        .decode(content)
+        .map_err(|err| eyre!("error decoding {}: {}", file.path, err))?;
+    Ok(Some(content))
+}
+
+// Creates or updates the file in the github repo's branch with the contents
+// API and returns the file's url.
+pub async fn commit_file(
+    file: &RepoFile,
+    ghtoken: &str,
+    contents: &[u8],
+    message: &str,
+) -> Result<String> {
+    let ghclient = Octocrab::builder()
+        .personal_token(ghtoken.to_string())
+        .build()?;
+    let route = format!("/repos/{}/{}/contents/{}", file.owner, file.repo, file.path);
+
+    // The sha of the existing file is needed to update it.
+    let res = ghclient
+        ._get(
+            ghclient.absolute_url(&route)?,
+            Some(&[("ref", &file.branch)]),
+        )
+        .await?;
+    let sha = if res.status() == reqwest::StatusCode::NOT_FOUND {
+        None
+    } else if res.status().is_success() {
+        let existing: serde_json::Value = res.json().await?;
+        existing["sha"].as_str().map(String::from)
+    } else {
+        return Err(ProviderError::from_status(
+            res.status(),
+            format!("error fetching {}, status: {}", file.path, res.status()),
+        ));
+    };
+
+    let mut body = serde_json::json!({
+        "message": message,
+        "content": STANDARD.encode(contents),
+        "branch": file.branch,
+    });
+    if let Some(sha) = sha {
+        body["sha"] = serde_json::json!(sha);
+    }
+    let res = ghclient
+        ._put(ghclient.absolute_url(&route)?, Some(&body))
+        .await?;
+    let status = res.status();
+    if !status.is_success() {
+        return Err(ProviderError::from_status(
+            status,
+            format!(
+                "error committing {}, status: {}, error: {}",
+                file.path,
+                status,
+                res.text().await?
+            ),
+        ));
+    }
+    Ok(format!(
+        "https://github.com/{}/{}/blob/{}/{}",
+        file.owner, file.repo, file.branch, file.path
+    ))
+}
+
+// Deletes the releases whose assets were being uploaded when rlsr was
+// interrupted, for github targets with `delete_on_interrupt`.
+pub async fn delete_partial_releases() {
+    let partials = std::mem::take(&mut *PARTIAL_RELEASES.lock().unwrap());
+    for partial in partials {
+        warn!(
+            "deleting partial release {} in {}/{}",
+            partial.id, partial.owner, partial.repo
+        );
+        let res = async {
+            let ghclient = Octocrab::builder()
+                .personal_token(partial.ghtoken.clone())
+                .build()?;
+            let url = ghclient.absolute_url(format!(
+                "/repos/{}/{}/releases/{}",
+                partial.owner, partial.repo, partial.id
+            ))?;
+            let res = ghclient._delete(url, None::<&()>).await?;
+            if res.status() != reqwest::StatusCode::NO_CONTENT {
+                bail!("status: {}", res.status());
+            }
+            Ok(())
+        }
+        .await;
+        if let Err(err) = res {
+            error!("error deleting partial release {}: {}", partial.id, err);
+        }
+    }
+}
@@ -0,0 +1,218 @@
+use crate::config::{Helm as HelmCfg, Release};
+use crate::get_github_token;
+use crate::github::{commit_file, fetch_file};
+use crate::release_provider::{AssetNames, ProviderError, PublishResult, ReleaseProvider};
+use crate::templating::{tag_context, tag_version, Templates};
+use crate::utils::{group_command, spawn_tracked};
+use async_trait::async_trait;
+use camino::{Utf8Path, Utf8PathBuf};
+use eyre::{bail, Context, Result};
+use log::{debug, info};
+use std::process::Stdio;
+use std::sync::Arc;
+use tokio::{fs, sync::Mutex};
+
+/// Helm publishes the chart packaged by the build stage to an OCI registry
+/// with `helm push` and adds it to the `index.yaml` of a chart repo in a
+/// github repo. Registry credentials are the ones from `helm registry login`.
+pub struct Helm {
+    asset_names: AssetNames,
+    templates: Arc<Templates>,
+}
+
+impl Helm {
+    pub fn new(asset_names: AssetNames, templates: Arc<Templates>) -> Self {
+        Helm {
+            asset_names,
+            templates,
+        }
+    }
+
+    // Pushes the chart to the OCI registry and returns its reference and
+    // digest.
+    async fn push(
+        &self,
+        chart: &Utf8Path,
+        oci: &str,
+        name: &str,
+        version: &str,
+    ) -> Result<(String, Option<String>)> {
+        let reference = format!("{}/{}:{}", oci.trim_end_matches('/'), name, version);
+        info!("pushing chart {} to {}", chart, reference);
+        let output = helm(&["push", chart.as_str(), oci]).await?;
+        let digest = output
+            .lines()
+            .find_map(|line| line.trim().strip_prefix("Digest:"))
+            .map(|digest| {
+                let repository = reference.trim_start_matches("oci://");
+                let repository = repository.rsplit_once(':').map_or(repository, |r| r.0);
+                format!("{}@{}", repository, digest.trim())
+            });
+        Ok((reference, digest))
+    }
+
+    // Adds the chart to the chart repo's index with `helm repo index`,
+    // merging it into the existing index, and commits it.
+    async fn update_index(
+        &self,
+        release: &Release,
+        cfg: &HelmCfg,
+        chart: &Utf8Path,
+        tag: &str,
+    ) -> Result<String> {
+        let index = match &cfg.index {
+            Some(index) => index,
+            None => bail!("helm index can't be empty"),
+        };
+        let base_url = match (&cfg.base_url, &release.targets.github) {
+            (Some(base_url), _) => self.templates.render(base_url, tag_context(tag))?,
+            (None, Some(gh)) => format!(
+                "https://github.com/{}/{}/releases/download/{}",
+                gh.owner, gh.repo, tag
+            ),
+            (None, None) => bail!("helm index needs a base_url without the github target"),
+        };
+
+        // The chart is indexed alone in its own folder, with the name it's
+        // published with, so that the url in the index points to the asset.
+        let dir = Utf8Path::new(&release.dist_folder).join("helm-index");
+        if dir.exists() {
+            fs::remove_dir_all(&dir).await?;
+        }
+        fs::create_dir_all(&dir).await?;
+        fs::copy(chart, dir.join(self.asset_names.get(chart.as_str()))).await?;
+
+        let ghtoken = get_github_token()?;
+        let dir_index = dir.join("index.yaml");
+        let mut args = vec!["repo", "index", dir.as_str(), "--url", &base_url];
+        if let Some(current) = fetch_file(index, &ghtoken).await? {
+            fs::write(&dir_index, current).await?;
+            args.extend(["--merge", dir_index.as_str()]);
+        }
+        helm(&args).await?;
+
+        let contents = fs::read(&dir_index)
+            .await
+            .wrap_err_with(|| format!("error reading {}", dir_index))?;
+        let message = format!("Add {} {} to the chart index", release.name, tag);
+        commit_file(index, &ghtoken, &contents, &message).await
+    }
+}
+
+#[allow(clippy::needless_arbitrary_self_type)]
+#[async_trait]
+impl ReleaseProvider for Helm {
+    fn name(self: &Self) -> String {
+        "helm".to_string()
+    }
+
+    async fn publish(
+        self: &Self,
+        release: &Release,
+        all_archives: Arc<Mutex<Vec<String>>>,
+        latest_tag: String,
+        _changelog: String,
+    ) -> Result<PublishResult> {
+        let cfg = match &release.targets.helm {
+            Some(cfg) => cfg,
+            None => bail!("helm config can't be empty"),
+        };
+        let name = chart_name(cfg).await?;
+        let version = tag_version(&latest_tag);
+        let chart = chart_path(release, &name, version);
+        if !all_archives
+            .lock()
+            .await
+            .iter()
+            .any(|a| Utf8Path::new(a) == chart)
+        {
+            bail!("chart {} wasn't packaged", chart);
+        }
+
+        let mut result = PublishResult::new("helm");
+        if let Some(oci) = &cfg.oci {
+            let (reference, digest) = self.push(&chart, oci, &name, version).await?;
+            result.release_url = Some(reference);
+            result.image_digests.extend(digest);
+        }
+        if cfg.index.is_some() {
+            let url = self.update_index(release, cfg, &chart, &latest_tag).await?;
+            info!("added chart {} to {}", name, url);
+            result.release_url.get_or_insert(url);
+        }
+        Ok(result)
+    }
+}
+
+// Packages the chart into the dist folder, with the tag's version as the
+// chart's version and appVersion. Returns the path of the package, which is
+// published with the archives.
+pub async fn package(release: &Release, cfg: &HelmCfg, tag: &str) -> Result<String> {
+    let name = chart_name(cfg).await?;
+    let version = tag_version(tag);
+    fs::create_dir_all(&release.dist_folder).await?;
+    info!("packaging chart {} {}", name, version);
+    helm(&[
+        "package",
+        &cfg.chart,
+        "--version",
+        version,
+        "--app-version",
+        version,
+        "--destination",
+        &release.dist_folder,
+    ])
+    .await?;
+
+    let path = chart_path(release, &name, version);
+    if !path.exists() {
+        bail!("helm package didn't create {}", path);
+    }
+    Ok(path.to_string())
+}
+
+// Gets the chart's name from its `Chart.yaml`.
+async fn chart_name(cfg: &HelmCfg) -> Result<String> {
+    let path = Utf8Path::new(&cfg.chart).join("Chart.yaml");
+    let contents = fs::read_to_string(&path)
+        .await
+        .wrap_err_with(|| format!("error reading {}", path))?;
+    let chart: serde_yaml::Value =
+        serde_yaml::from_str(&contents).wrap_err_with(|| format!("error parsing {}", path))?;
+    match chart["name"].as_str() {
+        Some(name) => Ok(name.to_string()),
+        None => bail!("{} doesn't have a name", path),
+    }
+}
+
+// Gets the path `helm package` writes the chart to.
+fn chart_path(release: &Release, name: &str, version: &str) -> Utf8PathBuf {
+    Utf8Path::new(&release.dist_folder).join(format!("{}-{}.tgz", name, version))
+}
+
+// Executes helm with the args and returns its output.
+async fn helm(args: &[&str]) -> Result<String> {
+    debug!("executing helm {}", args.join(" "));
+    let (child, _group) = spawn_tracked(
+        group_command("helm")
+            .args(args)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped()),
+    )
+    .wrap_err_with(|| format!("error executing helm {}", args[0]))?;
+    let output = child
+        .wait_with_output()
+        .await
+        .wrap_err_with(|| format!("error executing helm {}", args[0]))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(ProviderError::from_output(
+            &stderr,
+            format!("error executing helm {}: {}", args[0], stderr.trim()),
+        ));
+    }
+    // `helm push` prints the digest on stderr.
+    let mut out = String::from_utf8_lossy(&output.stdout).to_string();
+    out.push_str(&String::from_utf8_lossy(&output.stderr));
+    Ok(out)
+}
@@ -0,0 +1,36 @@
+use crate::templating::Templates;
+use crate::utils::{spawn_tracked, wrapped_command};
+use eyre::{bail, Context, Result};
+use log::info;
+use minijinja::Value;
+
+// Runs the hook commands in order with `sh -c`, in the shell wrapper if set. Commands are templated with
+// the given context and get the env vars, so that they can use the tag or
+// the release url. The first failing command fails the hook.
+pub async fn run_hooks(
+    name: &str,
+    cmds: &[String],
+    templates: &Templates,
+    ctx: Value,
+    envs: &[(String, String)],
+) -> Result<()> {
+    for cmd in cmds {
+        let cmd = templates
+            .render(cmd, ctx.clone())
+            .wrap_err_with(|| format!("error rendering {} hook", name))?;
+        info!("executing {} hook: {}", name, cmd);
+        let (mut child, _group) = spawn_tracked(
+            wrapped_command(&["sh", "-c", &cmd])
+                .envs(envs.iter().map(|(k, v)| (k.as_str(), v.as_str()))),
+        )
+        .wrap_err_with(|| format!("error executing {} hook: {}", name, cmd))?;
+        let status = child
+            .wait()
+            .await
+            .wrap_err_with(|| format!("error executing {} hook: {}", name, cmd))?;
+        if !status.success() {
+            bail!("{} hook `{}` failed with {}", name, cmd, status);
+        }
+    }
+    Ok(())
+}
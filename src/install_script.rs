@@ -0,0 +1,238 @@
+use crate::config::{ArchiveFormat, InstallScript, Release};
+use crate::templating::{asset_name, tag_context, target_os_arch, Templates};
+use crate::utils::{archive_name, build_compression, sha256_file};
+use camino::Utf8Path;
+use eyre::{bail, Context, Result};
+use log::{info, warn};
+use minijinja::{context, value::Serde};
+use serde::Serialize;
+use tokio::fs;
+
+const SH_TEMPLATE: &str = r#"#!/bin/sh
+# Installs {{ bin }} {{ tag }}. The install dir can be changed with INSTALL_DIR.
+set -eu
+
+INSTALL_DIR="${INSTALL_DIR:-{{ install_dir }}}"
+BASE_URL="{{ base_url }}"
+
+os=$(uname -s | tr '[:upper:]' '[:lower:]')
+arch=$(uname -m)
+case "$arch" in
+  amd64) arch=x86_64 ;;
+  arm64) arch=aarch64 ;;
+esac
+
+case "$os/$arch" in
+{%- for asset in assets if asset.os != "windows" %}
+  {{ asset.os }}/{{ asset.arch }}) asset="{{ asset.name }}"; sha256="{{ asset.sha256 }}"; format="{{ asset.format }}" ;;
+{%- endfor %}
+  *) echo "{{ bin }} {{ tag }} isn't available for $os/$arch" >&2; exit 1 ;;
+esac
+
+tmp=$(mktemp -d)
+trap 'rm -rf "$tmp"' EXIT
+
+echo "downloading $asset"
+if command -v curl >/dev/null 2>&1; then
+  curl -fsSL -o "$tmp/$asset" "$BASE_URL/$asset"
+else
+  wget -q -O "$tmp/$asset" "$BASE_URL/$asset"
+fi
+
+if command -v sha256sum >/dev/null 2>&1; then
+  actual=$(sha256sum "$tmp/$asset" | cut -d ' ' -f 1)
+else
+  actual=$(shasum -a 256 "$tmp/$asset" | cut -d ' ' -f 1)
+fi
+if [ "$actual" != "$sha256" ]; then
+  echo "checksum of $asset doesn't match, expected $sha256, got $actual" >&2
+  exit 1
+fi
+
+if [ "$format" = "zip" ]; then
+  unzip -q -o "$tmp/$asset" -d "$tmp"
+elif [ "$format" = "tar" ]; then
+  tar -xf "$tmp/$asset" -C "$tmp"
+else
+  mv "$tmp/$asset" "$tmp/{{ bin }}"
+fi
+mkdir -p "$INSTALL_DIR"
+install -m 755 "$tmp/{{ bin }}" "$INSTALL_DIR/{{ bin }}"
+echo "installed {{ bin }} {{ tag }} to $INSTALL_DIR"
+"#;
+
+const PS1_TEMPLATE: &str = r#"# Installs {{ bin }} {{ tag }}. The install dir can be changed with INSTALL_DIR.
+$ErrorActionPreference = "Stop"
+
+$InstallDir = if ($env:INSTALL_DIR) { $env:INSTALL_DIR } else { "$env:LOCALAPPDATA\{{ bin }}\bin" }
+$BaseUrl = "{{ base_url }}"
+
+$arch = switch ($env:PROCESSOR_ARCHITECTURE) {
+  "AMD64" { "x86_64" }
+  "ARM64" { "aarch64" }
+  default { $env:PROCESSOR_ARCHITECTURE }
+}
+$assets = @{
+{%- for asset in assets if asset.os == "windows" %}
+  "{{ asset.arch }}" = @("{{ asset.name }}", "{{ asset.sha256 }}", "{{ asset.format }}")
+{%- endfor %}
+}
+if (-not $assets.ContainsKey($arch)) {
+  throw "{{ bin }} {{ tag }} isn't available for windows/$arch"
+}
+$asset, $sha256, $format = $assets[$arch]
+
+$tmp = Join-Path ([System.IO.Path]::GetTempPath()) ([System.Guid]::NewGuid())
+New-Item -ItemType Directory -Path $tmp | Out-Null
+try {
+  Write-Host "downloading $asset"
+  Invoke-WebRequest -Uri "$BaseUrl/$asset" -OutFile "$tmp\$asset" -UseBasicParsing
+  $actual = (Get-FileHash -Algorithm SHA256 "$tmp\$asset").Hash.ToLower()
+  if ($actual -ne $sha256) {
+    throw "checksum of $asset doesn't match, expected $sha256, got $actual"
+  }
+  New-Item -ItemType Directory -Force -Path $InstallDir | Out-Null
+  if ($format -eq "zip") {
+    Expand-Archive -Force -Path "$tmp\$asset" -DestinationPath $InstallDir
+  } elseif ($format -eq "tar") {
+    tar -xf "$tmp\$asset" -C $InstallDir
+  } else {
+    Move-Item -Force "$tmp\$asset" (Join-Path $InstallDir "{{ bin }}.exe")
+  }
+  Write-Host "installed {{ bin }} {{ tag }} to $InstallDir"
+} finally {
+  Remove-Item -Recurse -Force $tmp
+}
+"#;
+
+// Asset the install scripts can download, for one os and arch.
+#[derive(Serialize)]
+struct ScriptAsset {
+    name: String,
+    sha256: String,
+    os: String,
+    arch: String,
+    target: String,
+    // `zip` or `tar` for archives, `bin` for builds with `no_archive`.
+    format: String,
+}
+
+// Writes `install.sh`, and `install.ps1` if there are windows builds, to the
+// dist folder. The scripts detect the platform, download the asset built for
+// it and verify its checksum before installing it. Returns the paths of the
+// scripts so that they're published with the archives.
+pub async fn write_scripts(
+    release: &Release,
+    cfg: &InstallScript,
+    templates: &Templates,
+    tag: &str,
+    archives: &[String],
+) -> Result<Vec<String>> {
+    let base_url = match (&cfg.base_url, &release.targets.github) {
+        (Some(base_url), _) => templates.render(base_url, tag_context(tag))?,
+        (None, Some(gh)) => format!(
+            "https://github.com/{}/{}/releases/download/{}",
+            gh.owner, gh.repo, tag
+        ),
+        (None, None) => bail!("install script needs a base_url without the github target"),
+    };
+
+    let mut bin = cfg.bin_name.clone();
+    let mut assets = vec![];
+    for build in &release.builds {
+        // Windows builds' binaries have the `.exe` extension.
+        let build_bin = build.bin_name.trim_end_matches(".exe");
+        if bin.as_ref().is_some_and(|bin| bin != build_bin) {
+            continue;
+        }
+        let target = match &build.target {
+            Some(target) => target,
+            None => {
+                warn!(
+                    "build {} doesn't have a target, leaving it out of the install script",
+                    build.name
+                );
+                continue;
+            }
+        };
+        let name = templates.render(&build.name, tag_context(tag))?;
+        let (file_name, format) = match build.no_archive {
+            Some(_) => (name, "bin"),
+            None => {
+                let format = match build_compression(release, build).format.unwrap_or_default() {
+                    ArchiveFormat::Zip => "zip",
+                    _ => "tar",
+                };
+                (archive_name(release, build, &name), format)
+            }
+        };
+        let path = Utf8Path::new(&release.dist_folder).join(&file_name);
+        if !archives.iter().any(|a| Utf8Path::new(a) == path) {
+            warn!(
+                "{} wasn't built, leaving it out of the install script",
+                path
+            );
+            continue;
+        }
+
+        let (os, arch) = target_os_arch(target);
+        assets.push(ScriptAsset {
+            name: asset_name(templates, release, tag, &file_name, Some(target))?,
+            sha256: sha256_file(path.to_string()).await?,
+            os,
+            arch,
+            target: target.clone(),
+            format: format.to_string(),
+        });
+        bin.get_or_insert_with(|| build_bin.to_string());
+    }
+    let bin = match bin {
+        Some(bin) if !assets.is_empty() => bin,
+        _ => bail!("no builds with a target for the install script"),
+    };
+
+    let ctx = context! {
+        bin => bin,
+        base_url => base_url,
+        install_dir => cfg.install_dir.clone().unwrap_or_else(|| "$HOME/.local/bin".to_string()),
+        assets => Serde(&assets),
+        ..tag_context(tag)
+    };
+    let mut scripts = vec![];
+    let sh = script_template(cfg.template.as_deref(), SH_TEMPLATE).await?;
+    scripts.push(write_script(release, templates, "install.sh", &sh, ctx.clone()).await?);
+    if assets.iter().any(|a| a.os == "windows") {
+        let ps1 = script_template(cfg.powershell_template.as_deref(), PS1_TEMPLATE).await?;
+        scripts.push(write_script(release, templates, "install.ps1", &ps1, ctx).await?);
+    }
+    Ok(scripts)
+}
+
+// Reads the template from the file, or gets the built-in one.
+async fn script_template(path: Option<&str>, default: &str) -> Result<String> {
+    match path {
+        Some(path) => fs::read_to_string(path)
+            .await
+            .wrap_err_with(|| format!("error reading install script template {}", path)),
+        None => Ok(default.to_string()),
+    }
+}
+
+async fn write_script(
+    release: &Release,
+    templates: &Templates,
+    file_name: &str,
+    template: &str,
+    ctx: minijinja::Value,
+) -> Result<String> {
+    let script = templates
+        .render(template, ctx)
+        .wrap_err_with(|| format!("error rendering {}", file_name))?;
+    let path = Utf8Path::new(&release.dist_folder).join(file_name);
+    fs::create_dir_all(&release.dist_folder).await?;
+    fs::write(&path, script)
+        .await
+        .wrap_err_with(|| format!("error writing {}", path))?;
+    info!("wrote install script to {}", path);
+    Ok(path.to_string())
+}
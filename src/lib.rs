@@ -1,157 +1,449 @@
-use crate::utils::get_latest_tag;
 use camino::Utf8Path;
 use eyre::{bail, Context, Result};
-use log::{debug, error, info, warn};
-use std::{env, sync::Arc};
-use tokio::{fs, process::Command, sync::Mutex};
+use log::{debug, info, warn};
+use std::env;
+use std::process::Stdio;
+use std::sync::Arc;
+use tokio::fs;
 
+mod azure;
+mod backfill;
+mod benchmarks;
+mod binstall;
+mod bitbucket;
+mod build_cache;
+mod cargo;
+mod changelog;
+mod collisions;
 pub mod config;
+mod custom;
+mod dispatch;
 mod docker;
+mod docs;
+mod doctor;
+mod download_page;
+pub mod events;
+mod failure;
+mod gates;
+mod git;
 mod github;
+mod helm;
+mod hooks;
+mod install_script;
+mod licenses;
+mod lint;
+mod oras;
+mod pipeline;
+mod plugins;
+mod preflight;
+mod prepare;
 pub mod release_provider;
+mod reproducible;
+mod runners;
+mod sentry;
+mod sizes;
+mod templating;
+mod terraform;
+mod testing;
 mod utils;
-use crate::release_provider::ReleaseProvider;
-use config::{Build, Config, Release};
+mod vendor;
+mod version;
+mod version_files;
+mod versions_manifest;
+mod vsce;
+use crate::release_provider::{AssetNames, PublishResult, ReleaseProvider};
+pub use backfill::backfill;
+use config::{Build, Config, Release, Runner};
+pub use doctor::doctor;
+pub use failure::{exit_code, Failure};
 use github::Github;
-use utils::archive_file;
+pub use lint::check;
+pub use pipeline::{Checksum, ReleasePipeline, Stages};
+use plugins::Plugin;
+pub use reproducible::verify_reproducible;
+use templating::{tag_context, Templates};
+pub use utils::redact;
+use utils::{
+    additional_files, archive_file, archive_name, build_compression, spawn_tracked, split_command,
+    wrapped_command,
+};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct Opts {
     pub publish: bool,
     pub rm_dist: bool,
+    pub edit_notes: bool,
+    pub yes: bool,
+    // Fails if the repo has uncommitted changes.
+    pub require_clean: bool,
+    // Overrides the start and the end of the changelog range.
+    pub from: Option<String>,
+    pub to: Option<String>,
+    // Overrides the previous tag for the changelog and the tag which is
+    // released.
+    pub from_tag: Option<String>,
+    pub to_tag: Option<String>,
+    // Starts the changelog after this commit.
+    pub since_commit: Option<String>,
+    // Skips the releases which don't have commits since the last release.
+    pub skip_if_no_changes: bool,
+    // Doesn't read or write the caches.
+    pub no_cache: bool,
+}
+
+// Cleans up after rlsr is interrupted: kills the running builds, hooks and
+// targets and deletes the partially uploaded github releases.
+pub async fn cleanup_interrupted() {
+    utils::kill_child_groups();
+    github::delete_partial_releases().await;
 }
 
 pub async fn run(cfg: Config, opts: Opts) -> Result<()> {
-    if !opts.publish {
-        warn!("--publish isn't given, so skipping publishing")
-    }
-
-    let num = cfg.releases.len();
-    let shared: Arc<Vec<Release>> = Arc::from(cfg.releases);
-    for i in 0..num {
-        let releases = shared.clone();
-        let mut all_builds = vec![];
-        let all_archives = Arc::new(Mutex::new(vec![]));
-        for b in 0..releases[i].builds.len() {
-            let builds = shared.clone();
-            let all_archives = all_archives.clone();
-            all_builds.push(tokio::spawn(async move {
-                info!("executing build: {}", &builds[i].name);
-                let res = run_build(&builds[i], &builds[i].builds[b], opts.rm_dist).await;
-                match res {
-                    Err(err) => {
-                        error!("error executing the build: {}", err);
-                    }
-                    Ok(archive) => {
-                        all_archives.lock().await.push(archive);
-                    }
-                }
-            }));
-        }
-
-        // Wait until all builds are finished in a release.
-        futures::future::join_all(&mut all_builds).await;
-
-        debug!("all archives generated: {:?}", all_archives);
-        if opts.publish {
-            let latest_tag = match get_latest_tag().await {
-                Ok(tag) => {
-                    info!("found out latest tag: {}", tag);
-                    tag
-                }
-                Err(_) => {
-                    bail!("error finding tag, skipping publishing");
-                }
-            };
-            debug!("latest tag: {}", latest_tag);
-
-            // Make release providers from given config.
-            let providers = get_release_providers(&releases[i])?;
-            for prov in providers {
-                let all_archives = all_archives.clone();
-                match prov
-                    .publish(&releases[i], all_archives, latest_tag.clone())
-                    .await
-                {
-                    Ok(_) => continue,
-                    Err(err) => {
-                        error!("{}", err);
-                    }
-                }
-            }
-        }
-    }
-    Ok(())
+    ReleasePipeline::from_config(cfg)
+        .with_opts(opts)
+        .run()
+        .await
 }
 
-fn get_release_providers(release: &Release) -> Result<Vec<Box<dyn ReleaseProvider>>> {
+fn get_release_providers(
+    release: &Release,
+    prerelease: bool,
+    asset_names: AssetNames,
+    templates: Arc<Templates>,
+    plugins: &[Plugin],
+) -> Result<Vec<Box<dyn ReleaseProvider>>> {
     let mut providers: Vec<Box<dyn ReleaseProvider>> = vec![];
 
+    // Images are pushed first, so that the other targets publish the
+    // `images.txt` listing them with the archives.
+    if release.targets.docker.is_some() {
+        providers.push(Box::new(docker::Docker::new()));
+    }
+
     // Check if github details are provided.
     if release.targets.github.is_some() {
         let ghtoken = get_github_token()?;
-        let gh = Github::new(ghtoken);
+        let gh = Github::new(ghtoken, prerelease, asset_names.clone(), templates.clone());
         providers.push(Box::new(gh));
     }
 
-    if release.targets.docker.is_some() {
-        providers.push(Box::new(docker::Docker::new()));
+    if release.targets.oras.is_some() {
+        providers.push(Box::new(oras::Oras::new()));
+    }
+
+    // Download page is written before the custom targets, so that they can
+    // publish it.
+    if release.targets.download_page.is_some() {
+        providers.push(Box::new(download_page::DownloadPage::new(
+            asset_names.clone(),
+            templates.clone(),
+        )));
+    }
+
+    if release.targets.sentry.is_some() {
+        providers.push(Box::new(sentry::Sentry::new(templates.clone())));
+    }
+
+    if release.targets.helm.is_some() {
+        providers.push(Box::new(helm::Helm::new(
+            asset_names.clone(),
+            templates.clone(),
+        )));
+    }
+
+    if release.targets.vsce.is_some() {
+        providers.push(Box::new(vsce::Vsce::new(prerelease)));
+    }
+
+    if release.targets.bitbucket.is_some() {
+        providers.push(Box::new(bitbucket::Bitbucket::new(
+            asset_names.clone(),
+            templates.clone(),
+        )));
+    }
+
+    if release.targets.azure_devops.is_some() {
+        providers.push(Box::new(azure::AzureDevops::new(
+            asset_names.clone(),
+            templates.clone(),
+        )));
+    }
+
+    for custom in release.targets.custom.clone().unwrap_or_default() {
+        providers.push(Box::new(custom::Custom::new(
+            custom,
+            prerelease,
+            asset_names.clone(),
+        )));
+    }
+
+    // Plugins which publish are targets like the custom ones.
+    for plugin in plugins.iter().filter(|p| p.exports("publish")) {
+        providers.push(Box::new(plugins::PluginTarget::new(
+            plugin.clone(),
+            prerelease,
+            asset_names.clone(),
+        )));
+    }
+
+    // Versions manifest is updated last, after the archives are uploaded.
+    if release.targets.versions_manifest.is_some() {
+        let ghtoken = get_github_token()?;
+        providers.push(Box::new(versions_manifest::VersionsManifest::new(
+            ghtoken,
+            asset_names,
+        )));
     }
 
     Ok(providers)
 }
 
-pub async fn run_build(release: &Release, build: &Build, rm_dist: bool) -> Result<String> {
-    // Split cmd into command, args.
-    let cmds = build.command.split(' ').collect::<Vec<&str>>();
-    let output = Command::new(cmds[0]).args(&cmds[1..]).output().await?;
+// Gets a summary of everything that will be published for the release.
+fn publish_summary(release: &Release, tag: &str, num_assets: usize) -> String {
+    let mut summary = format!("Release \"{}\" will publish tag {}:", release.name, tag);
+    if let Some(gh) = &release.targets.github {
+        summary.push_str(&format!(
+            "\n  - github: {}/{} with {} asset(s)",
+            gh.owner, gh.repo, num_assets
+        ));
+        if gh.comment.is_some() {
+            summary.push_str(", commenting on the tag's pr");
+        }
+    }
+    if let Some(docker) = &release.targets.docker {
+        summary.push_str(&format!("\n  - docker: {}:{}", docker.image, tag));
+    }
+    if let Some(manifest) = &release.targets.versions_manifest {
+        summary.push_str(&format!(
+            "\n  - versions manifest: {}/{}/{} ({})",
+            manifest.owner, manifest.repo, manifest.path, manifest.branch
+        ));
+    }
+    if let Some(oras) = &release.targets.oras {
+        summary.push_str(&format!("\n  - oras: {}:{}", oras.repository, tag));
+    }
+    if let Some(page) = &release.targets.download_page {
+        let output = page
+            .output
+            .clone()
+            .unwrap_or_else(|| format!("{}/download.html", release.dist_folder));
+        summary.push_str(&format!("\n  - download page: {}", output));
+        if let Some(pages) = &page.pages {
+            summary.push_str(&format!(
+                " and {}/{}/{} ({})",
+                pages.owner, pages.repo, pages.path, pages.branch
+            ));
+        }
+    }
+    if let Some(sentry) = &release.targets.sentry {
+        summary.push_str(&format!(
+            "\n  - sentry: {} ({})",
+            sentry.org,
+            sentry.projects.join(", ")
+        ));
+    }
+    if let Some(helm) = &release.targets.helm {
+        summary.push_str(&format!("\n  - helm: chart {}", helm.chart));
+        if let Some(oci) = &helm.oci {
+            summary.push_str(&format!(" to {}", oci));
+        }
+        if let Some(index) = &helm.index {
+            summary.push_str(&format!(
+                " and {}/{}/{} ({})",
+                index.owner, index.repo, index.path, index.branch
+            ));
+        }
+    }
+    if let Some(vsce) = &release.targets.vsce {
+        let mut stores = vec![];
+        if vsce.marketplace.unwrap_or(true) {
+            stores.push("marketplace");
+        }
+        if vsce.open_vsx.unwrap_or(true) {
+            stores.push("open vsx");
+        }
+        summary.push_str(&format!("\n  - vsce: {}", stores.join(" and ")));
+    }
+    if let Some(bitbucket) = &release.targets.bitbucket {
+        summary.push_str(&format!(
+            "\n  - bitbucket: {}/{} downloads with {} asset(s)",
+            bitbucket.workspace, bitbucket.repo, num_assets
+        ));
+    }
+    if let Some(azure) = &release.targets.azure_devops {
+        let mut parts = vec![];
+        if let Some(feed) = &azure.feed {
+            parts.push(format!("feed {} with {} asset(s)", feed, num_assets));
+        }
+        if let Some(wiki) = &azure.wiki {
+            parts.push(format!("wiki {}", wiki.name));
+        }
+        summary.push_str(&format!(
+            "\n  - azure devops: {} {}",
+            azure.organization,
+            parts.join(" and ")
+        ));
+    }
+    for custom in release.targets.custom.iter().flatten() {
+        summary.push_str(&format!("\n  - {}: {}", custom.name, custom.command));
+    }
+    for plugin in release.plugins.iter().flatten() {
+        summary.push_str(&format!("\n  - plugin {}", plugin.name));
+    }
+    summary
+}
 
-    // If the build executed succesfully, copy the artifact to dist folder.
-    if output.status.success() {
-        // Delete the dist directory if rm_dist is provided.
-        if rm_dist {
-            fs::remove_dir_all(&release.dist_folder).await?;
+// Gets a summary of what the providers published for the release and why
+// the failed ones failed.
+fn published_summary(
+    release: &Release,
+    results: &[PublishResult],
+    failures: &[(String, String)],
+) -> String {
+    let mut summary = format!("Release \"{}\" published:", release.name);
+    for result in results {
+        summary.push_str(&format!("\n  - {}", result.provider));
+        if let Some(url) = &result.release_url {
+            summary.push_str(&format!(": {}", url));
+        }
+        for url in &result.asset_urls {
+            summary.push_str(&format!("\n      {}", url));
+        }
+        for digest in &result.image_digests {
+            summary.push_str(&format!("\n      {}", digest));
         }
+    }
+    for (provider, reason) in failures {
+        summary.push_str(&format!("\n  - {} failed: {}", provider, reason));
+    }
+    summary
+}
 
-        // Create dist directory.
-        fs::create_dir_all(&release.dist_folder).await?;
-        fs::copy(
-            &build.artifact,
-            Utf8Path::new(&release.dist_folder).join(&build.bin_name),
-        )
-        .await
-        .with_context(|| format!("error while copying artifact: {}", build.artifact))?;
-
-        let dist_folder = Utf8Path::new(&release.dist_folder).join(&build.bin_name);
-        let bin_path = dist_folder.to_string();
-
-        if build.no_archive.is_none() {
-            // Create an archive.
-            debug!("creating an archive for {}", &build.name);
-            let zip_path = archive_file(
-                bin_path.to_owned(),
-                release.dist_folder.clone(),
-                build.name.clone(),
-            )
-            .await
-            .with_context(|| format!("error while creating archive for build: {}", build.name))?;
-            return Ok(zip_path);
+// Runs the build and copies its artifact to the dist folder. Returns the
+// artifact's path, which is archived by the archive stage unless the build
+// has `no_archive`. Artifacts to archive are copied to a folder of their own
+// named after the build, so that builds with the same binary name don't
+// overwrite each other's.
+pub async fn run_build(
+    release: &Release,
+    build: &Build,
+    templates: &Templates,
+    tag: &str,
+    cache: bool,
+    runner: Option<&Runner>,
+) -> Result<String> {
+    let name = templates
+        .render(&build.name, tag_context(tag))
+        .wrap_err_with(|| format!("error rendering name for build: {}", build.name))?;
+    let dist_path = match build.no_archive {
+        Some(_) => Utf8Path::new(&release.dist_folder).join(&name),
+        None => Utf8Path::new(&release.dist_folder)
+            .join(&name)
+            .join(&build.bin_name),
+    };
+    let cache_key = match &release.build_cache {
+        Some(cfg) if cache => Some(build_cache::key(release, build, cfg, tag)?),
+        _ => None,
+    };
+    if let Some(key) = &cache_key {
+        if let Some(path) = build_cache::cached(release, &name, key, dist_path.as_str()).await {
+            info!("build {} didn't change, reusing {}", name, path);
+            return Ok(path);
+        }
+        let remote = release
+            .build_cache
+            .as_ref()
+            .and_then(|c| c.remote.as_deref());
+        if let Some(remote) = remote {
+            if build_cache::fetch(release, &name, remote, key, dist_path.as_str()).await {
+                return Ok(dist_path.to_string());
+            }
+        }
+    }
+
+    let output = match (&build.container, runner) {
+        (Some(container), _) => runners::run_in_container(container, build).await?,
+        (None, Some(runner)) => runners::run(runner, build).await?,
+        (None, None) => {
+            // Split cmd into command, args.
+            let cmds = split_command(&build.command)?;
+            let (child, _group) = spawn_tracked(
+                wrapped_command(&cmds)
+                    .stdout(Stdio::piped())
+                    .stderr(Stdio::piped()),
+            )?;
+            child.wait_with_output().await?
         }
+    };
+
+    if !output.status.success() {
+        bail!(
+            "build {} failed with {}: {}",
+            name,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
 
+    // Create dist directory.
+    fs::create_dir_all(&release.dist_folder).await?;
+
+    if build.no_archive.is_none() {
+        fs::create_dir_all(Utf8Path::new(&release.dist_folder).join(&name)).await?;
+        fs::copy(&build.artifact, &dist_path)
+            .await
+            .with_context(|| format!("error while copying artifact: {}", build.artifact))?;
+    } else {
         // Copy the binary to the given name.
-        fs::copy(
-            &build.artifact,
-            Utf8Path::new(&release.dist_folder).join(&build.name),
-        )
-        .await
-        .with_context(|| "error while copying artifact to given name")?;
+        fs::copy(&build.artifact, &dist_path)
+            .await
+            .with_context(|| "error while copying artifact to given name")?;
+    }
 
-        return Ok(Utf8Path::new(&release.dist_folder)
-            .join(&build.name)
-            .to_string());
+    if let Some(key) = &cache_key {
+        if let Err(err) = build_cache::save(release, &name, key, dist_path.as_str()).await {
+            warn!("error caching build {}: {}", name, err);
+        }
+        let remote = release
+            .build_cache
+            .as_ref()
+            .and_then(|c| c.remote.as_deref());
+        if let Some(remote) = remote {
+            if let Err(err) = build_cache::store(remote, key, dist_path.as_str()).await {
+                warn!("error caching build {} in {}: {}", name, remote, err);
+            }
+        }
     }
+    Ok(dist_path.to_string())
+}
 
-    Ok(String::from(""))
+// Archives the artifact of the build returned by `run_build` and returns
+// the archive's path.
+pub async fn archive_build(
+    release: &Release,
+    build: &Build,
+    templates: &Templates,
+    tag: &str,
+    bin_path: String,
+) -> Result<String> {
+    let name = templates
+        .render(&build.name, tag_context(tag))
+        .wrap_err_with(|| format!("error rendering name for build: {}", build.name))?;
+    debug!("creating an archive for {}", &name);
+    if let Some(cfg) = &release.terraform {
+        return terraform::archive(release, build, cfg, tag, bin_path)
+            .await
+            .with_context(|| format!("error while creating archive for build: {}", name));
+    }
+    archive_file(
+        bin_path,
+        additional_files(release, build)?,
+        release.dist_folder.clone(),
+        archive_name(release, build, &name),
+        build_compression(release, build),
+    )
+    .await
+    .with_context(|| format!("error while creating archive for build: {}", name))
 }
 
 fn get_github_token() -> Result<String> {
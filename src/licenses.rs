@@ -0,0 +1,99 @@
+use crate::cargo;
+use crate::config::{Licenses, Release};
+use crate::templating::{tag_context, Templates};
+use crate::utils::{group_command, notices_path, spawn_tracked};
+use camino::Utf8Path;
+use eyre::{bail, Context, Result};
+use log::{info, warn};
+use minijinja::context;
+use tokio::fs;
+
+// Checks the licenses of the cargo workspace's dependencies against the
+// denied ones and runs the command writing the third party notices, which
+// the archive stage bundles into every archive.
+pub async fn scan(
+    release: &Release,
+    cfg: &Licenses,
+    templates: &Templates,
+    tag: &str,
+) -> Result<()> {
+    if let Some(deny) = &cfg.deny {
+        check_licenses(cfg, deny).await?;
+    }
+
+    let output = notices_path(release, cfg);
+    let cmd = match &cfg.command {
+        Some(cmd) => cmd,
+        None => {
+            if !Utf8Path::new(&output).is_file() {
+                bail!(
+                    "third party notices {} don't exist and there's no license command",
+                    output
+                );
+            }
+            return Ok(());
+        }
+    };
+    let cmd = templates
+        .render(cmd, context! { output => &output, ..tag_context(tag) })
+        .wrap_err_with(|| "error rendering license command")?;
+    if let Some(dir) = Utf8Path::new(&output).parent() {
+        if !dir.as_str().is_empty() {
+            fs::create_dir_all(dir).await?;
+        }
+    }
+
+    info!("generating third party notices: {}", cmd);
+    let (mut child, _group) = spawn_tracked(group_command("sh").arg("-c").arg(&cmd))
+        .wrap_err_with(|| format!("error executing license command: {}", cmd))?;
+    let status = child
+        .wait()
+        .await
+        .wrap_err_with(|| format!("error executing license command: {}", cmd))?;
+    if !status.success() {
+        bail!("license command `{}` failed with {}", cmd, status);
+    }
+    if !Utf8Path::new(&output).is_file() {
+        bail!("license command `{}` didn't write {}", cmd, output);
+    }
+    Ok(())
+}
+
+// Fails if a crate in the workspace or its dependencies is only under denied
+// licenses.
+async fn check_licenses(cfg: &Licenses, deny: &[String]) -> Result<()> {
+    let manifest = cfg.manifest.as_deref().unwrap_or("Cargo.toml");
+    let mut denied = vec![];
+    for (name, version, license) in cargo::licenses(manifest).await? {
+        match license {
+            Some(license) if is_denied(&license, deny) => {
+                denied.push(format!("  {} {} is {}", name, version, license))
+            }
+            Some(_) => {}
+            None => warn!("{} {} doesn't declare a license", name, version),
+        }
+    }
+    if !denied.is_empty() {
+        bail!("crates are under denied licenses:\n{}", denied.join("\n"));
+    }
+    info!("no crates are under denied licenses");
+    Ok(())
+}
+
+// Checks if the SPDX expression is denied: all of its `OR` alternatives have
+// a denied license. Old style `MIT/Apache-2.0` expressions are alternatives.
+fn is_denied(expr: &str, deny: &[String]) -> bool {
+    expr.split(" OR ")
+        .flat_map(|alt| alt.split('/'))
+        .all(|alt| {
+            alt.split(" AND ").any(|id| {
+                let id = id.trim().trim_matches(|c| c == '(' || c == ')');
+                let id = id.trim_end_matches('+');
+                let id = id
+                    .strip_suffix("-only")
+                    .or_else(|| id.strip_suffix("-or-later"))
+                    .unwrap_or(id);
+                deny.iter().any(|d| d.eq_ignore_ascii_case(id))
+            })
+        })
+}
@@ -0,0 +1,183 @@
+use crate::collisions::{self, describe};
+use crate::config::{parse_config, Config};
+use crate::failure::Failure;
+use crate::pipeline::build_waves;
+use crate::templating::{check_locale, check_timezone, Templates};
+use camino::Utf8Path;
+use eyre::{eyre, Result};
+use std::collections::HashMap;
+
+// Tag the names are rendered with, since lints don't depend on the version.
+const LINT_TAG: &str = "v0.0.0";
+
+// How bad a lint is. Errors fail `rlsr check`, warnings are only shown.
+#[derive(PartialEq)]
+enum Severity {
+    Warning,
+    Error,
+}
+
+struct Lint {
+    code: &'static str,
+    severity: Severity,
+    message: String,
+}
+
+impl Lint {
+    fn new(code: &'static str, severity: Severity, message: impl Into<String>) -> Self {
+        Lint {
+            code,
+            severity,
+            message: message.into(),
+        }
+    }
+}
+
+// Parses the config and checks it for mistakes which parse fine but make the
+// release fail or do something else than intended. Prints the lints and
+// fails if any of them is an error.
+pub async fn check(config: &str) -> Result<()> {
+    let cfg = parse_config(config)
+        .await
+        .map_err(|err| Failure::Config.wrap(err.wrap_err("error parsing config")))?;
+    let lints = lints(&cfg);
+
+    println!("rlsr check:");
+    if lints.is_empty() {
+        println!("  {} has no problems", config);
+        return Ok(());
+    }
+    for lint in &lints {
+        let severity = match lint.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        println!("  [{:<7}] {}: {}", severity, lint.code, lint.message);
+    }
+    let errors = lints
+        .iter()
+        .filter(|l| l.severity == Severity::Error)
+        .count();
+    if errors > 0 {
+        return Err(Failure::Config.wrap(eyre!("{} lint error(s) in {}", errors, config)));
+    }
+    Ok(())
+}
+
+fn lints(cfg: &Config) -> Vec<Lint> {
+    let templates = Templates::new(cfg.templating.as_ref(), cfg.version.as_ref());
+    let mut lints = vec![];
+
+    for collision in collisions::find(cfg, &templates, |_| LINT_TAG.to_string()) {
+        let (first, second) = (collision.first, collision.second);
+        lints.push(Lint::new(
+            "L001",
+            Severity::Error,
+            format!(
+                "{} and {} both write {}",
+                describe(first.0, first.1),
+                describe(second.0, second.1),
+                collision.output
+            ),
+        ));
+    }
+
+    if let Some(Err(err)) = cfg.templating.as_ref().map(check_timezone) {
+        lints.push(Lint::new("L008", Severity::Error, err.to_string()));
+    }
+    if let Some(Err(err)) = cfg.templating.as_ref().map(check_locale) {
+        lints.push(Lint::new("L009", Severity::Error, err.to_string()));
+    }
+
+    let mut dists: HashMap<String, &str> = HashMap::new();
+    for release in &cfg.releases {
+        let dist = release.dist_folder.trim_end_matches('/').to_string();
+        if let Some(other) = dists.insert(dist.clone(), &release.name) {
+            lints.push(Lint::new(
+                "L002",
+                Severity::Warning,
+                format!(
+                    "releases {} and {} share the dist folder {}, `--rm-dist` deletes each other's files",
+                    other, release.name, dist
+                ),
+            ));
+        }
+
+        for build in &release.builds {
+            // Terraform releases archive every build.
+            let archived = build.no_archive.is_none() || release.terraform.is_some();
+            let archive_options = build.additional_files.is_some()
+                || build.preset.is_some()
+                || build.compression.is_some();
+            if !archived && archive_options {
+                lints.push(Lint::new(
+                    "L003",
+                    Severity::Warning,
+                    format!(
+                        "{} has `no_archive`, so its `additional_files`, `preset` and `compression` are ignored",
+                        describe(release, Some(build))
+                    ),
+                ));
+            }
+        }
+
+        if let Err(err) = build_waves(release) {
+            lints.push(Lint::new("L004", Severity::Error, err.to_string()));
+        }
+
+        if let Some(docker) = &release.targets.docker {
+            if !Utf8Path::new(&docker.context).is_dir() {
+                lints.push(Lint::new(
+                    "L005",
+                    Severity::Error,
+                    format!(
+                        "docker context {} of {} doesn't exist",
+                        docker.context, release.name
+                    ),
+                ));
+            } else if !Utf8Path::new(&docker.context)
+                .join(&docker.dockerfile)
+                .is_file()
+                && !Utf8Path::new(&docker.dockerfile).is_file()
+            {
+                lints.push(Lint::new(
+                    "L005",
+                    Severity::Error,
+                    format!(
+                        "dockerfile {} of {} doesn't exist",
+                        docker.dockerfile, release.name
+                    ),
+                ));
+            }
+        }
+
+        // Install scripts and binstall metadata point at the archives.
+        let no_archives = !release.builds.is_empty()
+            && release.terraform.is_none()
+            && release.cargo.is_none()
+            && release.builds.iter().all(|b| b.no_archive.is_some());
+        if no_archives && (release.install_script.is_some() || release.binstall.is_some()) {
+            lints.push(Lint::new(
+                "L006",
+                Severity::Warning,
+                format!(
+                    "every build of {} has `no_archive`, but its install scripts and binstall metadata need archives",
+                    release.name
+                ),
+            ));
+        }
+
+        let github = release.targets.github.is_some();
+        if !github && (release.size_report.is_some() || release.benchmarks.is_some()) {
+            lints.push(Lint::new(
+                "L007",
+                Severity::Warning,
+                format!(
+                    "{} has no github target, so its size report and benchmarks aren't compared to the previous release",
+                    release.name
+                ),
+            ));
+        }
+    }
+    lints
+}
@@ -1,47 +1,211 @@
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use env_logger::Env;
 use log::error;
-use rlsr::{run, Opts};
+use rlsr::{
+    backfill, check, cleanup_interrupted, doctor, exit_code, redact, run, verify_reproducible,
+    Failure, Opts,
+};
+use std::io::Write;
 use std::process;
+use std::time::Duration;
 
-use rlsr::config::parse_config;
+use rlsr::config::{find_config, parse_config};
 
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
 struct Args {
-    #[clap(short, long, default_value = "rlsr.yml")]
-    config: String,
+    /// Config file, or `-` for stdin. Defaults to the first of rlsr.yml,
+    /// rlsr.yaml, rlsr.toml and rlsr.json, in the current folder or .config.
+    #[clap(short, long)]
+    config: Option<String>,
 
     #[clap(long, name = "rm-dist")]
     rm_dist: bool,
 
     #[clap(short, long)]
     publish: bool,
+
+    #[clap(long, name = "edit-notes")]
+    edit_notes: bool,
+
+    /// Skips the confirmation before publishing.
+    #[clap(short, long)]
+    yes: bool,
+
+    /// Fails if the repo has uncommitted changes.
+    #[clap(long, name = "require-clean")]
+    require_clean: bool,
+
+    /// Start of the changelog range, defaults to the previous tag.
+    #[clap(long)]
+    from: Option<String>,
+
+    /// End of the changelog range, defaults to the latest tag.
+    #[clap(long)]
+    to: Option<String>,
+
+    /// Previous tag for the changelog, instead of the detected one.
+    #[clap(long, conflicts_with_all = &["from", "since-commit"])]
+    from_tag: Option<String>,
+
+    /// Tag to release, instead of the latest tag.
+    #[clap(long)]
+    to_tag: Option<String>,
+
+    /// Starts the changelog after this commit.
+    #[clap(long, conflicts_with = "from")]
+    since_commit: Option<String>,
+
+    /// Does nothing if there are no commits since the last release.
+    #[clap(long, name = "skip-if-no-changes")]
+    skip_if_no_changes: bool,
+
+    /// Doesn't use or update the caches, e.g. of the commits in changelogs.
+    #[clap(long, name = "no-cache")]
+    no_cache: bool,
+
+    /// Rebuilds the tag and compares the checksums with its github release.
+    #[clap(long, value_name = "TAG")]
+    verify_reproducible: Option<String>,
+
+    #[clap(subcommand)]
+    command: Option<Cmd>,
 }
 
-#[tokio::main]
-async fn main() {
+#[derive(Subcommand, Debug)]
+enum Cmd {
+    /// Creates the releases for existing tags which don't have one.
+    Backfill {
+        /// Range of tags to release, e.g. `v1.0.0..v1.5.0`.
+        #[clap(long)]
+        tags: String,
+    },
+    /// Checks that the environment is ready for releasing.
+    Doctor,
+    /// Checks the config for mistakes, e.g. builds overwriting each other.
+    Check,
+}
+
+fn main() {
     color_eyre::install().unwrap();
-    env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
+    // Values decrypted from the config are redacted from the logs.
+    env_logger::Builder::from_env(Env::default().default_filter_or("info"))
+        .format(|buf, record| {
+            writeln!(
+                buf,
+                "[{} {:<5} {}] {}",
+                buf.timestamp(),
+                buf.default_styled_level(record.level()),
+                record.target(),
+                redact(&record.args().to_string())
+            )
+        })
+        .init();
     let args = Args::parse();
-    let config = args.config;
+
+    let rt = tokio::runtime::Runtime::new().expect("error starting the runtime");
+    let code = rt.block_on(run_args(args));
+    // Builds and uploads still running after an interrupt are dropped with
+    // the runtime, which kills their processes.
+    rt.shutdown_timeout(Duration::from_secs(5));
+    process::exit(code);
+}
+
+// Runs rlsr with the args and returns the exit code.
+async fn run_args(args: Args) -> i32 {
+    let config = match args.config.map_or_else(find_config, Ok) {
+        Ok(config) => config,
+        Err(err) => {
+            error!("error finding config: {}", err);
+            return Failure::Config.exit_code();
+        }
+    };
+    if let Some(Cmd::Doctor) = args.command {
+        if let Err(err) = doctor(&config).await {
+            error!("{}", err);
+            return exit_code(&err);
+        }
+        return 0;
+    }
+    if let Some(Cmd::Check) = args.command {
+        if let Err(err) = check(&config).await {
+            error!("{}", err);
+            return exit_code(&err);
+        }
+        return 0;
+    }
 
     let cfg = parse_config(&config).await;
     let cfg = match cfg {
         Ok(cfg) => cfg,
         Err(err) => {
             error!("error parsing config: {}", err);
-            process::exit(1);
+            return Failure::Config.exit_code();
         }
     };
 
     let opts = Opts {
         publish: args.publish,
         rm_dist: args.rm_dist,
+        edit_notes: args.edit_notes,
+        yes: args.yes,
+        require_clean: args.require_clean,
+        from: args.from,
+        to: args.to,
+        from_tag: args.from_tag,
+        to_tag: args.to_tag,
+        since_commit: args.since_commit,
+        skip_if_no_changes: args.skip_if_no_changes,
+        no_cache: args.no_cache,
     };
 
-    if let Err(error) = run(cfg, opts).await {
+    let cmd = async {
+        match (args.command, args.verify_reproducible) {
+            (Some(Cmd::Backfill { tags }), _) => backfill(cfg, opts, &tags).await,
+            (None, Some(tag)) => verify_reproducible(cfg, opts, &tag).await,
+            (Some(Cmd::Doctor | Cmd::Check), _) => unreachable!(),
+            (None, None) => run(cfg, opts).await,
+        }
+    };
+    // Cleaning up is done before the release's future is dropped, which
+    // would stop tracking its processes.
+    let cleanup = async {
+        let code = interrupted().await;
+        error!("interrupted, stopping the release");
+        cleanup_interrupted().await;
+        code
+    };
+    let res = tokio::select! {
+        res = cmd => res,
+        code = cleanup => return code,
+    };
+    if let Err(error) = res {
         error!("error running rlsr: {}", error);
-        process::exit(1);
+        return exit_code(&error);
+    }
+    0
+}
+
+// Waits for SIGINT or SIGTERM and returns the exit code for it, 130 or 143
+// like shells do.
+#[cfg(unix)]
+async fn interrupted() -> i32 {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut term = match signal(SignalKind::terminate()) {
+        Ok(term) => term,
+        Err(_) => return std::future::pending().await,
+    };
+    tokio::select! {
+        Ok(()) = tokio::signal::ctrl_c() => 130,
+        Some(()) = term.recv() => 143,
+        else => std::future::pending().await,
+    }
+}
+
+#[cfg(not(unix))]
+async fn interrupted() -> i32 {
+    match tokio::signal::ctrl_c().await {
+        Ok(()) => 130,
+        Err(_) => std::future::pending().await,
     }
 }
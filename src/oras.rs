@@ -0,0 +1,102 @@
+use crate::config::Release;
+use crate::release_provider::{ProviderError, PublishResult, ReleaseProvider};
+use crate::utils::{group_command, spawn_tracked};
+use async_trait::async_trait;
+use camino::Utf8Path;
+use eyre::{bail, Context, Result};
+use log::{debug, info};
+use std::process::Stdio;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+const DEFAULT_ARTIFACT_TYPE: &str = "application/vnd.rlsr.release";
+
+/// Oras pushes the archives of a release as an OCI artifact to a registry
+/// with the `oras` CLI, so that binaries can be distributed from the same
+/// registries as container images. Registry credentials are the ones from
+/// `oras login` or the docker config.
+pub struct Oras {}
+
+impl Oras {
+    pub fn new() -> Self {
+        Oras {}
+    }
+}
+
+#[allow(clippy::needless_arbitrary_self_type)]
+#[async_trait]
+impl ReleaseProvider for Oras {
+    fn name(self: &Self) -> String {
+        "oras".to_string()
+    }
+
+    async fn publish(
+        self: &Self,
+        release: &Release,
+        all_archives: Arc<Mutex<Vec<String>>>,
+        latest_tag: String,
+        _changelog: String,
+    ) -> Result<PublishResult> {
+        let oras = match &release.targets.oras {
+            Some(oras) => oras,
+            None => bail!("oras config can't be empty"),
+        };
+        let reference = format!("{}:{}", oras.repository, latest_tag);
+        let artifact_type = oras
+            .artifact_type
+            .clone()
+            .unwrap_or_else(|| DEFAULT_ARTIFACT_TYPE.to_string());
+
+        // Files are pushed from the dist folder so that their titles in the
+        // manifest are just the file names.
+        let mut files = vec![];
+        for archive in all_archives.lock().await.iter() {
+            match Utf8Path::new(archive).file_name() {
+                Some(name) => files.push(name.to_string()),
+                None => continue,
+            }
+        }
+        if files.is_empty() {
+            bail!("no archives to push to {}", reference);
+        }
+
+        info!("pushing {} archive(s) to {}", files.len(), reference);
+        let (child, _group) = spawn_tracked(
+            group_command("oras")
+                .arg("push")
+                .arg(&reference)
+                .arg("--artifact-type")
+                .arg(&artifact_type)
+                .args(&files)
+                .current_dir(&release.dist_folder)
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped()),
+        )
+        .wrap_err_with(|| "error executing oras push")?;
+        let output = child
+            .wait_with_output()
+            .await
+            .wrap_err_with(|| "error executing oras push")?;
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(ProviderError::from_output(
+                &stderr,
+                format!("error executing oras push: {}", stderr.trim()),
+            ));
+        }
+        debug!("oras push output: {}", stdout);
+
+        let mut result = PublishResult::new("oras");
+        result.release_url = Some(reference.clone());
+        if let Some(digest) = stdout
+            .lines()
+            .find_map(|line| line.trim().strip_prefix("Digest:"))
+        {
+            result
+                .image_digests
+                .push(format!("{}@{}", oras.repository, digest.trim()));
+        }
+        Ok(result)
+    }
+}
@@ -0,0 +1,1235 @@
+use crate::changelog::{
+    changelog_range, get_release_changelog, target_changelogs, truncate_changelog,
+};
+use crate::config::{Config, Release, WhenUntagged};
+use crate::events::{Event, EventHandler, Events};
+use crate::failure::Failure;
+use crate::git::GitRepo;
+use crate::hooks::run_hooks;
+use crate::plugins::{self, Announcement, Plugin};
+use crate::preflight::preflight;
+use crate::release_provider::{AssetNames, ProviderError, PublishResult};
+use crate::templating::{asset_name, tag_context, tag_version, Templates};
+use crate::utils::{
+    archive_name, confirm, edit_notes, expand_globs, interactive, push_tag, redact, remote_has_tag,
+    set_shell_wrapper, sha256_file, unshallow_repo,
+};
+use crate::{
+    archive_build, benchmarks, binstall, cargo, collisions, get_release_providers, install_script,
+    publish_summary, published_summary, run_build, sizes,
+};
+use crate::{
+    dispatch, docs, gates, helm, licenses, prepare, runners, terraform, testing, vendor, version,
+    version_files, Opts,
+};
+use camino::Utf8Path;
+use eyre::{bail, eyre, Context, Report, Result};
+use log::{debug, error, info, warn};
+use minijinja::{context, value::Serde};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::fs;
+use tokio::sync::{Mutex, Semaphore};
+
+// Providers failing with rate limits or network errors are retried, waiting
+// longer after every attempt.
+const PUBLISH_ATTEMPTS: u32 = 3;
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+// File in the dist folder with the checksums of the assets.
+const CHECKSUMS_FILE: &str = "checksums.txt";
+
+// File in the dist folder with what the targets published.
+const RESULTS_FILE: &str = "artifacts.json";
+
+/// ReleasePipeline runs the release stages (build, checksum, publish) for the
+/// configured releases. `rlsr::run` drives it from the config file, but other
+/// tools can build one programmatically and run all or some of the stages.
+///
+/// ```no_run
+/// # async fn example(release: rlsr::config::Release) -> eyre::Result<()> {
+/// let archives = rlsr::ReleasePipeline::new()
+///     .with_release(release)
+///     .build_only()
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct ReleasePipeline {
+    cfg: Config,
+    opts: Opts,
+    events: Events,
+}
+
+/// Stages has the state shared by the stages of a pipeline, i.e. the git repo,
+/// the templates and the tag. It's created by `ReleasePipeline::prepare`.
+pub struct Stages {
+    git: GitRepo,
+    templates: Arc<Templates>,
+    tag: String,
+    // Plugins of the releases keyed by the release name, loaded once so that
+    // all the hooks of a release use the same libraries.
+    plugins: HashMap<String, Vec<Plugin>>,
+    // Repos and tags of the releases with their own tag pattern or paths,
+    // keyed by the release name. Other releases use the main repo and tag.
+    scoped: HashMap<String, (GitRepo, String)>,
+    // Targets which failed while the others published, e.g. `docker of cli`.
+    failed_targets: std::sync::Mutex<Vec<String>>,
+    cfg: Config,
+    opts: Opts,
+    events: Events,
+}
+
+#[derive(Clone, Debug)]
+pub struct Checksum {
+    pub path: String,
+    pub sha256: String,
+}
+
+impl Default for ReleasePipeline {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ReleasePipeline {
+    pub fn new() -> Self {
+        Self::from_config(Config {
+            releases: vec![],
+            templating: None,
+            plugins_dir: None,
+            version: None,
+            git: None,
+            hooks: None,
+            requirements: None,
+            runners: None,
+            shell_wrapper: None,
+            publish_from: None,
+            extends: None,
+            extends_sha256: None,
+        })
+    }
+
+    pub fn from_config(cfg: Config) -> Self {
+        ReleasePipeline {
+            cfg,
+            opts: Opts::default(),
+            events: Events::default(),
+        }
+    }
+
+    pub fn with_release(mut self, release: Release) -> Self {
+        self.cfg.releases.push(release);
+        self
+    }
+
+    pub fn with_opts(mut self, opts: Opts) -> Self {
+        self.opts = opts;
+        self
+    }
+
+    // Subscribes the handler to the pipeline's events.
+    pub fn with_event_handler(mut self, handler: Arc<dyn EventHandler>) -> Self {
+        self.events.subscribe(handler);
+        self
+    }
+
+    // Opens the git repo, unshallowing it if needed, and sets up the
+    // templates so that the stages can be run.
+    pub async fn prepare(self) -> Result<Stages> {
+        // Tags and changelogs are wrong in shallow clones, so either fetch the
+        // full history or refuse to publish.
+        let git = GitRepo::open_with(self.cfg.git.as_ref())?.with_cache(!self.opts.no_cache);
+        if git.is_shallow() {
+            let unshallow = self
+                .cfg
+                .git
+                .as_ref()
+                .and_then(|g| g.unshallow)
+                .unwrap_or(false);
+            if unshallow {
+                info!("repo is a shallow clone, fetching full history and tags");
+                unshallow_repo().await?;
+            } else if self.opts.publish {
+                bail!(
+                    "repo is a shallow clone, so tags and changelog can't be found correctly. \
+                     Fetch the full history (e.g. `fetch-depth: 0` with actions/checkout) or set \
+                     `git.unshallow: true` in the config"
+                );
+            } else {
+                warn!("repo is a shallow clone, tags used in templates might be wrong");
+            }
+        }
+
+        preflight(&self.cfg, &git, self.opts.publish, self.opts.require_clean)?;
+        set_shell_wrapper(self.cfg.shell_wrapper.as_deref());
+
+        let templates = Arc::new(Templates::new(
+            self.cfg.templating.as_ref(),
+            self.cfg.version.as_ref(),
+        ));
+        // Tag is only used for rendering templates here, publishing checks for
+        // the tag again.
+        let tag = match &self.opts.to_tag {
+            Some(_) => release_tag(&git, &self.opts)?,
+            None => git.latest_tag().unwrap_or_default(),
+        };
+
+        let mut cfg = self.cfg;
+        for release in cfg.releases.iter_mut() {
+            if let Some(cargo) = &release.cargo {
+                let builds = cargo::workspace_builds(cargo)
+                    .await
+                    .wrap_err_with(|| format!("error finding cargo builds for {}", release.name))?;
+                release.builds.extend(builds);
+            }
+        }
+
+        let mut plugins = HashMap::new();
+        for release in &cfg.releases {
+            let loaded = plugins::load_all(release, cfg.plugins_dir.as_deref())?;
+            plugins.insert(release.name.clone(), loaded);
+        }
+
+        let mut scoped = HashMap::new();
+        for release in &cfg.releases {
+            if release.tag_pattern.is_none() && release.change_paths.is_none() {
+                continue;
+            }
+            let git = GitRepo::open_scoped(
+                cfg.git.as_ref(),
+                release.tag_pattern.as_ref(),
+                &release.change_paths.clone().unwrap_or_default(),
+            )?
+            .with_cache(!self.opts.no_cache);
+            let tag = match &self.opts.to_tag {
+                Some(_) => release_tag(&git, &self.opts)?,
+                None => git.latest_tag().unwrap_or_default(),
+            };
+            debug!("tag of release {}: {}", release.name, tag);
+            scoped.insert(release.name.clone(), (git, tag));
+        }
+        collisions::check(&cfg, &templates, |release| {
+            match scoped.get(&release.name) {
+                Some((_, tag)) => tag.clone(),
+                None => tag.clone(),
+            }
+        })?;
+
+        Ok(Stages {
+            git,
+            templates,
+            tag,
+            plugins,
+            scoped,
+            failed_targets: std::sync::Mutex::new(vec![]),
+            cfg,
+            opts: self.opts,
+            events: self.events,
+        })
+    }
+
+    // Runs only the build stage for all the releases and returns the archives
+    // of each release.
+    pub async fn build_only(self) -> Result<Vec<Vec<String>>> {
+        let stages = self.prepare().await?;
+        stages.global_hooks("before").await?;
+        let mut all_archives = vec![];
+        for release in &stages.cfg.releases {
+            all_archives.push(stages.build(release).await?);
+        }
+        stages.global_hooks("after").await?;
+        Ok(all_archives)
+    }
+
+    // Runs all the stages for all the releases. Publishing is skipped unless
+    // the `publish` option is set.
+    pub async fn run(self) -> Result<()> {
+        if !self.opts.publish {
+            warn!("--publish isn't given, so skipping publishing")
+        }
+
+        let stages = self
+            .prepare()
+            .await
+            .map_err(|err| Failure::Config.wrap(err))?;
+        let mut releases = vec![];
+        for release in &stages.cfg.releases {
+            let skip =
+                stages.opts.skip_if_no_changes || release.skip_if_no_changes.unwrap_or(false);
+            if skip && !stages.has_changes(release)? {
+                info!(
+                    "no changes since the last release, skipping {}",
+                    release.name
+                );
+                continue;
+            }
+            releases.push(release);
+        }
+        if releases.is_empty() && !stages.cfg.releases.is_empty() {
+            info!("nothing to release");
+            return Ok(());
+        }
+
+        stages.global_hooks("before").await?;
+        let res = stages.run_releases(&releases).await.and_then(|_| {
+            let failed = stages.failed_targets.lock().unwrap();
+            if failed.is_empty() {
+                return Ok(());
+            }
+            Err(Failure::Partial.wrap(eyre!(
+                "{} target(s) failed: {}",
+                failed.len(),
+                failed.join(", ")
+            )))
+        });
+        if let Err(err) = res {
+            stages.error_hooks(&err).await;
+            return Err(err);
+        }
+        stages.global_hooks("after").await?;
+        Ok(())
+    }
+}
+
+impl Stages {
+    // Gets the plugins loaded for the release.
+    fn plugins(&self, release: &Release) -> &[Plugin] {
+        self.plugins
+            .get(&release.name)
+            .map_or(&[], |plugins| plugins.as_slice())
+    }
+
+    // Builds and publishes the releases which weren't skipped.
+    async fn run_releases(&self, releases: &[&Release]) -> Result<()> {
+        for release in releases.iter().copied() {
+            match (&release.when_untagged, self.is_untagged(release)) {
+                (Some(WhenUntagged::Fail), true) => {
+                    return Err(Failure::Publish
+                        .wrap(eyre!("HEAD isn't tagged, failing release {}", release.name)));
+                }
+                (Some(WhenUntagged::Skip), true) => {
+                    warn!("HEAD isn't tagged, skipping publishing {}", release.name);
+                    self.build(release).await?;
+                    continue;
+                }
+                (Some(WhenUntagged::Snapshot), true) => {
+                    let tag = self.snapshot_tag(release)?;
+                    warn!(
+                        "HEAD isn't tagged, building snapshot {} of {}",
+                        tag, release.name
+                    );
+                    self.build_with_tag(release, &tag).await?;
+                    continue;
+                }
+                _ => {}
+            }
+
+            let archives = self
+                .build(release)
+                .await
+                .map_err(|err| Failure::Build.wrap(err))?;
+            if self.opts.publish {
+                self.publish(release, archives)
+                    .await
+                    .map_err(|err| Failure::Publish.wrap(err))?;
+            }
+        }
+        Ok(())
+    }
+
+    // Gets the repo and the tag of the release.
+    fn repo(&self, release: &Release) -> (&GitRepo, &str) {
+        match self.scoped.get(&release.name) {
+            Some((git, tag)) => (git, tag),
+            None => (&self.git, &self.tag),
+        }
+    }
+
+    // Gets the releases the stages run for.
+    pub(crate) fn releases(&self) -> &[Release] {
+        &self.cfg.releases
+    }
+
+    // Checks if HEAD isn't the commit of the release's latest tag, or
+    // there's no tag. An explicit `--to-tag` is never untagged.
+    fn is_untagged(&self, release: &Release) -> bool {
+        if self.opts.to_tag.is_some() {
+            return false;
+        }
+        let (git, _) = self.repo(release);
+        match git.latest_tag() {
+            Ok(tag) => !git.is_head_tagged(&tag).unwrap_or(false),
+            Err(_) => true,
+        }
+    }
+
+    // Checks if the release has changes since the last release, i.e. since
+    // the latest tag, or since the previous tag if HEAD is the latest tag.
+    fn has_changes(&self, release: &Release) -> Result<bool> {
+        let (git, tag) = self.repo(release);
+        let range = if tag.is_empty() {
+            None
+        } else if git.is_head_tagged(tag)? {
+            git.previous_tag(tag)?
+                .map(|prev| format!("{}..{}", prev, tag))
+        } else {
+            Some(format!("{}..HEAD", tag))
+        };
+        git.has_changes(range.as_deref())
+    }
+
+    // Gets the snapshot version for an untagged HEAD, from the latest tag
+    // (or `v0.0.0`) and the short hash of HEAD.
+    fn snapshot_tag(&self, release: &Release) -> Result<String> {
+        let (_, tag) = self.repo(release);
+        let tag = if tag.is_empty() { "v0.0.0" } else { tag };
+        Ok(format!("{}-SNAPSHOT-{}", tag, self.git.head_short()?))
+    }
+
+    // Runs the `before` or `after` hooks from the top level of the config,
+    // which run once for all the releases.
+    async fn global_hooks(&self, name: &str) -> Result<()> {
+        let hooks = self.cfg.hooks.clone().unwrap_or_default();
+        let cmds = match name {
+            "before" => hooks.before,
+            _ => hooks.after,
+        };
+        let cmds = match cmds {
+            Some(cmds) => cmds,
+            None => return Ok(()),
+        };
+        let envs = vec![
+            ("RLSR_TAG".to_string(), self.tag.clone()),
+            (
+                "RLSR_VERSION".to_string(),
+                tag_version(&self.tag).to_string(),
+            ),
+        ];
+        run_hooks(name, &cmds, &self.templates, tag_context(&self.tag), &envs).await
+    }
+
+    // Runs the `on_error` hooks with the error which stopped the releases.
+    // Failing hooks are only logged so that the original error is returned.
+    async fn error_hooks(&self, err: &Report) {
+        let cmds = match self.cfg.hooks.as_ref().and_then(|h| h.on_error.as_ref()) {
+            Some(cmds) => cmds,
+            None => return,
+        };
+        let envs = vec![
+            ("RLSR_TAG".to_string(), self.tag.clone()),
+            (
+                "RLSR_VERSION".to_string(),
+                tag_version(&self.tag).to_string(),
+            ),
+            ("RLSR_ERROR".to_string(), err.to_string()),
+        ];
+        let ctx = context! {
+            error => err.to_string(),
+            ..tag_context(&self.tag)
+        };
+        if let Err(hook_err) = run_hooks("on_error", cmds, &self.templates, ctx, &envs).await {
+            error!("error running on_error hooks: {}", hook_err);
+        }
+    }
+
+    // Runs the builds of the release in parallel and then archives them.
+    // Failed builds and archives are logged and fail the stage once the
+    // others finished. Extra assets are returned along with the archives.
+    pub async fn build(&self, release: &Release) -> Result<Vec<String>> {
+        self.build_with_tag(release, self.repo(release).1).await
+    }
+
+    // Runs the build stage with the given tag in the templates instead of the
+    // latest tag, e.g. for snapshots.
+    pub async fn build_with_tag(&self, release: &Release, tag: &str) -> Result<Vec<String>> {
+        if let Some(tests) = &release.tests {
+            testing::run(release, tests, &self.templates, tag, &self.events).await?;
+        }
+        let cfg = match &release.version_files {
+            Some(cfg) => cfg,
+            None => return self.build_archives(release, tag).await,
+        };
+        let originals = version_files::apply(cfg, &self.templates, tag).await?;
+        // The files are reverted even if the build fails.
+        let res = self.build_archives(release, tag).await;
+        version_files::finish(cfg, originals, tag).await?;
+        res
+    }
+
+    async fn build_archives(&self, release: &Release, tag: &str) -> Result<Vec<String>> {
+        let release = Arc::new(release.clone());
+        // The dist folder is deleted once before the builds, since the builds
+        // would delete each other's artifacts.
+        if self.opts.rm_dist && Utf8Path::new(&release.dist_folder).exists() {
+            fs::remove_dir_all(&release.dist_folder)
+                .await
+                .wrap_err_with(|| format!("error deleting {}", release.dist_folder))?;
+        }
+        let prepared = match &release.prepare {
+            Some(steps) => prepare::run(&release, steps, &self.templates, tag, !self.opts.no_cache)
+                .await
+                .wrap_err_with(|| format!("error preparing {}", release.name))?,
+            None => HashMap::new(),
+        };
+        let all_artifacts: Arc<Mutex<Vec<(usize, String)>>> = Arc::new(Mutex::new(vec![]));
+        let failed: Arc<Mutex<Vec<String>>> = Arc::new(Mutex::new(vec![]));
+        // Builds run in waves, each after the builds it needs finished.
+        for builds in build_waves(&release)? {
+            let done = all_artifacts.lock().await.clone();
+            let mut all_builds = vec![];
+            for b in builds {
+                let mut build = release.builds[b].clone();
+                // Artifacts of the finished builds it needs, keyed by their name.
+                let mut artifacts = HashMap::new();
+                if let Some(needs) = &build.needs {
+                    for (n, artifact) in &done {
+                        if needs.contains(&release.builds[*n].name) {
+                            artifacts.insert(
+                                release.builds[*n].name.clone(),
+                                context! { artifact => artifact },
+                            );
+                        }
+                    }
+                }
+                if build.needs.is_some() || release.prepare.is_some() {
+                    let ctx = context! {
+                        builds => artifacts,
+                        prepared => &prepared,
+                        ..tag_context(tag)
+                    };
+                    build.command = self
+                        .templates
+                        .render(&build.command, ctx)
+                        .wrap_err_with(|| format!("error rendering command of {}", build.name))?;
+                }
+
+                let release = release.clone();
+                let all_artifacts = all_artifacts.clone();
+                let failed = failed.clone();
+                let templates = self.templates.clone();
+                let tag = tag.to_string();
+                let cache = !self.opts.no_cache;
+                let runners = self.cfg.runners.clone().unwrap_or_default();
+                let events = self.events.clone();
+                all_builds.push(tokio::spawn(async move {
+                    info!("executing build: {}", &release.name);
+                    events.emit(Event::BuildStarted {
+                        release: release.name.clone(),
+                        build: build.name.clone(),
+                    });
+                    let runner = runners::find(&runners, &build);
+                    let res = run_build(&release, &build, &templates, &tag, cache, runner).await;
+                    events.emit(Event::BuildFinished {
+                        release: release.name.clone(),
+                        build: build.name.clone(),
+                        success: res.is_ok(),
+                    });
+                    match res {
+                        Err(err) => {
+                            error!("error executing the build: {}", err);
+                            failed.lock().await.push(build.name.clone());
+                        }
+                        Ok(artifact) => all_artifacts.lock().await.push((b, artifact)),
+                    }
+                }));
+            }
+
+            // Wait until all builds of the wave are finished. The next waves
+            // would need the failed builds' artifacts, so they aren't run.
+            for res in futures::future::join_all(&mut all_builds).await {
+                if let Err(err) = res {
+                    error!("build of {} panicked: {}", release.name, err);
+                    failed.lock().await.push(err.to_string());
+                }
+            }
+            let failed = failed.lock().await;
+            if !failed.is_empty() {
+                bail!(
+                    "{} build(s) of {} failed: {}",
+                    failed.len(),
+                    release.name,
+                    failed.join(", ")
+                );
+            }
+        }
+
+        if let Some(cfg) = &release.licenses {
+            licenses::scan(&release, cfg, &self.templates, tag).await?;
+        }
+        let mut artifacts = all_artifacts.lock().await.to_vec();
+        artifacts.sort();
+        let mut archives = self.archive_builds(&release, tag, artifacts).await?;
+        if let Some(cfg) = &release.terraform {
+            let files = terraform::checksums(&release, cfg, tag, &archives).await?;
+            archives.extend(files);
+        }
+        debug!("all archives generated: {:?}", archives);
+
+        if let Some(cfg) = &release.binstall {
+            if let Err(err) = binstall::write_metadata(&release, cfg, &self.templates, tag).await {
+                error!("error writing binstall metadata: {}", err);
+            }
+        }
+
+        sizes::check_budgets(&release, &self.templates, tag, &archives).await?;
+        if let Some(cfg) = &release.benchmarks {
+            archives.push(benchmarks::run(&release, cfg, &self.templates, tag).await?);
+        }
+        if let Some(cfg) = &release.install_script {
+            let scripts =
+                install_script::write_scripts(&release, cfg, &self.templates, tag, &archives)
+                    .await?;
+            archives.extend(scripts);
+        }
+        if let Some(cfg) = &release.vendor {
+            archives.push(vendor::vendor_archive(&release, cfg, &self.templates, tag).await?);
+        }
+        // The chart is packaged with the builds, so that it's published with
+        // the archives as well as pushed by the helm target.
+        if let Some(cfg) = &release.targets.helm {
+            archives.push(helm::package(&release, cfg, tag).await?);
+        }
+        // Extra assets are copied to the dist folder so that all the assets
+        // of the release are in one place.
+        if let Some(extra_assets) = &release.extra_assets {
+            fs::create_dir_all(&release.dist_folder).await?;
+            for asset in expand_globs(extra_assets)? {
+                let name = match Utf8Path::new(&asset).file_name() {
+                    Some(name) => name,
+                    None => continue,
+                };
+                let dest = Utf8Path::new(&release.dist_folder).join(name);
+                debug!("copying extra asset {} to {}", asset, dest);
+                fs::copy(&asset, &dest)
+                    .await
+                    .wrap_err_with(|| format!("error copying extra asset: {}", asset))?;
+                archives.push(dest.to_string());
+            }
+        }
+        // Plugins package last, so that they get all the other assets.
+        let packaged =
+            plugins::package(self.plugins(&release), &release, &self.tag, &archives).await?;
+        archives.extend(packaged);
+        Ok(archives)
+    }
+
+    // Archives the builds' artifacts, as many at a time as there are CPUs.
+    // Artifacts of builds with `no_archive` are returned as they are, unless
+    // the release has the terraform layout, which only has archives. Fails
+    // after all the archives are done if any of them failed.
+    async fn archive_builds(
+        &self,
+        release: &Arc<Release>,
+        tag: &str,
+        artifacts: Vec<(usize, String)>,
+    ) -> Result<Vec<String>> {
+        let jobs = std::thread::available_parallelism().map_or(1, |n| n.get());
+        let permits = Arc::new(Semaphore::new(jobs));
+        let mut all_archives = vec![];
+        for (b, artifact) in artifacts {
+            let release = release.clone();
+            let templates = self.templates.clone();
+            let tag = tag.to_string();
+            let permits = permits.clone();
+            all_archives.push(tokio::spawn(async move {
+                let build = &release.builds[b];
+                if build.no_archive.is_some() && release.terraform.is_none() {
+                    return Ok(artifact);
+                }
+                let _permit = permits.acquire().await?;
+                let start = Instant::now();
+                let archive = archive_build(&release, build, &templates, &tag, artifact).await?;
+                info!("archived {} in {:.2?}", archive, start.elapsed());
+                Ok::<_, Report>(archive)
+            }));
+        }
+
+        let mut archives = vec![];
+        let mut failed = 0;
+        for res in futures::future::join_all(all_archives).await {
+            match res.map_err(Report::from).and_then(|res| res) {
+                Ok(archive) => {
+                    self.events.emit(Event::ArtifactArchived {
+                        release: release.name.clone(),
+                        path: archive.clone(),
+                    });
+                    archives.push(archive);
+                }
+                Err(err) => {
+                    error!("error archiving the build: {}", err);
+                    failed += 1;
+                }
+            }
+        }
+        if failed > 0 {
+            bail!("{} archive(s) of {} failed", failed, release.name);
+        }
+        Ok(archives)
+    }
+
+    // Gets the sha256 checksums of the archives.
+    pub async fn checksum(&self, archives: &[String]) -> Result<Vec<Checksum>> {
+        let mut checksums = vec![];
+        for archive in archives {
+            checksums.push(Checksum {
+                path: archive.clone(),
+                sha256: sha256_file(archive.clone()).await?,
+            });
+        }
+        Ok(checksums)
+    }
+
+    // Publishes the archives of the release to all of its targets, after
+    // generating the changelog for the latest tag.
+    pub async fn publish(
+        &self,
+        release: &Release,
+        archives: Vec<String>,
+    ) -> Result<Vec<PublishResult>> {
+        let (git, _) = self.repo(release);
+        let latest_tag = match release_tag(git, &self.opts) {
+            Ok(tag) => {
+                info!("found out latest tag: {}", tag);
+                tag
+            }
+            Err(err) => {
+                bail!("error finding tag, skipping publishing: {}", err);
+            }
+        };
+        debug!("latest tag: {}", latest_tag);
+
+        let mut prerelease = false;
+        if let Some(version_cfg) = &self.cfg.version {
+            version::validate_tag(version_cfg, &latest_tag)?;
+            prerelease = version::is_prerelease(version_cfg, &latest_tag)?;
+        }
+        if let Some(cargo) = &release.cargo {
+            if cargo.check_version.unwrap_or(true) {
+                cargo::check_versions(cargo, &latest_tag).await?;
+            }
+        }
+
+        let from = self
+            .opts
+            .from
+            .as_deref()
+            .or(self.opts.from_tag.as_deref())
+            .or(self.opts.since_commit.as_deref());
+        let range = changelog_range(git, &latest_tag, from, self.opts.to.as_deref())?;
+        debug!("changelog range: {:?}", range);
+        let mut changelog =
+            get_release_changelog(release, &self.templates, git, range.as_deref(), &latest_tag)
+                .await
+                .wrap_err_with(|| "error getting changelog")?;
+        if self.opts.edit_notes {
+            changelog = edit_notes(&changelog, &release.name)
+                .await
+                .wrap_err_with(|| "error editing release notes")?;
+            if changelog.trim().is_empty() {
+                bail!("release notes are empty, aborting publishing");
+            }
+        }
+        let changelog = truncate_changelog(
+            release,
+            &self.templates,
+            range.as_deref(),
+            &latest_tag,
+            changelog,
+        )?;
+
+        let changelogs = target_changelogs(
+            release,
+            &self.templates,
+            git,
+            range.as_deref(),
+            &latest_tag,
+            &changelog,
+        )?;
+
+        let asset_names = self.asset_names(release, &archives, &latest_tag)?;
+        if let Some(previous_tag) = git.previous_tag(&latest_tag)? {
+            sizes::report(release, &archives, &asset_names, &previous_tag, &latest_tag).await?;
+            if let Some(cfg) = &release.benchmarks {
+                benchmarks::compare(release, cfg, &previous_tag).await?;
+            }
+        }
+
+        if let Some(gates) = &release.gates {
+            gates::wait(release, gates, &self.templates, git, &latest_tag).await?;
+        }
+
+        let mut archives = archives;
+        let with_checksums = release.checksums.unwrap_or(true);
+        let mut checksums = vec![];
+        if with_checksums && !archives.is_empty() {
+            checksums = self.checksum(&archives).await?;
+            archives.push(write_checksums(release, &checksums, &asset_names).await?);
+        }
+
+        if !self.opts.yes {
+            println!(
+                "{}",
+                redact(&publish_summary(release, &latest_tag, archives.len()))
+            );
+            // Non-interactive runs, e.g. in CI or with the config on stdin,
+            // can't be confirmed and publish as before.
+            if interactive() && !confirm("Publish?").await? {
+                bail!("publishing aborted");
+            }
+        }
+
+        let hooks = release.hooks.clone().unwrap_or_default();
+        if let Some(cmds) = &hooks.before_publish {
+            run_hooks(
+                "before_publish",
+                cmds,
+                &self.templates,
+                tag_context(&latest_tag),
+                &hook_envs(release, &latest_tag, &[]),
+            )
+            .await?;
+        }
+
+        self.sync_tag(&latest_tag).await?;
+
+        // Make release providers from given config.
+        let all_archives = Arc::new(Mutex::new(archives.clone()));
+        let providers = get_release_providers(
+            release,
+            prerelease,
+            asset_names.clone(),
+            self.templates.clone(),
+            self.plugins(release),
+        )?;
+        let mut results = vec![];
+        let mut failures = vec![];
+        let mut checksummed = archives.len();
+        for prov in providers {
+            let name = prov.name();
+            let changelog = changelogs.get(&name).unwrap_or(&changelog);
+            let mut attempt = 1;
+            let res = loop {
+                let res = prov
+                    .publish(
+                        release,
+                        all_archives.clone(),
+                        latest_tag.clone(),
+                        changelog.clone(),
+                    )
+                    .await;
+                let retryable = match &res {
+                    Err(err) => err
+                        .downcast_ref::<ProviderError>()
+                        .is_some_and(ProviderError::is_retryable),
+                    Ok(_) => false,
+                };
+                if !retryable || attempt >= PUBLISH_ATTEMPTS {
+                    break res;
+                }
+                let delay = RETRY_DELAY * attempt;
+                warn!(
+                    "{} failed, retrying in {}s: {}",
+                    name,
+                    delay.as_secs(),
+                    res.err().map(|err| err.to_string()).unwrap_or_default()
+                );
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            };
+            // Targets can add assets for the targets after them, e.g. docker's
+            // `images.txt`, which are added to the checksums.
+            let mut assets = all_archives.lock().await;
+            let added = assets[checksummed..].to_vec();
+            if with_checksums && !added.is_empty() {
+                checksums.extend(self.checksum(&added).await?);
+                let path = write_checksums(release, &checksums, &asset_names).await?;
+                if !assets.contains(&path) {
+                    assets.push(path);
+                }
+            }
+            checksummed = assets.len();
+            drop(assets);
+            match res {
+                Ok(result) => {
+                    for url in &result.asset_urls {
+                        self.events.emit(Event::AssetUploaded {
+                            release: release.name.clone(),
+                            provider: result.provider.clone(),
+                            url: url.clone(),
+                        });
+                    }
+                    self.events.emit(Event::ReleasePublished {
+                        release: release.name.clone(),
+                        tag: latest_tag.clone(),
+                        result: result.clone(),
+                    });
+                    results.push(result);
+                }
+                Err(err) => {
+                    error!("{} failed: {}", name, err);
+                    let kind = err.downcast_ref::<ProviderError>();
+                    let reason = match kind {
+                        Some(kind) => format!("({}) {}", kind.kind(), err),
+                        None => err.to_string(),
+                    };
+                    failures.push((name.clone(), reason));
+                    self.failed_targets
+                        .lock()
+                        .unwrap()
+                        .push(format!("{} of {}", name, release.name));
+                    // Other targets would most likely fail with the same
+                    // credentials, so publishing stops at auth errors, and
+                    // at uploaded assets which don't match the files.
+                    let mismatched = Failure::of(&err) == Some(Failure::Verification);
+                    if mismatched || matches!(kind, Some(ProviderError::Auth(_))) {
+                        println!(
+                            "{}",
+                            redact(&published_summary(release, &results, &failures))
+                        );
+                        let err = eyre!(
+                            "publishing {} aborted, {} failed: {}",
+                            release.name,
+                            name,
+                            err
+                        );
+                        return Err(match mismatched {
+                            true => Failure::Verification.wrap(err),
+                            false => err,
+                        });
+                    }
+                }
+            }
+        }
+        write_results(release, &results).await?;
+        println!(
+            "{}",
+            redact(&published_summary(release, &results, &failures))
+        );
+        if results.is_empty() && !failures.is_empty() {
+            bail!("all the targets of {} failed", release.name);
+        }
+
+        // Snapshots of untagged commits aren't announced.
+        if !(release.when_untagged.is_some() && self.is_untagged(release)) {
+            let archives = all_archives.lock().await.clone();
+            let failed = plugins::announce(
+                self.plugins(release),
+                &self.templates,
+                release,
+                Announcement {
+                    tag: &latest_tag,
+                    prerelease,
+                    changelog: &changelog,
+                    archives: &archives,
+                    asset_names: &asset_names,
+                    results: &results,
+                },
+            )
+            .await;
+            for plugin in failed {
+                self.failed_targets
+                    .lock()
+                    .unwrap()
+                    .push(format!("{} of {}", plugin, release.name));
+            }
+        }
+
+        if let Some(dispatches) = &release.dispatch {
+            let payload = dispatch::payload(
+                release,
+                &latest_tag,
+                prerelease,
+                &changelog,
+                &archives,
+                &asset_names,
+                &results,
+            )
+            .await?;
+            // Failed dispatches don't undo the release, they're reported
+            // like failed targets.
+            for cfg in dispatches {
+                if let Err(err) =
+                    dispatch::dispatch(cfg, &self.templates, &latest_tag, &payload).await
+                {
+                    error!("dispatch failed: {}", err);
+                    self.failed_targets
+                        .lock()
+                        .unwrap()
+                        .push(format!("dispatch of {}", release.name));
+                }
+            }
+        }
+
+        if let Some(cfg) = &release.docs {
+            let remote = cfg
+                .remote
+                .as_deref()
+                .or_else(|| self.cfg.git.as_ref().and_then(|g| g.remote.as_deref()))
+                .unwrap_or("origin");
+            if let Err(err) = docs::deploy(release, cfg, &self.templates, &latest_tag, remote).await
+            {
+                error!("deploying docs failed: {}", err);
+                self.failed_targets
+                    .lock()
+                    .unwrap()
+                    .push(format!("docs of {}", release.name));
+            }
+        }
+
+        if let Some(cmds) = &hooks.after_publish {
+            let release_url = results.iter().find_map(|r| r.release_url.clone());
+            let release_id = results.iter().find_map(|r| r.release_id.clone());
+            let asset_urls: Vec<String> =
+                results.iter().flat_map(|r| r.asset_urls.clone()).collect();
+            let ctx = context! {
+                release_url => release_url.clone().unwrap_or_default(),
+                release => context! {
+                    url => release_url.unwrap_or_default(),
+                    id => release_id.unwrap_or_default(),
+                    asset_urls => asset_urls,
+                },
+                results => Serde(&results),
+                ..tag_context(&latest_tag)
+            };
+            run_hooks(
+                "after_publish",
+                cmds,
+                &self.templates,
+                ctx,
+                &hook_envs(release, &latest_tag, &results),
+            )
+            .await?;
+        }
+        Ok(results)
+    }
+
+    // Pushes the tag if `git.push_tag` is set and the remote doesn't have it.
+    async fn sync_tag(&self, tag: &str) -> Result<()> {
+        let git = match &self.cfg.git {
+            Some(git) if git.push_tag.unwrap_or(false) => git,
+            _ => return Ok(()),
+        };
+        let remote = git.remote.as_deref().unwrap_or("origin");
+        if remote_has_tag(remote, tag).await? {
+            debug!("tag {} already exists in {}", tag, remote);
+            return Ok(());
+        }
+        info!("pushing tag {} to {}", tag, remote);
+        push_tag(remote, tag).await
+    }
+
+    // Gets the published names of the archives. Archives are matched back to
+    // their builds for the target.
+    pub(crate) fn asset_names(
+        &self,
+        release: &Release,
+        archives: &[String],
+        latest_tag: &str,
+    ) -> Result<AssetNames> {
+        let mut targets = HashMap::new();
+        for build in &release.builds {
+            let name = self
+                .templates
+                .render(&build.name, tag_context(self.repo(release).1))?;
+            targets.insert(archive_name(release, build, &name), build.target.clone());
+            targets.insert(name, build.target.clone());
+        }
+
+        let mut names = HashMap::new();
+        let mut archive_targets = HashMap::new();
+        let checksums = Utf8Path::new(&release.dist_folder).join(CHECKSUMS_FILE);
+        for archive in archives {
+            // The checksums keep their name, so that they can be found.
+            if Utf8Path::new(archive) == checksums {
+                continue;
+            }
+            let file_name = Utf8Path::new(archive).file_name().unwrap_or_default();
+            let target = targets.get(file_name).cloned().flatten();
+            if let Some(target) = &target {
+                archive_targets.insert(archive.clone(), target.clone());
+            }
+            let name = asset_name(
+                &self.templates,
+                release,
+                latest_tag,
+                file_name,
+                target.as_deref(),
+            )?;
+            names.insert(archive.clone(), name);
+        }
+        Ok(AssetNames::new(names).with_targets(archive_targets))
+    }
+}
+
+// Writes the checksums of the assets to `checksums.txt` in the dist folder,
+// as `<sha256>  <name>` lines like sha256sum's, with the names the assets are
+// published with.
+async fn write_checksums(
+    release: &Release,
+    checksums: &[Checksum],
+    names: &AssetNames,
+) -> Result<String> {
+    let path = Utf8Path::new(&release.dist_folder).join(CHECKSUMS_FILE);
+    let mut contents = String::new();
+    for checksum in checksums {
+        contents.push_str(&format!(
+            "{}  {}\n",
+            checksum.sha256,
+            names.get(&checksum.path)
+        ));
+    }
+    fs::write(&path, contents)
+        .await
+        .wrap_err_with(|| format!("error writing {}", path))?;
+    debug!("wrote checksums to {}", path);
+    Ok(path.to_string())
+}
+
+// Writes what the targets published to `artifacts.json` in the dist folder,
+// for steps after rlsr, e.g. to link the uploaded assets.
+async fn write_results(release: &Release, results: &[PublishResult]) -> Result<()> {
+    fs::create_dir_all(&release.dist_folder).await?;
+    let path = Utf8Path::new(&release.dist_folder).join(RESULTS_FILE);
+    fs::write(&path, serde_json::to_string_pretty(results)?)
+        .await
+        .wrap_err_with(|| format!("error writing {}", path))?;
+    debug!("wrote publish results to {}", path);
+    Ok(())
+}
+
+// Groups the release's builds into waves by their `needs`: the first wave has
+// the builds without needs and every other wave the builds which only need
+// builds of the earlier waves. Returns the indexes of the builds.
+pub(crate) fn build_waves(release: &Release) -> Result<Vec<Vec<usize>>> {
+    let names: Vec<&str> = release.builds.iter().map(|b| b.name.as_str()).collect();
+    for build in &release.builds {
+        for need in build.needs.iter().flatten() {
+            if !names.contains(&need.as_str()) {
+                bail!(
+                    "build {} needs {}, which isn't a build of {}",
+                    build.name,
+                    need,
+                    release.name
+                );
+            }
+        }
+    }
+
+    let mut waves: Vec<Vec<usize>> = vec![];
+    let mut waiting: Vec<usize> = (0..release.builds.len()).collect();
+    let mut done: Vec<&str> = vec![];
+    while !waiting.is_empty() {
+        let (ready, rest): (Vec<usize>, Vec<usize>) = waiting.into_iter().partition(|b| {
+            release.builds[*b]
+                .needs
+                .iter()
+                .flatten()
+                .all(|n| done.contains(&n.as_str()))
+        });
+        if ready.is_empty() {
+            let cycle: Vec<&str> = rest.iter().map(|b| names[*b]).collect();
+            bail!("builds {} need each other", cycle.join(", "));
+        }
+        done.extend(ready.iter().map(|b| names[*b]));
+        waves.push(ready);
+        waiting = rest;
+    }
+    Ok(waves)
+}
+
+// Gets the tag being released, which is `--to-tag` if it's given or the
+// latest tag.
+fn release_tag(git: &GitRepo, opts: &Opts) -> Result<String> {
+    match &opts.to_tag {
+        Some(tag) => {
+            git.tag_commit(tag)?;
+            Ok(tag.clone())
+        }
+        None => git.latest_tag(),
+    }
+}
+
+// Gets the env vars for the release's hooks. After publishing, they have the
+// release url and id and the asset urls (one per line) from the targets.
+fn hook_envs(release: &Release, tag: &str, results: &[PublishResult]) -> Vec<(String, String)> {
+    let mut envs = vec![
+        ("RLSR_RELEASE".to_string(), release.name.clone()),
+        ("RLSR_TAG".to_string(), tag.to_string()),
+        ("RLSR_VERSION".to_string(), tag_version(tag).to_string()),
+    ];
+    if let Some(url) = results.iter().find_map(|r| r.release_url.clone()) {
+        envs.push(("RLSR_RELEASE_URL".to_string(), url));
+    }
+    if let Some(id) = results.iter().find_map(|r| r.release_id.clone()) {
+        envs.push(("RLSR_RELEASE_ID".to_string(), id));
+    }
+    let asset_urls: Vec<String> = results.iter().flat_map(|r| r.asset_urls.clone()).collect();
+    if !asset_urls.is_empty() {
+        envs.push(("RLSR_ASSET_URLS".to_string(), asset_urls.join("\n")));
+    }
+    envs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn release(builds: &str) -> Release {
+        serde_yaml::from_str(&format!(
+            "name: r\ndist_folder: dist\ntargets: {{}}\nbuilds:\n{}",
+            builds
+        ))
+        .unwrap()
+    }
+
+    fn build(name: &str, needs: &[&str]) -> String {
+        format!(
+            "  - name: {}\n    command: \"true\"\n    bin_name: {}\n    artifact: {}\n    needs: [{}]\n",
+            name,
+            name,
+            name,
+            needs.join(", ")
+        )
+    }
+
+    #[test]
+    fn groups_builds_into_waves() {
+        let release = release(
+            &[
+                build("app", &["lib", "assets"]),
+                build("lib", &[]),
+                build("assets", &[]),
+                build("installer", &["app"]),
+            ]
+            .concat(),
+        );
+        assert_eq!(
+            build_waves(&release).unwrap(),
+            vec![vec![1, 2], vec![0], vec![3]]
+        );
+    }
+
+    #[test]
+    fn fails_on_cycles() {
+        let release = release(
+            &[
+                build("a", &["b"]),
+                build("b", &["c"]),
+                build("c", &["a"]),
+                build("d", &[]),
+            ]
+            .concat(),
+        );
+        let err = build_waves(&release).unwrap_err().to_string();
+        assert_eq!(err, "builds a, b, c need each other");
+    }
+
+    #[test]
+    fn fails_on_unknown_needs() {
+        let release = release(&build("a", &["missing"]));
+        let err = build_waves(&release).unwrap_err().to_string();
+        assert_eq!(err, "build a needs missing, which isn't a build of r");
+    }
+}
@@ -0,0 +1,464 @@
+use crate::config::{Plugin as PluginCfg, Release};
+use crate::release_provider::{AssetNames, PublishResult, ReleaseProvider};
+use crate::templating::{tag_context, Templates};
+use crate::utils::sha256_file;
+use async_trait::async_trait;
+use camino::Utf8Path;
+use eyre::{bail, eyre, Context, Result};
+use libloading::{library_filename, Library, Symbol};
+use log::{debug, error, info};
+use minijinja::value::Serde;
+use minijinja::{context, Value as MiniValue};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::ffi::{c_char, CStr, CString};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tokio::task;
+
+// Version of the plugin interface. Plugins export `rlsr_plugin_api`
+// returning the version they're built for, and plugins for another version
+// aren't loaded.
+pub const PLUGIN_API: u32 = 1;
+
+// Folder plugins are loaded from without `plugins_dir`.
+const PLUGINS_DIR: &str = ".rlsr/plugins";
+
+// Hooks take the payload as a JSON C string and return the result as one,
+// which is given back to the plugin's `rlsr_free`.
+type ApiFn = unsafe extern "C" fn() -> u32;
+type HookFn = unsafe extern "C" fn(*const c_char) -> *mut c_char;
+type FreeFn = unsafe extern "C" fn(*mut c_char);
+
+// Gets the path of the plugin's library, its `path` or the library named
+// after it in the plugins folder, e.g. `libslack.so` on linux.
+fn library_path(cfg: &PluginCfg, plugins_dir: Option<&str>) -> String {
+    match &cfg.path {
+        Some(path) => path.clone(),
+        None => Utf8Path::new(plugins_dir.unwrap_or(PLUGINS_DIR))
+            .join(library_filename(&cfg.name).to_string_lossy().as_ref())
+            .to_string(),
+    }
+}
+
+/// Plugin is a plugin loaded from a dynamic library. It can export any of
+/// the hooks: `rlsr_package` adds files to the release's assets after the
+/// builds, `rlsr_publish` publishes the release like a target and
+/// `rlsr_announce` announces it after it's published.
+#[derive(Clone)]
+pub struct Plugin {
+    cfg: PluginCfg,
+    lib: Arc<Library>,
+}
+
+impl Plugin {
+    // Loads the plugin's library and checks that it's built for this
+    // version of the plugin interface.
+    pub fn load(cfg: &PluginCfg, plugins_dir: Option<&str>) -> Result<Self> {
+        let path = library_path(cfg, plugins_dir);
+        // Loading the library runs its initializers, plugins are trusted like
+        // the commands in the config.
+        let lib = unsafe { Library::new(&path) }
+            .wrap_err_with(|| format!("error loading plugin {} from {}", cfg.name, path))?;
+        let api = unsafe { lib.get::<ApiFn>(b"rlsr_plugin_api\0") }
+            .map(|api| unsafe { api() })
+            .wrap_err_with(|| format!("plugin {} doesn't export rlsr_plugin_api", cfg.name))?;
+        if api != PLUGIN_API {
+            bail!(
+                "plugin {} is built for plugin api {}, rlsr supports {}",
+                cfg.name,
+                api,
+                PLUGIN_API
+            );
+        }
+        debug!("loaded plugin {} from {}", cfg.name, path);
+        Ok(Plugin {
+            cfg: cfg.clone(),
+            lib: Arc::new(lib),
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.cfg.name
+    }
+
+    // Checks if the plugin exports the hook, e.g. `publish`.
+    pub fn exports(&self, hook: &str) -> bool {
+        let symbol = format!("rlsr_{}\0", hook);
+        unsafe { self.lib.get::<HookFn>(symbol.as_bytes()) }.is_ok()
+    }
+
+    // Calls the hook with the payload and the plugin's config, on a blocking
+    // thread since plugins can block. A result with an `error` fails the
+    // hook.
+    pub async fn call(&self, hook: &str, mut payload: Value) -> Result<Value> {
+        payload["config"] = self.cfg.config.clone().unwrap_or(Value::Null);
+        let payload = CString::new(payload.to_string())?;
+        let lib = self.lib.clone();
+        let name = self.cfg.name.clone();
+        let symbol = format!("rlsr_{}\0", hook);
+        let output = task::spawn_blocking(move || -> Result<String> {
+            unsafe {
+                let hook: Symbol<HookFn> = lib.get(symbol.as_bytes())?;
+                let free: Symbol<FreeFn> = lib.get(b"rlsr_free\0")?;
+                let ptr = hook(payload.as_ptr());
+                if ptr.is_null() {
+                    bail!("plugin {} returned no result", name);
+                }
+                let output = CStr::from_ptr(ptr).to_string_lossy().to_string();
+                free(ptr);
+                Ok(output)
+            }
+        })
+        .await??;
+
+        let result: Value = match output.trim() {
+            "" => Value::Null,
+            output => serde_json::from_str(output).wrap_err_with(|| {
+                format!("plugin {} returned invalid JSON: {}", self.cfg.name, output)
+            })?,
+        };
+        if let Some(err) = result.get("error").and_then(Value::as_str) {
+            return Err(eyre!("plugin {} failed: {}", self.cfg.name, err));
+        }
+        Ok(result)
+    }
+}
+
+// Loads the release's plugins. They're loaded once per release and shared by
+// its hooks.
+pub fn load_all(release: &Release, plugins_dir: Option<&str>) -> Result<Vec<Plugin>> {
+    release
+        .plugins
+        .iter()
+        .flatten()
+        .map(|cfg| Plugin::load(cfg, plugins_dir))
+        .collect()
+}
+
+// Gets the release's assets for the plugins, like the custom targets get them.
+async fn artifacts(archives: &[String], asset_names: &AssetNames) -> Result<Vec<Value>> {
+    let mut artifacts = vec![];
+    for archive in archives {
+        artifacts.push(json!({
+            "path": archive,
+            "name": asset_names.get(archive),
+            "sha256": sha256_file(archive.clone()).await?,
+        }));
+    }
+    Ok(artifacts)
+}
+
+#[derive(Deserialize)]
+struct Packaged {
+    #[serde(default)]
+    files: Vec<String>,
+}
+
+// Runs the `package` hook of the plugins with the archives, and returns the
+// files they made to publish with them.
+pub async fn package(
+    plugins: &[Plugin],
+    release: &Release,
+    tag: &str,
+    archives: &[String],
+) -> Result<Vec<String>> {
+    let mut files = vec![];
+    for plugin in plugins.iter().filter(|p| p.exports("package")) {
+        info!("packaging with plugin {}", plugin.name());
+        let payload = json!({
+            "release": release.name,
+            "tag": tag,
+            "dist_folder": release.dist_folder,
+            "artifacts": artifacts(archives, &AssetNames::default()).await?,
+        });
+        let result = plugin.call("package", payload).await?;
+        let packaged: Packaged =
+            serde_json::from_value(result).unwrap_or(Packaged { files: vec![] });
+        for file in packaged.files {
+            if !Utf8Path::new(&file).is_file() {
+                bail!(
+                    "plugin {} packaged {}, which doesn't exist",
+                    plugin.name(),
+                    file
+                );
+            }
+            files.push(file);
+        }
+    }
+    Ok(files)
+}
+
+/// Announcement is what the `announce` hook of the plugins is given about
+/// the published release.
+pub struct Announcement<'a> {
+    pub tag: &'a str,
+    pub prerelease: bool,
+    pub changelog: &'a str,
+    pub archives: &'a [String],
+    pub asset_names: &'a AssetNames,
+    pub results: &'a [PublishResult],
+}
+
+// Gets the published assets with their download urls, found by the asset
+// names in the urls the targets returned.
+async fn announced_artifacts(announcement: &Announcement<'_>) -> Result<Vec<Value>> {
+    let mut artifacts = artifacts(announcement.archives, announcement.asset_names).await?;
+    for artifact in artifacts.iter_mut() {
+        let suffix = format!("/{}", artifact["name"].as_str().unwrap_or_default());
+        let url = announcement
+            .results
+            .iter()
+            .flat_map(|r| r.asset_urls.iter())
+            .find(|url| url.ends_with(&suffix));
+        artifact["url"] = json!(url);
+    }
+    Ok(artifacts)
+}
+
+// Gets a markdown table of the artifacts, linking the ones with urls.
+fn artifacts_table(artifacts: &[Value]) -> String {
+    let mut table = "| Artifact | SHA256 |\n| --- | --- |\n".to_string();
+    for artifact in artifacts {
+        let name = artifact["name"].as_str().unwrap_or_default();
+        let sha256 = artifact["sha256"].as_str().unwrap_or_default();
+        match artifact["url"].as_str() {
+            Some(url) => table.push_str(&format!("| [{}]({}) | `{}` |\n", name, url, sha256)),
+            None => table.push_str(&format!("| {} | `{}` |\n", name, sha256)),
+        }
+    }
+    table
+}
+
+// Renders every string in the message as a template, so that structured
+// messages like slack blocks or discord embeds can be given in the config.
+fn render_message(templates: &Templates, message: &Value, ctx: &MiniValue) -> Result<Value> {
+    Ok(match message {
+        Value::String(tmpl) => Value::String(templates.render(tmpl, ctx.clone())?),
+        Value::Array(items) => Value::Array(
+            items
+                .iter()
+                .map(|item| render_message(templates, item, ctx))
+                .collect::<Result<_>>()?,
+        ),
+        Value::Object(fields) => Value::Object(
+            fields
+                .iter()
+                .map(|(k, v)| Ok((k.clone(), render_message(templates, v, ctx)?)))
+                .collect::<Result<_>>()?,
+        ),
+        other => other.clone(),
+    })
+}
+
+// Builds the payload of the `announce` hook for the plugin, with its
+// rendered `message`.
+fn announce_payload(
+    plugin: &Plugin,
+    templates: &Templates,
+    release: &Release,
+    announcement: &Announcement<'_>,
+    artifacts: &[Value],
+) -> Result<Value> {
+    let download_links: Vec<String> = artifacts
+        .iter()
+        .filter_map(|a| a["url"].as_str().map(String::from))
+        .collect();
+    let release_url = announcement
+        .results
+        .iter()
+        .find_map(|r| r.release_url.clone());
+    let message = match &plugin.cfg.message {
+        Some(message) => {
+            let ctx = context! {
+                release => release.name.clone(),
+                release_url => release_url.clone().unwrap_or_default(),
+                changelog => announcement.changelog,
+                artifacts => Serde(artifacts),
+                artifacts_table => artifacts_table(artifacts),
+                download_links => download_links.clone(),
+                ..tag_context(announcement.tag)
+            };
+            render_message(templates, message, &ctx).wrap_err_with(|| {
+                format!("error rendering the message of plugin {}", plugin.name())
+            })?
+        }
+        None => Value::Null,
+    };
+    Ok(json!({
+        "release": release.name,
+        "tag": announcement.tag,
+        "changelog": announcement.changelog,
+        "results": announcement.results,
+        "release_url": release_url,
+        "artifacts": artifacts,
+        "download_links": download_links,
+        "message": message,
+    }))
+}
+
+// Checks the plugin's `when` to see if it announces the release. It's
+// rendered with the tag and `prerelease`, and is false if it renders to
+// `false` or nothing.
+fn announces(templates: &Templates, when: &str, tag: &str, prerelease: bool) -> Result<bool> {
+    let ctx = context! {
+        prerelease => prerelease,
+        ..tag_context(tag)
+    };
+    let rendered = templates.render(when, ctx)?.trim().to_lowercase();
+    Ok(!matches!(rendered.as_str(), "" | "false"))
+}
+
+// Runs the `announce` hook of the plugins after the release is published.
+// Returns the plugins which failed, after logging why, since the release is
+// already out.
+pub async fn announce(
+    plugins: &[Plugin],
+    templates: &Templates,
+    release: &Release,
+    announcement: Announcement<'_>,
+) -> Vec<String> {
+    let mut failed = vec![];
+    let mut announcing = vec![];
+    for plugin in plugins.iter().filter(|p| p.exports("announce")) {
+        let when = match &plugin.cfg.when {
+            Some(when) => announces(templates, when, announcement.tag, announcement.prerelease)
+                .wrap_err_with(|| format!("error checking when of plugin {}", plugin.name())),
+            None => Ok(true),
+        };
+        match when {
+            Ok(true) => announcing.push(plugin),
+            Ok(false) => debug!("skipping announcing with plugin {}", plugin.name()),
+            Err(err) => {
+                error!("{:#}", err);
+                failed.push(plugin.name().to_string());
+            }
+        }
+    }
+    let plugins = announcing;
+    if plugins.is_empty() {
+        return failed;
+    }
+    let artifacts = match announced_artifacts(&announcement).await {
+        Ok(artifacts) => artifacts,
+        Err(err) => {
+            error!("error getting the artifacts to announce: {}", err);
+            failed.extend(plugins.iter().map(|p| p.name().to_string()));
+            return failed;
+        }
+    };
+    for plugin in plugins {
+        info!("announcing with plugin {}", plugin.name());
+        let payload = match announce_payload(plugin, templates, release, &announcement, &artifacts)
+        {
+            Ok(payload) => payload,
+            Err(err) => {
+                error!("{:#}", err);
+                failed.push(plugin.name().to_string());
+                continue;
+            }
+        };
+        if let Err(err) = plugin.call("announce", payload).await {
+            error!("{}", err);
+            failed.push(plugin.name().to_string());
+        }
+    }
+    failed
+}
+
+/// PluginTarget publishes the release with a plugin's `rlsr_publish` hook. It
+/// gets the same payload as the custom targets, and returns a publish result.
+pub struct PluginTarget {
+    plugin: Plugin,
+    prerelease: bool,
+    asset_names: AssetNames,
+}
+
+impl PluginTarget {
+    pub fn new(plugin: Plugin, prerelease: bool, asset_names: AssetNames) -> Self {
+        PluginTarget {
+            plugin,
+            prerelease,
+            asset_names,
+        }
+    }
+}
+
+#[allow(clippy::needless_arbitrary_self_type)]
+#[async_trait]
+impl ReleaseProvider for PluginTarget {
+    fn name(self: &Self) -> String {
+        self.plugin.name().to_string()
+    }
+
+    async fn publish(
+        self: &Self,
+        release: &Release,
+        all_archives: Arc<Mutex<Vec<String>>>,
+        latest_tag: String,
+        changelog: String,
+    ) -> Result<PublishResult> {
+        let archives = all_archives.lock().await.clone();
+        let payload = json!({
+            "release": release.name,
+            "tag": latest_tag,
+            "prerelease": self.prerelease,
+            "changelog": changelog,
+            "artifacts": artifacts(&archives, &self.asset_names).await?,
+        });
+        info!("publishing with plugin {}", self.plugin.name());
+        let result = self.plugin.call("publish", payload).await?;
+        let mut result: PublishResult = match result {
+            Value::Null => PublishResult::default(),
+            result => serde_json::from_value(result).wrap_err_with(|| {
+                format!("plugin {} returned an invalid result", self.plugin.name())
+            })?,
+        };
+        if result.provider.is_empty() {
+            result.provider = self.plugin.name().to_string();
+        }
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_structured_messages() {
+        let artifacts = vec![
+            json!({"name": "app.zip", "sha256": "abc", "url": "https://x/app.zip"}),
+            json!({"name": "app.tar.gz", "sha256": "def", "url": null}),
+        ];
+        let message = json!({
+            "text": "{{ tag }} is out",
+            "blocks": [{"type": "section", "text": "{{ artifacts_table }}"}],
+            "unfurl": false,
+        });
+        let ctx = context! {
+            artifacts_table => artifacts_table(&artifacts),
+            ..tag_context("v1.0.0")
+        };
+        let rendered = render_message(&Templates::new(None, None), &message, &ctx).unwrap();
+        assert_eq!(rendered["text"], "v1.0.0 is out");
+        assert_eq!(
+            rendered["blocks"][0]["text"],
+            "| Artifact | SHA256 |\n| --- | --- |\n\
+             | [app.zip](https://x/app.zip) | `abc` |\n\
+             | app.tar.gz | `def` |\n"
+        );
+        assert_eq!(rendered["unfurl"], false);
+    }
+
+    #[test]
+    fn checks_when() {
+        let templates = Templates::new(None, None);
+        let when = "{{ not prerelease }}";
+        assert!(announces(&templates, when, "v1.0.0", false).unwrap());
+        assert!(!announces(&templates, when, "v1.1.0-rc.1", true).unwrap());
+        let when = "{% if version == '1.0.0' %}yes{% endif %}";
+        assert!(announces(&templates, when, "v1.0.0", false).unwrap());
+        assert!(!announces(&templates, when, "v2.0.0", false).unwrap());
+    }
+}
@@ -0,0 +1,422 @@
+use crate::config::{Config, ContainerBackend, PublishFrom, Release, Requirement};
+use crate::docker::{backend_bin, check_push_auth};
+use crate::git::GitRepo;
+use crate::utils::{find_binary, format_size, parse_size};
+use camino::Utf8Path;
+use eyre::{bail, eyre, Result};
+use log::{debug, warn};
+use regex::Regex;
+use std::process::Command;
+use std::{env, fs};
+
+// Number of dirty files listed before the rest are summarized.
+const MAX_DIRTY_FILES: usize = 10;
+
+// Checks that everything needed for the release is in place and reports what
+// isn't: uncommitted changes, an untagged HEAD, missing tokens and missing
+// tools. Problems which would make publishing fail are errors when
+// publishing, a dirty repo is an error only with `require_clean`.
+pub fn preflight(cfg: &Config, git: &GitRepo, publish: bool, require_clean: bool) -> Result<()> {
+    let mut problems = vec![];
+
+    let dirty = git.dirty_files()?;
+    if dirty.is_empty() {
+        debug!("preflight: repo is clean");
+    } else {
+        let mut files = dirty
+            .iter()
+            .take(MAX_DIRTY_FILES)
+            .map(|f| format!("  {}", f))
+            .collect::<Vec<_>>();
+        if dirty.len() > MAX_DIRTY_FILES {
+            files.push(format!("  ... and {} more", dirty.len() - MAX_DIRTY_FILES));
+        }
+        let msg = format!(
+            "repo has uncommitted changes in {} file(s):\n{}",
+            dirty.len(),
+            files.join("\n")
+        );
+        if require_clean {
+            problems.push(msg);
+        } else {
+            warn!("preflight: {}", msg);
+        }
+    }
+
+    match git.latest_tag() {
+        Ok(tag) => match git.is_head_tagged(&tag) {
+            Ok(true) => debug!("preflight: HEAD is tagged with {}", tag),
+            _ => warn!(
+                "preflight: HEAD isn't tagged, the latest tag {} is on an older commit",
+                tag
+            ),
+        },
+        Err(_) if publish => {
+            problems.push("no release tag found, tag the commit to publish".into())
+        }
+        Err(_) => warn!("preflight: no release tag found"),
+    }
+
+    let needs_github = cfg
+        .releases
+        .iter()
+        .any(|r| r.targets.github.is_some() || r.targets.versions_manifest.is_some());
+    if needs_github {
+        if env::var("GITHUB_TOKEN")
+            .map(|t| !t.is_empty())
+            .unwrap_or(false)
+        {
+            debug!("preflight: GITHUB_TOKEN is set");
+        } else if publish {
+            problems.push("GITHUB_TOKEN isn't set, it's needed for the github targets".into());
+        } else {
+            warn!("preflight: GITHUB_TOKEN isn't set, publishing to github would fail");
+        }
+    }
+
+    let mut tools = vec!["git"];
+    for release in &cfg.releases {
+        if let Some(docker) = &release.targets.docker {
+            tools.push(backend_bin(
+                docker.backend.as_ref().unwrap_or(&ContainerBackend::Docker),
+            ));
+            if docker.create_repository.unwrap_or(false) {
+                match docker.image.contains("-docker.pkg.dev/") {
+                    true => tools.push("gcloud"),
+                    false => tools.push("aws"),
+                }
+            }
+        }
+        if release.targets.oras.is_some() {
+            tools.push("oras");
+        }
+        if release
+            .targets
+            .azure_devops
+            .as_ref()
+            .is_some_and(|a| a.feed.is_some())
+        {
+            tools.push("az");
+        }
+        if release.builds.iter().any(|b| b.container.is_some()) {
+            tools.push("docker");
+        }
+    }
+    tools.sort_unstable();
+    tools.dedup();
+    for tool in tools {
+        if find_binary(tool) {
+            debug!("preflight: found {}", tool);
+        } else if publish {
+            problems.push(format!("{} isn't installed or isn't in PATH", tool));
+        } else {
+            warn!("preflight: {} isn't installed or isn't in PATH", tool);
+        }
+    }
+
+    for release in &cfg.releases {
+        match check_disk_space(release) {
+            Ok(()) => {}
+            Err(err) if publish => problems.push(err.to_string()),
+            Err(err) => warn!("preflight: {}", err),
+        }
+    }
+    for release in &cfg.releases {
+        let docker = match &release.targets.docker {
+            Some(docker) => docker,
+            None => continue,
+        };
+        let backend = docker.backend.clone().unwrap_or(ContainerBackend::Docker);
+        if let Err(err) = check_docker_space(&backend, release) {
+            warn!("preflight: {}", err);
+        }
+        match check_push_auth(&docker.image, &backend) {
+            Ok(()) => debug!("preflight: found credentials for {}", docker.image),
+            Err(err) if publish => problems.push(format!("{:#}", err)),
+            Err(err) => warn!("preflight: {:#}, pushing would fail", err),
+        }
+    }
+    for release in &cfg.releases {
+        // Oras reads docker's credentials.
+        if let Some(oras) = &release.targets.oras {
+            match check_push_auth(&oras.repository, &ContainerBackend::Docker) {
+                Ok(()) => debug!("preflight: found credentials for {}", oras.repository),
+                Err(err) if publish => problems.push(format!("{:#}", err)),
+                Err(err) => warn!("preflight: {:#}, pushing would fail", err),
+            }
+        }
+    }
+
+    if let Some(publish_from) = cfg.publish_from.as_ref().filter(|_| publish) {
+        if let Err(err) = check_publish_from(publish_from, git) {
+            problems.push(err.to_string());
+        }
+    }
+    if let Some(err) = check_shell_wrapper(cfg) {
+        problems.push(err);
+    }
+    for requirement in cfg.requirements.as_deref().unwrap_or_default() {
+        match check_requirement(requirement) {
+            Ok(()) => debug!("preflight: found {}", requirement.tool),
+            Err(err) => match &requirement.hint {
+                Some(hint) => problems.push(format!("{} ({})", err, hint)),
+                None => problems.push(err.to_string()),
+            },
+        }
+    }
+
+    if !problems.is_empty() {
+        bail!(
+            "preflight checks failed:\n{}",
+            problems
+                .iter()
+                .map(|p| format!("- {}", p))
+                .collect::<Vec<_>>()
+                .join("\n")
+        );
+    }
+    Ok(())
+}
+
+// Checks that the program of the shell wrapper is installed, since every
+// build and hook would fail without it. Returns the problem if it isn't.
+pub(crate) fn check_shell_wrapper(cfg: &Config) -> Option<String> {
+    let program = cfg.shell_wrapper.as_deref()?.split_whitespace().next()?;
+    match find_binary(program) {
+        true => {
+            debug!("preflight: found shell wrapper {}", program);
+            None
+        }
+        false => Some(format!(
+            "{} of the shell wrapper isn't installed or isn't in PATH",
+            program
+        )),
+    }
+}
+
+// Env vars set by each CI system, checked for the `ci` allow-list.
+const CI_VARS: &[(&str, &str)] = &[
+    ("github", "GITHUB_ACTIONS"),
+    ("gitlab", "GITLAB_CI"),
+    ("circleci", "CIRCLECI"),
+    ("buildkite", "BUILDKITE"),
+    ("jenkins", "JENKINS_URL"),
+    ("azure", "TF_BUILD"),
+    ("bitbucket", "BITBUCKET_BUILD_NUMBER"),
+    ("any", "CI"),
+];
+
+// Env vars CI systems set to the branch being built, since CI checkouts are
+// usually detached.
+const CI_BRANCH_VARS: &[&str] = &[
+    "CI_COMMIT_BRANCH",
+    "CIRCLE_BRANCH",
+    "BUILDKITE_BRANCH",
+    "BRANCH_NAME",
+    "BITBUCKET_BRANCH",
+];
+
+// Checks that the release is published from one of the allowed branches and
+// CI systems. The branch is allowed if HEAD is on it, CI says it's building
+// it, or it has HEAD in its history, which is the case for tag checkouts.
+pub(crate) fn check_publish_from(publish_from: &PublishFrom, git: &GitRepo) -> Result<()> {
+    if let Some(allowed) = &publish_from.ci {
+        let found = CI_VARS
+            .iter()
+            .find(|(_, var)| env::var(var).is_ok_and(|v| !v.is_empty() && v != "false"));
+        match found {
+            Some((name, _)) if allowed.iter().any(|a| a == "any" || a == name) => {
+                debug!("preflight: publishing from {} ci", name)
+            }
+            Some((name, _)) => bail!(
+                "publishing from {} ci isn't allowed, it's only allowed from {}",
+                name,
+                allowed.join(", ")
+            ),
+            None => bail!(
+                "publishing outside ci isn't allowed, it's only allowed from {}",
+                allowed.join(", ")
+            ),
+        }
+    }
+
+    let patterns = match &publish_from.branches {
+        Some(patterns) => patterns,
+        None => return Ok(()),
+    };
+    let mut branches = vec![git.head_ref()?];
+    if env::var("GITHUB_REF").is_ok_and(|r| r.starts_with("refs/heads/")) {
+        branches.extend(env::var("GITHUB_REF_NAME"));
+    }
+    branches.extend(CI_BRANCH_VARS.iter().filter_map(|var| env::var(var).ok()));
+    branches.extend(git.branches_with_head()?);
+
+    let mut globs = vec![];
+    for pattern in patterns {
+        globs.push(
+            glob::Pattern::new(pattern)
+                .map_err(|err| eyre!("invalid branch pattern {}: {}", pattern, err))?,
+        );
+    }
+    match branches.iter().find(|b| globs.iter().any(|g| g.matches(b))) {
+        Some(branch) => {
+            debug!("preflight: publishing from branch {}", branch);
+            Ok(())
+        }
+        None => bail!(
+            "publishing from {} isn't allowed, HEAD isn't on any of the branches {}",
+            branches[0],
+            patterns.join(", ")
+        ),
+    }
+}
+
+// Checks that the required tool is installed and isn't older than the
+// minimum version.
+pub(crate) fn check_requirement(requirement: &Requirement) -> Result<()> {
+    let tool = &requirement.tool;
+    if !find_binary(tool) {
+        bail!("{} is required but isn't installed or isn't in PATH", tool);
+    }
+    let min = match &requirement.version {
+        Some(min) => min,
+        None => return Ok(()),
+    };
+
+    let args = requirement
+        .version_args
+        .clone()
+        .unwrap_or_else(|| vec!["--version".to_string()]);
+    let output = match Command::new(tool).args(&args).output() {
+        Ok(output) => output,
+        Err(err) => bail!("error getting the version of {}: {}", tool, err),
+    };
+    let out = format!(
+        "{}{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let version = match Regex::new(r"\d+(\.\d+)*").unwrap().find(&out) {
+        Some(version) => version.as_str().to_string(),
+        None => bail!(
+            "couldn't find the version of {} in `{} {}`",
+            tool,
+            tool,
+            args.join(" ")
+        ),
+    };
+    if version_parts(&version) < version_parts(min) {
+        bail!("{} {} or newer is required, found {}", tool, min, version);
+    }
+    Ok(())
+}
+
+// Splits a version like `2.30.1` into its numbers for comparing versions.
+// Missing parts are zero, so that `24` is the same as `24.0.0`.
+fn version_parts(version: &str) -> Vec<u64> {
+    let mut parts: Vec<u64> = version.split('.').map(|p| p.parse().unwrap_or(0)).collect();
+    parts.resize(parts.len().max(3), 0);
+    parts
+}
+
+// Checks that the dist folder has enough free space for the release.
+fn check_disk_space(release: &Release) -> Result<()> {
+    let needed = needed_space(release)?;
+    if needed == 0 {
+        return Ok(());
+    }
+    let free = free_space(&release.dist_folder)?;
+    if free < needed {
+        bail!(
+            "{} needs about {} in {} but only {} is free",
+            release.name,
+            format_size(needed),
+            release.dist_folder,
+            format_size(free)
+        );
+    }
+    debug!(
+        "preflight: {} free in {}, {} needs about {}",
+        format_size(free),
+        release.dist_folder,
+        release.name,
+        format_size(needed)
+    );
+    Ok(())
+}
+
+// Checks that docker's storage has enough free space for building the
+// release's image. Only docker reports where its storage is.
+fn check_docker_space(backend: &ContainerBackend, release: &Release) -> Result<()> {
+    if *backend != ContainerBackend::Docker {
+        return Ok(());
+    }
+    let needed = needed_space(release)?;
+    let output = Command::new("docker")
+        .args(["info", "--format", "{{.DockerRootDir}}"])
+        .output()?;
+    let root = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if !output.status.success() || root.is_empty() {
+        debug!("preflight: couldn't find docker's storage");
+        return Ok(());
+    }
+    let free = free_space(&root)?;
+    if free < needed {
+        bail!(
+            "docker's storage in {} has only {} free, {} needs about {}",
+            root,
+            format_size(free),
+            release.name,
+            format_size(needed)
+        );
+    }
+    Ok(())
+}
+
+// Gets the space the release needs, from `disk_space` or the size of the
+// files in the dist folder from the previous run.
+fn needed_space(release: &Release) -> Result<u64> {
+    if let Some(size) = &release.disk_space {
+        return parse_size(size);
+    }
+    let entries = match fs::read_dir(&release.dist_folder) {
+        Ok(entries) => entries,
+        Err(_) => return Ok(0),
+    };
+    Ok(entries
+        .flatten()
+        .filter_map(|e| e.metadata().ok())
+        .filter(|m| m.is_file())
+        .map(|m| m.len())
+        .sum())
+}
+
+// Gets the free space of the filesystem the path is on with `df`. The path
+// might not exist yet, so its nearest existing parent is used.
+fn free_space(path: &str) -> Result<u64> {
+    let mut path = Utf8Path::new(path);
+    while !path.exists() {
+        path = match path.parent() {
+            Some(parent) if !parent.as_str().is_empty() => parent,
+            _ => Utf8Path::new("."),
+        };
+    }
+    let output = Command::new("df").args(["-Pk", path.as_str()]).output()?;
+    if !output.status.success() {
+        bail!(
+            "error getting free space of {}: {}",
+            path,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    let out = String::from_utf8_lossy(&output.stdout);
+    match out
+        .lines()
+        .nth(1)
+        .and_then(|l| l.split_whitespace().nth(3))
+        .and_then(|kb| kb.parse::<u64>().ok())
+    {
+        Some(kb) => Ok(kb * 1024),
+        None => bail!("couldn't parse the free space of {} from df", path),
+    }
+}
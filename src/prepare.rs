@@ -0,0 +1,91 @@
+use crate::config::{Prepare, Release};
+use crate::git::GitRepo;
+use crate::templating::{tag_context, Templates};
+use crate::utils::{spawn_tracked, wrapped_command};
+use camino::Utf8Path;
+use eyre::{bail, Context, Result};
+use log::{info, warn};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::Path;
+use tokio::fs;
+
+// Runs the release's prepare steps in order and returns their outputs keyed
+// by name, for the template context of the builds. With `cache`, steps whose
+// command and inputs didn't change since the last run are skipped if their
+// output still exists.
+pub async fn run(
+    release: &Release,
+    steps: &[Prepare],
+    templates: &Templates,
+    tag: &str,
+    cache: bool,
+) -> Result<HashMap<String, String>> {
+    let git = GitRepo::open()?;
+    let mut outputs = HashMap::new();
+    for step in steps {
+        let cmd = templates
+            .render(&step.command, tag_context(tag))
+            .wrap_err_with(|| format!("error rendering prepare command of {}", step.name))?;
+        let key = key(&git, step, &cmd)?;
+        let entry = git
+            .cache_dir()
+            .join("prepare")
+            .join(format!("{}-{}", release.name, step.name));
+        let cached = match fs::read_to_string(&entry).await {
+            Ok(cached) => cache && cached == key && Utf8Path::new(&step.output).exists(),
+            Err(_) => false,
+        };
+        if cached {
+            info!(
+                "prepare step {} didn't change, reusing {}",
+                step.name, step.output
+            );
+        } else {
+            execute(step, &cmd).await?;
+            if let Err(err) = save(&entry, &key).await {
+                warn!("error caching prepare step {}: {}", step.name, err);
+            }
+        }
+        outputs.insert(step.name.clone(), step.output.clone());
+    }
+    Ok(outputs)
+}
+
+async fn execute(step: &Prepare, cmd: &str) -> Result<()> {
+    info!("executing prepare step {}: {}", step.name, cmd);
+    let (mut child, _group) = spawn_tracked(&mut wrapped_command(&["sh", "-c", cmd]))
+        .wrap_err_with(|| format!("error executing prepare step {}", step.name))?;
+    let status = child
+        .wait()
+        .await
+        .wrap_err_with(|| format!("error executing prepare step {}", step.name))?;
+    if !status.success() {
+        bail!("prepare step {} failed with {}", step.name, status);
+    }
+    if !Utf8Path::new(&step.output).exists() {
+        bail!("prepare step {} didn't create {}", step.name, step.output);
+    }
+    Ok(())
+}
+
+// Hashes the step's command and output and its inputs in the repo, without
+// the output, which changes with every run if git doesn't ignore it.
+fn key(git: &GitRepo, step: &Prepare, cmd: &str) -> Result<String> {
+    let output = step.output.trim_start_matches("./");
+    let tree = git.tree_hash(step.inputs.as_deref().unwrap_or_default(), Some(output))?;
+    let mut hasher = Sha256::new();
+    for part in [env!("CARGO_PKG_VERSION"), cmd, output, &tree] {
+        hasher.update(part);
+        hasher.update("\n");
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+async fn save(entry: &Path, key: &str) -> Result<()> {
+    if let Some(dir) = entry.parent() {
+        fs::create_dir_all(dir).await?;
+    }
+    fs::write(entry, key).await?;
+    Ok(())
+}
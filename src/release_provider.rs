@@ -1,6 +1,11 @@
 use crate::config::Release;
 use async_trait::async_trait;
-use eyre::Result;
+use camino::Utf8Path;
+use eyre::{eyre, Report, Result};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
 use std::sync::Arc;
 use tokio::sync::Mutex;
 
@@ -10,10 +15,215 @@ use tokio::sync::Mutex;
 #[async_trait]
 #[allow(clippy::needless_arbitrary_self_type)]
 pub trait ReleaseProvider {
+    // Name of the target, which is used to pick its changelog template.
+    fn name(self: &Self) -> String;
+
     async fn publish(
         self: &Self,
         cfg: &Release,
         all_archives: Arc<Mutex<Vec<String>>>,
         latest_tag: String,
-    ) -> Result<()>;
+        changelog: String,
+    ) -> Result<PublishResult>;
+}
+
+/// ProviderError classifies why a provider failed, so that publishing can
+/// retry, skip the provider or abort depending on the kind. Errors which
+/// don't fit a kind are returned as plain errors.
+#[derive(Debug)]
+pub enum ProviderError {
+    Auth(String),
+    NotFound(String),
+    RateLimited(String),
+    Conflict(String),
+    Network(String),
+}
+
+impl ProviderError {
+    // Classifies an error response by its http status.
+    pub fn from_status(status: StatusCode, message: String) -> Report {
+        match status.as_u16() {
+            401 | 403 if message.to_lowercase().contains("rate limit") => {
+                ProviderError::RateLimited(message).into()
+            }
+            401 | 403 => ProviderError::Auth(message).into(),
+            404 => ProviderError::NotFound(message).into(),
+            409 | 422 => ProviderError::Conflict(message).into(),
+            429 => ProviderError::RateLimited(message).into(),
+            502..=504 => ProviderError::Network(message).into(),
+            _ => eyre!(message),
+        }
+    }
+
+    // Classifies the error output of a CLI tool, e.g. `docker push`.
+    pub fn from_output(output: &str, message: String) -> Report {
+        let output = output.to_lowercase();
+        let has = |phrases: &[&str]| phrases.iter().any(|p| output.contains(p));
+        if has(&["toomanyrequests", "rate limit"]) {
+            ProviderError::RateLimited(message).into()
+        } else if has(&["unauthorized", "denied", "authentication required"]) {
+            ProviderError::Auth(message).into()
+        } else if has(&[
+            "connection refused",
+            "i/o timeout",
+            "no such host",
+            "tls handshake",
+        ]) {
+            ProviderError::Network(message).into()
+        } else {
+            eyre!(message)
+        }
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ProviderError::Auth(_) => "auth",
+            ProviderError::NotFound(_) => "not found",
+            ProviderError::RateLimited(_) => "rate limited",
+            ProviderError::Conflict(_) => "conflict",
+            ProviderError::Network(_) => "network",
+        }
+    }
+
+    // Rate limits and network errors are usually gone after a while.
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            ProviderError::RateLimited(_) | ProviderError::Network(_)
+        )
+    }
+
+    // Combines the errors, e.g. of several uploads, into one with the
+    // message. It has the kind of the most severe error, so that it stops
+    // publishing if any is an auth error and is only retried if all of them
+    // are retryable.
+    pub fn combine<'a>(errors: impl IntoIterator<Item = &'a Report>, message: String) -> Report {
+        // Errors without a kind aren't retried, but don't stop publishing.
+        let rank = |err: &Option<&ProviderError>| match err {
+            Some(ProviderError::Auth(_)) => 4,
+            None => 3,
+            Some(ProviderError::NotFound(_)) | Some(ProviderError::Conflict(_)) => 2,
+            Some(ProviderError::RateLimited(_)) => 1,
+            Some(ProviderError::Network(_)) => 0,
+        };
+        let worst = errors
+            .into_iter()
+            .map(|err| err.downcast_ref::<ProviderError>())
+            .max_by_key(rank)
+            .flatten();
+        match worst {
+            Some(ProviderError::Auth(_)) => ProviderError::Auth(message).into(),
+            Some(ProviderError::NotFound(_)) => ProviderError::NotFound(message).into(),
+            Some(ProviderError::RateLimited(_)) => ProviderError::RateLimited(message).into(),
+            Some(ProviderError::Conflict(_)) => ProviderError::Conflict(message).into(),
+            Some(ProviderError::Network(_)) => ProviderError::Network(message).into(),
+            None => eyre!(message),
+        }
+    }
+}
+
+impl fmt::Display for ProviderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProviderError::Auth(msg)
+            | ProviderError::NotFound(msg)
+            | ProviderError::RateLimited(msg)
+            | ProviderError::Conflict(msg)
+            | ProviderError::Network(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl std::error::Error for ProviderError {}
+
+impl From<reqwest::Error> for ProviderError {
+    fn from(err: reqwest::Error) -> Self {
+        ProviderError::Network(err.to_string())
+    }
+}
+
+/// PublishResult has what a provider published, for example the url of the
+/// release and its assets or the digests of the pushed images.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PublishResult {
+    pub provider: String,
+    pub release_url: Option<String>,
+    // Id of the release in the target, e.g. github's release id.
+    pub release_id: Option<String>,
+    pub asset_urls: Vec<String>,
+    pub image_digests: Vec<String>,
+}
+
+// Names the assets are published with, keyed by their paths. Assets which
+// aren't in it are published with their file names.
+#[derive(Clone, Debug, Default)]
+pub struct AssetNames {
+    names: HashMap<String, String>,
+    // Target triples of the builds the assets are from, keyed by their paths.
+    targets: HashMap<String, String>,
+}
+
+impl AssetNames {
+    pub fn new(names: HashMap<String, String>) -> Self {
+        AssetNames {
+            names,
+            targets: HashMap::new(),
+        }
+    }
+
+    pub fn with_targets(mut self, targets: HashMap<String, String>) -> Self {
+        self.targets = targets;
+        self
+    }
+
+    pub fn get(&self, path: &str) -> String {
+        match self.names.get(path) {
+            Some(name) => name.clone(),
+            None => Utf8Path::new(path)
+                .file_name()
+                .unwrap_or_default()
+                .to_string(),
+        }
+    }
+
+    pub fn target(&self, path: &str) -> Option<&str> {
+        self.targets.get(path).map(String::as_str)
+    }
+}
+
+impl PublishResult {
+    pub fn new(provider: &str) -> Self {
+        PublishResult {
+            provider: provider.to_string(),
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn combines_to_most_severe_kind() {
+        let errors = vec![
+            ProviderError::Network("reset".to_string()).into(),
+            ProviderError::Auth("bad token".to_string()).into(),
+            eyre!("disk full"),
+        ];
+        let err = ProviderError::combine(&errors, "uploads failed".to_string());
+        assert!(matches!(
+            err.downcast_ref::<ProviderError>(),
+            Some(ProviderError::Auth(_))
+        ));
+
+        let err = ProviderError::combine(&errors[..1], "uploads failed".to_string());
+        assert!(err
+            .downcast_ref::<ProviderError>()
+            .is_some_and(ProviderError::is_retryable));
+
+        let err = ProviderError::combine([&errors[0], &errors[2]], "uploads failed".to_string());
+        assert!(err.downcast_ref::<ProviderError>().is_none());
+    }
 }
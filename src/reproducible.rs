@@ -0,0 +1,141 @@
+use crate::config::{Config, Release};
+use crate::failure::Failure;
+use crate::git::GitRepo;
+use crate::github::{release_assets, ReleaseAsset};
+use crate::utils::{checkout, sha256_file};
+use crate::{get_github_token, Opts, ReleasePipeline};
+use eyre::{bail, eyre, Result};
+use log::{info, warn};
+use std::collections::HashMap;
+
+// Rebuilds the tag and compares the checksums of the archives with the ones
+// of the published github release, from the assets' digests or a
+// `checksums.txt` asset. HEAD is checked out again at the end.
+pub async fn verify_reproducible(cfg: Config, opts: Opts, tag: &str) -> Result<()> {
+    let git = GitRepo::open_with(cfg.git.as_ref())?;
+    if !git.dirty_files()?.is_empty() {
+        bail!("repo has uncommitted changes, commit or stash them before verifying a release");
+    }
+    let head = git.head_ref()?;
+    info!("checking out {} to rebuild it", tag);
+    checkout(tag).await?;
+    let res = verify_tag(cfg, opts, tag).await;
+    info!("checking out {} again", head);
+    checkout(&head).await?;
+    res
+}
+
+async fn verify_tag(cfg: Config, opts: Opts, tag: &str) -> Result<()> {
+    let opts = Opts {
+        publish: false,
+        to_tag: Some(tag.to_string()),
+        ..opts
+    };
+    let stages = ReleasePipeline::from_config(cfg)
+        .with_opts(opts)
+        .prepare()
+        .await?;
+
+    let mut differing = 0;
+    for release in stages.releases() {
+        let published = match published_checksums(release, tag).await? {
+            Some(published) => published,
+            None => continue,
+        };
+        let archives = stages.build(release).await?;
+        let names = stages.asset_names(release, &archives, tag)?;
+
+        let mut lines = vec![];
+        for archive in &archives {
+            let name = names.get(archive);
+            let built = sha256_file(archive.clone()).await?;
+            match published.get(&name) {
+                Some(sha) if *sha == built => lines.push(format!("  {}: identical", name)),
+                Some(sha) => {
+                    differing += 1;
+                    lines.push(format!(
+                        "  {}: differs (published {}, built {})",
+                        name, sha, built
+                    ));
+                }
+                None => lines.push(format!("  {}: no published checksum", name)),
+            }
+        }
+        println!(
+            "Reproducibility of {} {}:\n{}",
+            release.name,
+            tag,
+            lines.join("\n")
+        );
+    }
+
+    if differing > 0 {
+        return Err(Failure::Verification.wrap(eyre!(
+            "{} archive(s) of {} aren't reproducible",
+            differing,
+            tag
+        )));
+    }
+    Ok(())
+}
+
+// Gets the published sha256 checksums of the release's assets, keyed by the
+// asset name. A `checksums.txt` asset is used for assets without a digest.
+async fn published_checksums(
+    release: &Release,
+    tag: &str,
+) -> Result<Option<HashMap<String, String>>> {
+    let gh = match &release.targets.github {
+        Some(gh) => gh,
+        None => {
+            warn!(
+                "{} doesn't have a github target, skipping verifying it",
+                release.name
+            );
+            return Ok(None);
+        }
+    };
+    let ghtoken = get_github_token()?;
+    let assets = match release_assets(gh, &ghtoken, tag).await? {
+        Some(assets) => assets,
+        None => bail!("no github release for {} of {}", tag, release.name),
+    };
+
+    let mut checksums = HashMap::new();
+    if let Some(file) = assets.iter().find(|a| a.name == "checksums.txt") {
+        checksums.extend(checksums_file(file, &ghtoken).await?);
+    }
+    for asset in &assets {
+        if let Some(sha) = asset
+            .digest
+            .as_ref()
+            .and_then(|d| d.strip_prefix("sha256:"))
+        {
+            checksums.insert(asset.name.clone(), sha.to_string());
+        }
+    }
+    Ok(Some(checksums))
+}
+
+// Downloads and parses a `checksums.txt` with `<sha256>  <name>` lines.
+async fn checksums_file(asset: &ReleaseAsset, ghtoken: &str) -> Result<HashMap<String, String>> {
+    let mut req = reqwest::Client::new().get(&asset.browser_download_url);
+    if !ghtoken.is_empty() {
+        req = req.header("Authorization", format!("token {}", ghtoken));
+    }
+    let res = req.send().await?;
+    if !res.status().is_success() {
+        bail!("error downloading {}, status: {}", asset.name, res.status());
+    }
+    let body = res.text().await?;
+    Ok(body
+        .lines()
+        .filter_map(|line| line.split_once(char::is_whitespace))
+        .map(|(sha, name)| {
+            (
+                name.trim().trim_start_matches('*').to_string(),
+                sha.to_lowercase(),
+            )
+        })
+        .collect())
+}
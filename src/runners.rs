@@ -0,0 +1,143 @@
+use crate::config::{Build, BuildContainer, Runner};
+use crate::templating::target_os_arch;
+use crate::utils::{group_command, spawn_tracked};
+use camino::Utf8Path;
+use eyre::{bail, Context, Result};
+use log::info;
+use std::collections::HashMap;
+use std::env;
+use std::process::{Output, Stdio};
+use std::sync::{Arc, LazyLock};
+use tokio::{fs, sync::Mutex};
+
+// Builds on the same runner share its workspace, so they run one at a time.
+static RUNNER_LOCKS: LazyLock<std::sync::Mutex<HashMap<String, Arc<Mutex<()>>>>> =
+    LazyLock::new(Default::default);
+
+// Gets the runner for the build's target, the first one whose os and arch
+// match. Builds without a target run locally.
+pub fn find<'a>(runners: &'a [Runner], build: &Build) -> Option<&'a Runner> {
+    let (os, arch) = target_os_arch(build.target.as_deref()?);
+    runners
+        .iter()
+        .find(|r| r.os == os && r.arch.as_ref().is_none_or(|a| *a == arch))
+}
+
+// Runs the build on the runner: the workspace is copied to it with rsync,
+// without the files ignored by git, the command is executed there over ssh
+// and the artifact is copied back to its path in the local workspace.
+pub async fn run(runner: &Runner, build: &Build) -> Result<Output> {
+    let lock = RUNNER_LOCKS
+        .lock()
+        .unwrap()
+        .entry(runner.host.clone())
+        .or_default()
+        .clone();
+    let _guard = lock.lock().await;
+
+    let dir = match &runner.dir {
+        Some(dir) => dir.clone(),
+        None => {
+            let cwd = env::current_dir()?;
+            let name = cwd.file_name().unwrap_or_default().to_string_lossy();
+            format!(".cache/rlsr/{}", name)
+        }
+    };
+    info!("copying the workspace to {}:{}", runner.host, dir);
+    let mkdir = format!("mkdir -p {}", quote(&dir));
+    check(&["ssh", &runner.host, &mkdir]).await?;
+    check(&[
+        "rsync",
+        "-az",
+        "--delete",
+        "--exclude=.git",
+        "--filter=:- .gitignore",
+        "./",
+        &format!("{}:{}/", runner.host, dir),
+    ])
+    .await?;
+
+    info!("executing build {} on {}", build.name, runner.host);
+    let cmd = format!("cd {} && {}", quote(&dir), build.command);
+    let (child, _group) = spawn_tracked(
+        group_command("ssh")
+            .arg(&runner.host)
+            .arg(&cmd)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped()),
+    )
+    .wrap_err_with(|| format!("error executing build on {}", runner.host))?;
+    let output = child.wait_with_output().await?;
+    if !output.status.success() {
+        return Ok(output);
+    }
+
+    // Relative artifacts are in the remote workspace.
+    let remote = match Utf8Path::new(&build.artifact).is_absolute() {
+        true => build.artifact.clone(),
+        false => format!("{}/{}", dir, build.artifact.trim_start_matches("./")),
+    };
+    if let Some(parent) = Utf8Path::new(&build.artifact).parent() {
+        if !parent.as_str().is_empty() {
+            fs::create_dir_all(parent).await?;
+        }
+    }
+    check(&[
+        "scp",
+        "-q",
+        &format!("{}:{}", runner.host, remote),
+        &build.artifact,
+    ])
+    .await?;
+    Ok(output)
+}
+
+// Runs the build's command in a docker container with the workspace mounted
+// at the same path, so the artifact's path is the same in and outside it.
+pub async fn run_in_container(container: &BuildContainer, build: &Build) -> Result<Output> {
+    let cwd = env::current_dir()?;
+    let cwd = cwd.to_string_lossy();
+    let mut cmd = group_command("docker");
+    cmd.args(["run", "--rm", "--init"])
+        .arg("-v")
+        .arg(format!("{}:{}", cwd, cwd))
+        .arg("-w")
+        .arg(cwd.as_ref());
+    for volume in container.volumes.as_deref().unwrap_or_default() {
+        cmd.arg("-v").arg(volume);
+    }
+    for var in container.env.as_deref().unwrap_or_default() {
+        cmd.arg("-e").arg(var);
+    }
+    cmd.args([container.image.as_str(), "sh", "-c", build.command.as_str()]);
+
+    info!("executing build {} in {}", build.name, container.image);
+    let (child, _group) = spawn_tracked(cmd.stdout(Stdio::piped()).stderr(Stdio::piped()))
+        .wrap_err_with(|| format!("error executing build in {}", container.image))?;
+    Ok(child.wait_with_output().await?)
+}
+
+// Executes the command and fails with its stderr.
+async fn check(args: &[&str]) -> Result<()> {
+    let (child, _group) = spawn_tracked(
+        group_command(args[0])
+            .args(&args[1..])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped()),
+    )
+    .wrap_err_with(|| format!("error executing {}", args[0]))?;
+    let output = child.wait_with_output().await?;
+    if !output.status.success() {
+        bail!(
+            "error executing {}: {}",
+            args[0],
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+// Quotes the arg for the remote shell.
+fn quote(arg: &str) -> String {
+    format!("'{}'", arg.replace('\'', r"'\''"))
+}
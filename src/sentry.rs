@@ -0,0 +1,148 @@
+use crate::config::Release;
+use crate::git::GitRepo;
+use crate::release_provider::{ProviderError, PublishResult, ReleaseProvider};
+use crate::templating::{tag_context, tag_version, Templates};
+use crate::utils::expand_globs;
+use async_trait::async_trait;
+use camino::Utf8Path;
+use chrono::Utc;
+use eyre::{bail, Result};
+use log::{debug, info};
+use reqwest::multipart::{Form, Part};
+use reqwest::{Client, RequestBuilder};
+use serde_json::json;
+use std::env;
+use std::sync::Arc;
+use tokio::{fs, sync::Mutex};
+
+/// Sentry creates a release in sentry with the commits since the previous
+/// tag, so that errors are attributed to the release that introduced them,
+/// and uploads the debug files of the builds to the projects.
+pub struct Sentry {
+    templates: Arc<Templates>,
+}
+
+impl Sentry {
+    pub fn new(templates: Arc<Templates>) -> Self {
+        Sentry { templates }
+    }
+}
+
+#[allow(clippy::needless_arbitrary_self_type)]
+#[async_trait]
+impl ReleaseProvider for Sentry {
+    fn name(self: &Self) -> String {
+        "sentry".to_string()
+    }
+
+    async fn publish(
+        self: &Self,
+        release: &Release,
+        _all_archives: Arc<Mutex<Vec<String>>>,
+        latest_tag: String,
+        _changelog: String,
+    ) -> Result<PublishResult> {
+        let cfg = match &release.targets.sentry {
+            Some(cfg) => cfg,
+            None => bail!("sentry config can't be empty"),
+        };
+        let token = env::var("SENTRY_AUTH_TOKEN").unwrap_or_default();
+        if token.is_empty() {
+            bail!("SENTRY_AUTH_TOKEN is blank, skipping the sentry release");
+        }
+        let version = match &cfg.version {
+            Some(version) => self.templates.render(version, tag_context(&latest_tag))?,
+            None => format!("{}@{}", release.name, tag_version(&latest_tag)),
+        };
+        let api = format!(
+            "{}/api/0",
+            cfg.url
+                .as_deref()
+                .unwrap_or("https://sentry.io")
+                .trim_end_matches('/')
+        );
+        let client = Client::new();
+        let auth = |req: RequestBuilder| req.bearer_auth(&token);
+
+        // The release's commits are the ones since the previous tag.
+        let git = GitRepo::open()?;
+        let repository = match (&cfg.repository, &release.targets.github) {
+            (Some(repository), _) => repository.clone(),
+            (None, Some(gh)) => format!("{}/{}", gh.owner, gh.repo),
+            (None, None) => {
+                let (owner, repo) = git.remote_repo()?;
+                format!("{}/{}", owner, repo)
+            }
+        };
+        let mut commits = json!({
+            "repository": repository,
+            "commit": git.tag_commit(&latest_tag)?,
+        });
+        if let Some(previous) = git.previous_tag(&latest_tag)? {
+            commits["previousCommit"] = json!(git.tag_commit(&previous)?);
+        }
+
+        debug!("creating sentry release {}", version);
+        let res = auth(client.post(format!("{}/organizations/{}/releases/", api, cfg.org)))
+            .json(&json!({
+                "version": version,
+                "projects": cfg.projects,
+                "refs": [commits],
+            }))
+            .send()
+            .await
+            .map_err(ProviderError::from)?;
+        check_status(res, "error creating sentry release").await?;
+
+        for file in expand_globs(cfg.debug_files.as_deref().unwrap_or_default())? {
+            let name = Utf8Path::new(&file)
+                .file_name()
+                .unwrap_or_default()
+                .to_string();
+            for project in &cfg.projects {
+                let part = Part::bytes(fs::read(&file).await?).file_name(name.clone());
+                let res = auth(client.post(format!(
+                    "{}/projects/{}/{}/files/dsyms/",
+                    api, cfg.org, project
+                )))
+                .multipart(Form::new().part("file", part))
+                .send()
+                .await
+                .map_err(ProviderError::from)?;
+                check_status(res, &format!("error uploading {} to {}", file, project)).await?;
+                info!("uploaded debug file {} to {}", file, project);
+            }
+        }
+
+        let res = auth(client.put(format!(
+            "{}/organizations/{}/releases/{}/",
+            api, cfg.org, version
+        )))
+        .json(&json!({ "dateReleased": Utc::now().to_rfc3339() }))
+        .send()
+        .await
+        .map_err(ProviderError::from)?;
+        check_status(res, "error finalizing sentry release").await?;
+        info!("created sentry release {}", version);
+
+        let mut result = PublishResult::new("sentry");
+        result.release_id = Some(version);
+        Ok(result)
+    }
+}
+
+async fn check_status(res: reqwest::Response, what: &str) -> Result<()> {
+    let status = res.status();
+    if !status.is_success() {
+        return Err(ProviderError::from_status(
+            status,
+            format!(
+                "{}, status: {}, error: {}",
+                what,
+                status,
+                res.text().await.unwrap_or_default()
+            ),
+        ));
+    }
+    Ok(())
+}
@@ -0,0 +1,131 @@
+use crate::config::Release;
+use crate::get_github_token;
+use crate::github::release_assets;
+use crate::release_provider::AssetNames;
+use crate::templating::{tag_context, tag_version, Templates};
+use crate::utils::{archive_name, format_size, parse_size};
+use camino::Utf8Path;
+use eyre::{bail, Result};
+use log::{info, warn};
+use tokio::fs;
+
+// Checks the archives against the `max_size` of their builds. Archives are
+// matched to the builds by their rendered names.
+pub async fn check_budgets(
+    release: &Release,
+    templates: &Templates,
+    tag: &str,
+    archives: &[String],
+) -> Result<()> {
+    let mut over = vec![];
+    for build in &release.builds {
+        let max = match &build.max_size {
+            Some(max) => parse_size(max)?,
+            None => continue,
+        };
+        let name = templates.render(&build.name, tag_context(tag))?;
+        let archive_name = archive_name(release, build, &name);
+        let archive = archives.iter().find(|a| {
+            let file_name = Utf8Path::new(a).file_name().unwrap_or_default();
+            file_name == name || file_name == archive_name
+        });
+        let archive = match archive {
+            Some(archive) => archive,
+            None => continue,
+        };
+        let size = fs::metadata(archive).await?.len();
+        if size > max {
+            over.push(format!(
+                "  {} is {}, the budget is {}",
+                archive,
+                format_size(size),
+                format_size(max)
+            ));
+        }
+    }
+    if !over.is_empty() {
+        bail!("archives are over their size budgets:\n{}", over.join("\n"));
+    }
+    Ok(())
+}
+
+// Prints the sizes of the assets compared to the same assets in the previous
+// github release. Assets are matched by their names with the previous version
+// replaced by the current one. Assets which grew more than `max_growth` are
+// warned about, or fail publishing with `fail`.
+pub async fn report(
+    release: &Release,
+    archives: &[String],
+    asset_names: &AssetNames,
+    previous_tag: &str,
+    tag: &str,
+) -> Result<()> {
+    let cfg = match &release.size_report {
+        Some(cfg) => cfg,
+        None => return Ok(()),
+    };
+    let gh = match &release.targets.github {
+        Some(gh) => gh,
+        None => {
+            warn!("size report needs the github target to find the previous release");
+            return Ok(());
+        }
+    };
+    let previous = match release_assets(gh, &get_github_token()?, previous_tag).await? {
+        Some(previous) => previous,
+        None => {
+            info!(
+                "no github release for {}, skipping size report",
+                previous_tag
+            );
+            return Ok(());
+        }
+    };
+
+    let mut lines = vec![];
+    let mut grown = vec![];
+    for archive in archives {
+        let name = asset_names.get(archive);
+        let size = fs::metadata(archive).await?.len();
+        let old = previous.iter().find_map(|a| {
+            (a.name.replace(tag_version(previous_tag), tag_version(tag)) == name).then_some(a.size)
+        });
+        let old = match old {
+            Some(old) if old > 0 => old,
+            _ => {
+                lines.push(format!("  {}: {} (new)", name, format_size(size)));
+                continue;
+            }
+        };
+        let growth = (size as f64 - old as f64) / old as f64 * 100.0;
+        lines.push(format!(
+            "  {}: {} ({:+.1}% from {})",
+            name,
+            format_size(size),
+            growth,
+            format_size(old)
+        ));
+        if cfg.max_growth.is_some_and(|max| growth > max) {
+            grown.push(format!("{} grew {:.1}%", name, growth));
+        }
+    }
+    println!(
+        "Asset sizes compared to {}:\n{}",
+        previous_tag,
+        lines.join("\n")
+    );
+
+    if grown.is_empty() {
+        return Ok(());
+    }
+    let msg = format!(
+        "assets grew more than {}%: {}",
+        cfg.max_growth.unwrap_or_default(),
+        grown.join(", ")
+    );
+    if cfg.fail.unwrap_or(false) {
+        bail!(msg);
+    }
+    warn!("{}", msg);
+    Ok(())
+}
@@ -0,0 +1,362 @@
+use crate::config::{Release, Templating, Version, VersionScheme};
+use crate::git::GitRepo;
+use crate::utils::{output_tracked, wrapped_std_command};
+use crate::version::{increment, Part};
+use chrono::{DateTime, FixedOffset, Local, Locale, SecondsFormat, Utc};
+use eyre::{eyre, Context, Result};
+use log::warn;
+use minijinja::value::{Rest, Serde};
+use minijinja::{context, Environment, Error, ErrorKind, Value};
+use std::collections::HashMap;
+use std::path::Path;
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use std::{env, fs};
+
+// Caches the output of commands executed from templates, keyed by the
+// command and its arguments.
+type ExecCache = Arc<Mutex<HashMap<Vec<String>, String>>>;
+
+/// Templates renders the templates given in the config. It holds the static
+/// vars and the command backed functions from the `templating` section so that
+/// every template (build names, changelog etc) gets the same context.
+pub struct Templates {
+    env: Environment<'static>,
+    vars: HashMap<String, String>,
+    timezone: Timezone,
+}
+
+// Timezone dates are rendered in.
+#[derive(Clone, Copy)]
+enum Timezone {
+    Utc,
+    Local,
+    Fixed(FixedOffset),
+}
+
+impl Timezone {
+    // Parses `UTC`, `local` or an offset like `+05:30`.
+    fn parse(timezone: &str) -> Result<Self> {
+        match timezone {
+            "UTC" | "utc" | "Z" => Ok(Timezone::Utc),
+            "local" => Ok(Timezone::Local),
+            offset => {
+                let dt = DateTime::parse_from_rfc3339(&format!("2000-01-01T00:00:00{}", offset))
+                    .map_err(|_| {
+                        eyre!(
+                            "invalid timezone {}, use UTC, local or an offset like +05:30",
+                            offset
+                        )
+                    })?;
+                Ok(Timezone::Fixed(*dt.offset()))
+            }
+        }
+    }
+
+    fn convert(&self, dt: DateTime<Utc>) -> DateTime<FixedOffset> {
+        match self {
+            Timezone::Utc => dt.fixed_offset(),
+            Timezone::Local => dt.with_timezone(&Local).fixed_offset(),
+            Timezone::Fixed(offset) => dt.with_timezone(offset),
+        }
+    }
+}
+
+// Checks the templating timezone, for the config lints.
+pub(crate) fn check_timezone(cfg: &Templating) -> Result<()> {
+    match &cfg.timezone {
+        Some(timezone) => Timezone::parse(timezone).map(|_| ()),
+        None => Ok(()),
+    }
+}
+
+// Parses a locale like `de_DE`, or `de-DE`.
+fn parse_locale(locale: &str) -> Result<Locale> {
+    locale
+        .replace('-', "_")
+        .parse()
+        .map_err(|_| eyre!("invalid locale {}, use a locale like de_DE", locale))
+}
+
+// Checks the templating locale, for the config lints.
+pub(crate) fn check_locale(cfg: &Templating) -> Result<()> {
+    match &cfg.locale {
+        Some(locale) => parse_locale(locale).map(|_| ()),
+        None => Ok(()),
+    }
+}
+
+impl Templates {
+    pub fn new(cfg: Option<&Templating>, version: Option<&Version>) -> Self {
+        let cfg = cfg.cloned().unwrap_or_default();
+        let timezone = match cfg.timezone.as_deref().map(Timezone::parse) {
+            Some(Ok(timezone)) => timezone,
+            Some(Err(err)) => {
+                warn!("{}, using UTC", err);
+                Timezone::Utc
+            }
+            None => Timezone::Utc,
+        };
+        let locale = match cfg.locale.as_deref().map(parse_locale) {
+            Some(Ok(locale)) => locale,
+            Some(Err(err)) => {
+                warn!("{}, using english names", err);
+                Locale::POSIX
+            }
+            None => Locale::POSIX,
+        };
+        let cache: ExecCache = Arc::new(Mutex::new(HashMap::new()));
+        let mut env = Environment::new();
+
+        // `incmajor`, `incminor` and `incpatch` increment versions according
+        // to the configured version scheme.
+        let scheme = version
+            .and_then(|v| v.scheme.clone())
+            .unwrap_or(VersionScheme::Semver);
+        for (name, part) in [
+            ("incmajor", Part::Major),
+            ("incminor", Part::Minor),
+            ("incpatch", Part::Patch),
+        ] {
+            let scheme = scheme.clone();
+            env.add_filter(name, move |version: String| {
+                increment(&scheme, &version, &part)
+                    .map_err(|err| Error::new(ErrorKind::InvalidOperation, err.to_string()))
+            });
+        }
+
+        let exec_cache = cache.clone();
+        env.add_function("exec", move |cmd: String| exec(&exec_cache, &cmd, &[]));
+        env.add_function("readfile", readfile);
+        // `time` formats RFC 3339 dates, like the commits' dates, and unix
+        // timestamps in the timezone and with the locale's names, e.g.
+        // `{{ commit.date | time("%B %d") }}`.
+        env.add_filter("time", move |value: Value, format: Option<String>| {
+            let dt = match value.as_str() {
+                Some(date) => DateTime::parse_from_rfc3339(date)
+                    .map(|dt| dt.with_timezone(&Utc))
+                    .map_err(|err| {
+                        Error::new(
+                            ErrorKind::InvalidOperation,
+                            format!("invalid date {}: {}", date, err),
+                        )
+                    })?,
+                None => i64::try_from(value.clone())
+                    .ok()
+                    .and_then(|secs| DateTime::from_timestamp(secs, 0))
+                    .ok_or_else(|| {
+                        Error::new(
+                            ErrorKind::InvalidOperation,
+                            format!("{} isn't a date or a timestamp", value),
+                        )
+                    })?,
+            };
+            let format = format.as_deref().unwrap_or("%Y-%m-%d %H:%M:%S");
+            Ok(timezone
+                .convert(dt)
+                .format_localized(format, locale)
+                .to_string())
+        });
+
+        for (name, cmd) in cfg.functions.unwrap_or_default() {
+            let cache = cache.clone();
+            env.add_function(name, move |args: Rest<String>| exec(&cache, &cmd, &args));
+        }
+
+        Templates {
+            env,
+            vars: cfg.vars.unwrap_or_default(),
+            timezone,
+        }
+    }
+
+    pub fn render(&self, tmpl: &str, ctx: Value) -> Result<String> {
+        let now = Utc::now();
+        let local = self.timezone.convert(now);
+        let ctx = context! {
+            vars => Serde(&self.vars),
+            date => local.format("%Y-%m-%d").to_string(),
+            now => local.to_rfc3339_opts(SecondsFormat::Secs, false),
+            timestamp => now.timestamp(),
+            ..ctx
+        };
+        self.env
+            .render_str(tmpl, ctx)
+            .wrap_err_with(|| format!("error rendering template: {}", tmpl))
+    }
+}
+
+// Gets the template context for the given tag.
+pub fn tag_context(tag: &str) -> Value {
+    context! {
+        tag => tag,
+        version => tag_version(tag),
+    }
+}
+
+// Gets the version in the tag, without the `v` and the prefix of monorepo
+// tags, e.g. `1.4.0` for `cli/v1.4.0`.
+pub fn tag_version(tag: &str) -> &str {
+    let version = tag.rsplit('/').next().unwrap_or(tag);
+    version.trim_start_matches('v')
+}
+
+// Gets the name an asset is published with, from the release's
+// `asset_name` template. Without the template, it's the file name.
+pub fn asset_name(
+    templates: &Templates,
+    release: &Release,
+    tag: &str,
+    file_name: &str,
+    target: Option<&str>,
+) -> Result<String> {
+    let tmpl = match &release.asset_name {
+        Some(tmpl) => tmpl,
+        None => return Ok(file_name.to_string()),
+    };
+    let (name, ext) = split_ext(file_name);
+    let (os, arch) = target.map(target_os_arch).unwrap_or_default();
+    let ctx = context! {
+        name => name,
+        ext => ext,
+        os => os,
+        arch => arch,
+        target => target.unwrap_or_default(),
+        ..tag_context(tag)
+    };
+    templates.render(tmpl, ctx)
+}
+
+// Splits the file name into the name and the extension with the dot, e.g.
+// `.zip` or `.tar.gz`. Version numbers like `rlsr-1.2.0` aren't extensions.
+fn split_ext(file_name: &str) -> (&str, &str) {
+    let stem = match file_name.rsplit_once('.') {
+        Some((stem, ext))
+            if !stem.is_empty()
+                && ext.chars().all(|c| c.is_ascii_alphanumeric())
+                && ext.chars().any(|c| c.is_ascii_alphabetic()) =>
+        {
+            stem
+        }
+        _ => return (file_name, ""),
+    };
+    let stem = stem.strip_suffix(".tar").unwrap_or(stem);
+    file_name.split_at(stem.len())
+}
+
+// Gets the os and arch from a target triple, e.g. `linux` and `x86_64` for
+// `x86_64-unknown-linux-gnu`.
+pub(crate) fn target_os_arch(target: &str) -> (String, String) {
+    let parts: Vec<&str> = target.split('-').collect();
+    let arch = parts.first().copied().unwrap_or_default();
+    let os = match parts.len() {
+        0 | 1 => "",
+        2 => parts[1],
+        _ => parts[2],
+    };
+    (os.to_string(), arch.to_string())
+}
+
+// Reads a file in the repo, e.g. upgrade notes for the changelog. The path is
+// relative to the repo's root and can't point outside of it, so templates
+// can't read files like `~/.ssh` or `/etc`.
+fn readfile(path: String) -> Result<String, Error> {
+    let err = |msg: String| Error::new(ErrorKind::InvalidOperation, msg);
+    let root = match GitRepo::open()
+        .ok()
+        .and_then(|git| git.workdir().map(Path::to_path_buf))
+    {
+        Some(root) => root,
+        None => env::current_dir().map_err(|e| err(e.to_string()))?,
+    };
+    let root = root.canonicalize().map_err(|e| err(e.to_string()))?;
+    let file = root
+        .join(&path)
+        .canonicalize()
+        .map_err(|e| err(format!("error reading {}: {}", path, e)))?;
+    if !file.starts_with(&root) {
+        return Err(err(format!("{} is outside the repo", path)));
+    }
+    fs::read_to_string(&file).map_err(|e| err(format!("error reading {}: {}", path, e)))
+}
+
+// Executes the command with `sh`, like the other commands with the shell
+// wrapper and in a tracked process group, and returns the trimmed stdout.
+// Templates are rendered synchronously, so it blocks while the command runs
+// instead of going through the runtime.
+// Outputs are cached so that a command used in multiple templates only runs
+// once.
+fn exec(cache: &ExecCache, cmd: &str, args: &[String]) -> Result<String, Error> {
+    let mut key = vec![cmd.to_string()];
+    key.extend_from_slice(args);
+    if let Some(out) = cache.lock().unwrap().get(&key) {
+        return Ok(out.clone());
+    }
+
+    let mut argv = vec!["sh", "-c", cmd, "rlsr"];
+    argv.extend(args.iter().map(String::as_str));
+    let mut command = wrapped_std_command(&argv);
+    command.stdout(Stdio::piped()).stderr(Stdio::piped());
+    let output = output_tracked(&mut command).map_err(|err| {
+        Error::new(
+            ErrorKind::InvalidOperation,
+            format!("error executing `{}`: {}", cmd, err),
+        )
+    })?;
+    if !output.status.success() {
+        return Err(Error::new(
+            ErrorKind::InvalidOperation,
+            format!(
+                "error executing `{}`: {}",
+                cmd,
+                String::from_utf8_lossy(&output.stderr)
+            ),
+        ));
+    }
+
+    let out = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    cache.lock().unwrap().insert(key, out.clone());
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_extensions() {
+        assert_eq!(split_ext("rlsr.zip"), ("rlsr", ".zip"));
+        assert_eq!(split_ext("rlsr-linux.tar.gz"), ("rlsr-linux", ".tar.gz"));
+        assert_eq!(split_ext("rlsr-1.2.0"), ("rlsr-1.2.0", ""));
+        assert_eq!(split_ext("rlsr-1.2.0.tar.zst"), ("rlsr-1.2.0", ".tar.zst"));
+        assert_eq!(split_ext(".env"), (".env", ""));
+        assert_eq!(split_ext("README"), ("README", ""));
+    }
+
+    #[test]
+    fn parses_timezones() {
+        assert!(matches!(Timezone::parse("UTC"), Ok(Timezone::Utc)));
+        assert!(matches!(Timezone::parse("Z"), Ok(Timezone::Utc)));
+        assert!(matches!(Timezone::parse("local"), Ok(Timezone::Local)));
+        match Timezone::parse("+05:30") {
+            Ok(Timezone::Fixed(offset)) => assert_eq!(offset.local_minus_utc(), 5 * 3600 + 1800),
+            _ => panic!("+05:30 isn't parsed as a fixed offset"),
+        }
+        match Timezone::parse("-08:00") {
+            Ok(Timezone::Fixed(offset)) => assert_eq!(offset.local_minus_utc(), -8 * 3600),
+            _ => panic!("-08:00 isn't parsed as a fixed offset"),
+        }
+        assert!(Timezone::parse("Asia/Kolkata").is_err());
+        assert!(Timezone::parse("+25:00").is_err());
+    }
+
+    // `#[tokio::test]` runs on the current-thread runtime, which can't block
+    // in place.
+    #[tokio::test]
+    async fn executes_in_current_thread_runtime() {
+        let cache = ExecCache::default();
+        let out = exec(&cache, "echo $1", &["rlsr".to_string()]).unwrap();
+        assert_eq!(out, "rlsr");
+        assert!(exec(&cache, "exit 1", &[]).is_err());
+    }
+}
@@ -0,0 +1,148 @@
+use crate::config::{ArchiveFormat, Build, Compression, Release, Terraform};
+use crate::templating::{tag_version, target_os_arch};
+use crate::utils::{
+    additional_files, archive_file, build_compression, group_command, sha256_file, spawn_tracked,
+};
+use camino::Utf8Path;
+use eyre::{bail, Context, Result};
+use log::info;
+use std::env;
+use tokio::fs;
+
+// Archives the build's binary as `terraform-provider-<name>_v<version>` into
+// `terraform-provider-<name>_<version>_<os>_<arch>.zip`, with the os and
+// arch named like go names them. Returns the archive's path.
+pub async fn archive(
+    release: &Release,
+    build: &Build,
+    cfg: &Terraform,
+    tag: &str,
+    bin_path: String,
+) -> Result<String> {
+    let target = match &build.target {
+        Some(target) => target,
+        None => bail!(
+            "build {} needs a target for the terraform layout",
+            build.name
+        ),
+    };
+    let (os, arch) = go_os_arch(target)?;
+    let prefix = format!("terraform-provider-{}", cfg.provider);
+    let version = tag_version(tag);
+    let mut bin = format!("{}_v{}", prefix, version);
+    if os == "windows" {
+        bin.push_str(".exe");
+    }
+
+    // The binary is copied, since artifacts of builds with `no_archive`
+    // aren't staged in the dist folder.
+    let dir = Utf8Path::new(&release.dist_folder)
+        .join("terraform")
+        .join(format!("{}_{}", os, arch));
+    fs::create_dir_all(&dir).await?;
+    let staged = dir.join(&bin);
+    fs::copy(&bin_path, &staged)
+        .await
+        .wrap_err_with(|| format!("error copying {} to {}", bin_path, staged))?;
+
+    // The registry only accepts zips.
+    let compression = Compression {
+        format: Some(ArchiveFormat::Zip),
+        ..build_compression(release, build)
+    };
+    archive_file(
+        staged.to_string(),
+        additional_files(release, build)?,
+        release.dist_folder.clone(),
+        format!("{}_{}_{}_{}.zip", prefix, version, os, arch),
+        compression,
+    )
+    .await
+}
+
+// Writes the registry manifest and the `SHA256SUMS` of the archives and the
+// manifest, and signs the checksums with gpg. Returns the paths of the
+// files, which are published with the archives.
+pub async fn checksums(
+    release: &Release,
+    cfg: &Terraform,
+    tag: &str,
+    archives: &[String],
+) -> Result<Vec<String>> {
+    let prefix = format!("terraform-provider-{}_{}", cfg.provider, tag_version(tag));
+    let dist = Utf8Path::new(&release.dist_folder);
+
+    let protocols = cfg
+        .protocol_versions
+        .clone()
+        .unwrap_or_else(|| vec!["5.0".to_string()]);
+    let manifest = serde_json::json!({
+        "version": 1,
+        "metadata": { "protocol_versions": protocols },
+    });
+    let manifest_path = dist.join(format!("{}_manifest.json", prefix));
+    fs::write(&manifest_path, serde_json::to_string_pretty(&manifest)?).await?;
+
+    let mut files = vec![manifest_path.to_string()];
+    files.extend(
+        archives
+            .iter()
+            .filter(|a| {
+                Utf8Path::new(a)
+                    .file_name()
+                    .is_some_and(|name| name.starts_with(&prefix) && name.ends_with(".zip"))
+            })
+            .cloned(),
+    );
+    files.sort_by_key(|file| Utf8Path::new(file).file_name().map(String::from));
+    let mut sums = String::new();
+    for file in &files {
+        let name = Utf8Path::new(file).file_name().unwrap_or_default();
+        sums.push_str(&format!("{}  {}\n", sha256_file(file.clone()).await?, name));
+    }
+    let sums_path = dist.join(format!("{}_SHA256SUMS", prefix));
+    fs::write(&sums_path, sums).await?;
+
+    let sig_path = dist.join(format!("{}_SHA256SUMS.sig", prefix));
+    sign(cfg, sums_path.as_str(), sig_path.as_str()).await?;
+    info!("signed {} with gpg", sums_path);
+    Ok(vec![
+        manifest_path.to_string(),
+        sums_path.to_string(),
+        sig_path.to_string(),
+    ])
+}
+
+// Writes a binary detached signature of the file, as the registry expects.
+async fn sign(cfg: &Terraform, file: &str, sig: &str) -> Result<()> {
+    let key = cfg
+        .gpg_key
+        .clone()
+        .or_else(|| env::var("GPG_FINGERPRINT").ok().filter(|k| !k.is_empty()));
+    let mut cmd = group_command("gpg");
+    cmd.args(["--batch", "--yes", "--detach-sign", "--output", sig]);
+    if let Some(key) = &key {
+        cmd.args(["--local-user", key]);
+    }
+    cmd.arg(file);
+    let (mut child, _group) = spawn_tracked(&mut cmd).wrap_err_with(|| "error executing gpg")?;
+    let status = child.wait().await.wrap_err_with(|| "error executing gpg")?;
+    if !status.success() {
+        bail!("error signing {} with gpg, it exited with {}", file, status);
+    }
+    Ok(())
+}
+
+// Gets the os and arch of the target triple as go names them, e.g. `linux`
+// and `amd64` for `x86_64-unknown-linux-gnu`.
+fn go_os_arch(target: &str) -> Result<(String, String)> {
+    let (os, arch) = target_os_arch(target);
+    let arch = match arch.as_str() {
+        "x86_64" => "amd64",
+        "aarch64" => "arm64",
+        "i686" | "i586" | "i386" => "386",
+        "arm" | "armv7" | "armv6" => "arm",
+        _ => bail!("terraform layout doesn't support the arch of {}", target),
+    };
+    Ok((os, arch.to_string()))
+}
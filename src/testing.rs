@@ -0,0 +1,76 @@
+use crate::config::{Release, Test};
+use crate::events::{Event, Events};
+use crate::templating::{tag_context, Templates};
+use crate::utils::{spawn_tracked, wrapped_command};
+use eyre::{bail, Context, Result};
+use log::{error, info};
+use std::time::{Duration, Instant};
+use tokio::time;
+
+// Runs the release's tests in order and prints how each of them went. All
+// the tests are run even if one fails, and any failed test fails the
+// release before it's built.
+pub async fn run(
+    release: &Release,
+    tests: &[Test],
+    templates: &Templates,
+    tag: &str,
+    events: &Events,
+) -> Result<()> {
+    let mut lines = vec![];
+    let mut failed = vec![];
+    for test in tests {
+        let start = Instant::now();
+        let res = run_test(test, templates, tag).await;
+        let elapsed = start.elapsed();
+        events.emit(Event::TestFinished {
+            release: release.name.clone(),
+            test: test.name.clone(),
+            success: res.is_ok(),
+        });
+        match res {
+            Ok(()) => lines.push(format!("  - {}: passed in {:.2?}", test.name, elapsed)),
+            Err(err) => {
+                error!("test {} failed: {}", test.name, err);
+                lines.push(format!("  - {}: {} in {:.2?}", test.name, err, elapsed));
+                failed.push(test.name.clone());
+            }
+        }
+    }
+    println!("Tests of \"{}\":\n{}", release.name, lines.join("\n"));
+    if !failed.is_empty() {
+        bail!(
+            "{} test(s) of {} failed: {}",
+            failed.len(),
+            release.name,
+            failed.join(", ")
+        );
+    }
+    Ok(())
+}
+
+async fn run_test(test: &Test, templates: &Templates, tag: &str) -> Result<()> {
+    let cmd = templates
+        .render(&test.command, tag_context(tag))
+        .wrap_err_with(|| format!("error rendering command of test {}", test.name))?;
+    info!("running test {}: {}", test.name, cmd);
+    let (mut child, group) =
+        spawn_tracked(wrapped_command(&["sh", "-c", &cmd]).envs(test.env.iter().flatten()))
+            .wrap_err_with(|| format!("error executing test {}", test.name))?;
+    let status = match test.timeout_minutes {
+        Some(minutes) => match time::timeout(Duration::from_secs(minutes * 60), child.wait()).await
+        {
+            Ok(status) => status?,
+            Err(_) => {
+                // Kills the processes the command started too.
+                group.kill();
+                bail!("timed out after {} minute(s)", minutes);
+            }
+        },
+        None => child.wait().await?,
+    };
+    if !status.success() {
+        bail!("failed with {}", status);
+    }
+    Ok(())
+}
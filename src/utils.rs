@@ -1,123 +1,692 @@
-use eyre::{bail, Result};
+use crate::config::{ArchiveFormat, ArchivePreset, Build, Compression, Licenses, Release};
+use eyre::{bail, Context, Result};
+use log::warn;
 // use async_zip::write::{EntryOptions, ZipFileWriter};
 use camino::Utf8Path;
-use std::{fs, io};
-use tokio::{process::Command, task};
+use sha2::{Digest, Sha256};
+use std::io::{IsTerminal, Write};
+use std::sync::Mutex;
+use std::{env, fs, io};
+use tokio::process::{Child, Command};
+use tokio::task;
 
-// Gets the latest tag if it exists.
-pub async fn get_latest_tag() -> Result<String> {
-    let mut cmd = Command::new("git");
-    cmd.args(vec!["describe", "--abbrev=0"]);
-    let output = cmd.output().await?;
-    if !output.status.success() {
-        bail!("error getting latest tag");
+// Process groups of the running builds, hooks and targets, which are killed
+// if rlsr is interrupted.
+static CHILD_GROUPS: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+
+// Words of the config's `shell_wrapper`, e.g. `nix develop -c`, which the
+// build and hook commands are prefixed with.
+static SHELL_WRAPPER: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+// Values decrypted from the config, which are redacted from the logs.
+static SECRETS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+// Values shorter than this aren't redacted, since they'd hide unrelated
+// text like `true` or numbers.
+const MIN_SECRET_LEN: usize = 4;
+
+pub(crate) fn add_secret(secret: &str) {
+    if secret.len() >= MIN_SECRET_LEN {
+        SECRETS.lock().unwrap().push(secret.to_string());
     }
-    Ok(String::from(
-        String::from_utf8_lossy(&output.stdout).to_string().trim(),
-    ))
 }
 
-// Gets all the tags for the current repo.
-pub async fn get_all_tags() -> Result<Vec<String>> {
-    let mut cmd = Command::new("git");
-    cmd.args(vec!["tag", "--list"]);
-    let output = cmd.output().await?;
-    if !output.status.success() {
-        bail!(
-            "error getting all tags: {}",
-            String::from_utf8_lossy(&output.stdout).to_string()
-        );
+// Replaces the secrets decrypted from the config in the message with `***`.
+pub fn redact(message: &str) -> String {
+    let mut message = message.to_string();
+    for secret in SECRETS.lock().unwrap().iter() {
+        message = message.replace(secret.as_str(), "***");
+    }
+    message
+}
+
+// Makes a command for a long running child process, e.g. a build or a hook.
+// It runs in its own process group so that the processes it starts can be
+// killed along with it, and it's killed if its future is dropped.
+pub fn group_command(program: &str) -> Command {
+    let mut cmd = Command::from(std_group_command(program));
+    cmd.kill_on_drop(true);
+    cmd
+}
+
+// Makes a blocking command running in its own process group, e.g. for the
+// synchronous template functions.
+fn std_group_command(program: &str) -> std::process::Command {
+    #[allow(unused_mut)]
+    let mut cmd = std::process::Command::new(program);
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(&mut cmd, 0);
+    cmd
+}
+
+// Sets the shell wrapper which `wrapped_command` prefixes commands with.
+pub fn set_shell_wrapper(wrapper: Option<&str>) {
+    *SHELL_WRAPPER.lock().unwrap() = wrapper
+        .map(|w| w.split_whitespace().map(String::from).collect())
+        .unwrap_or_default();
+}
+
+// Makes a `group_command` executing the args, prefixed with the shell
+// wrapper if one is set.
+pub fn wrapped_command<S: AsRef<str>>(args: &[S]) -> Command {
+    let wrapper = SHELL_WRAPPER.lock().unwrap().clone();
+    let args = wrap_args(&wrapper, args);
+    let mut cmd = group_command(args.first().map(String::as_str).unwrap_or_default());
+    cmd.args(args.iter().skip(1));
+    cmd
+}
+
+// Makes a blocking `std_group_command` executing the args, prefixed with
+// the shell wrapper if one is set.
+pub fn wrapped_std_command<S: AsRef<str>>(args: &[S]) -> std::process::Command {
+    let wrapper = SHELL_WRAPPER.lock().unwrap().clone();
+    let args = wrap_args(&wrapper, args);
+    let mut cmd = std_group_command(args.first().map(String::as_str).unwrap_or_default());
+    cmd.args(args.iter().skip(1));
+    cmd
+}
+
+// Prefixes the args with the wrapper's args.
+fn wrap_args<S: AsRef<str>>(wrapper: &[String], args: &[S]) -> Vec<String> {
+    wrapper
+        .iter()
+        .cloned()
+        .chain(args.iter().map(|a| a.as_ref().to_string()))
+        .collect()
+}
+
+// Splits the command into its args like a shell would, so that quoted args
+// with spaces stay whole. The command isn't run by a shell though.
+pub fn split_command(command: &str) -> Result<Vec<String>> {
+    shell_words::split(command).wrap_err_with(|| format!("error parsing command: {}", command))
+}
+
+// Keeps the child's process group tracked until it's dropped.
+pub struct ChildGroup(Option<u32>);
+
+impl ChildGroup {
+    // Sends SIGTERM to the process group, e.g. when its command timed out.
+    pub fn kill(&self) {
+        if let Some(pid) = self.0 {
+            kill_group(pid);
+        }
+    }
+}
+
+impl Drop for ChildGroup {
+    fn drop(&mut self) {
+        if let Some(pid) = self.0 {
+            CHILD_GROUPS.lock().unwrap().retain(|p| *p != pid);
+        }
+    }
+}
+
+// Spawns the command made with `group_command` and tracks its process group
+// while the returned guard is alive.
+pub fn spawn_tracked(cmd: &mut Command) -> io::Result<(Child, ChildGroup)> {
+    let child = cmd.spawn()?;
+    let pid = child.id();
+    if let Some(pid) = pid {
+        CHILD_GROUPS.lock().unwrap().push(pid);
+    }
+    Ok((child, ChildGroup(pid)))
+}
+
+// Runs the blocking command made with `std_group_command` and returns its
+// output, tracking its process group while it runs.
+pub fn output_tracked(cmd: &mut std::process::Command) -> io::Result<std::process::Output> {
+    let child = cmd.spawn()?;
+    let pid = child.id();
+    CHILD_GROUPS.lock().unwrap().push(pid);
+    let _group = ChildGroup(Some(pid));
+    child.wait_with_output()
+}
+
+// Sends SIGTERM to the process groups of all the running child processes.
+pub fn kill_child_groups() {
+    let groups = std::mem::take(&mut *CHILD_GROUPS.lock().unwrap());
+    for pid in groups {
+        warn!("killing process group {}", pid);
+        kill_group(pid);
     }
-    let out = String::from_utf8_lossy(&output.stdout).to_string();
-    Ok(out
-        .split('\n')
-        .map(String::from)
-        .filter(|tag| !tag.is_empty())
-        .collect())
 }
 
-async fn get_previous_tag() -> Result<String> {
-    // Get previous tag's commit.
+fn kill_group(pid: u32) {
+    let _ = std::process::Command::new("kill")
+        .args(["-TERM", "--", &format!("-{}", pid)])
+        .status();
+}
+
+// Fetches the full history and all the tags of a shallow clone. This needs
+// the network, so it uses the git CLI.
+pub async fn unshallow_repo() -> Result<()> {
     let mut cmd = Command::new("git");
-    cmd.args(vec!["rev-list", "--tags", "--skip=1", "--max-count=1"]);
+    cmd.args(vec!["fetch", "--unshallow", "--tags"]);
     let output = cmd.output().await?;
     if !output.status.success() {
         bail!(
-            "error getting previous tag commit: {}",
-            String::from_utf8_lossy(&output.stdout).to_string()
+            "error fetching full history: {}",
+            String::from_utf8_lossy(&output.stderr).to_string()
         );
     }
-    let prev_tag_commit = String::from_utf8_lossy(&output.stdout).to_string();
-    let prev_tag_commit = prev_tag_commit.trim();
+    Ok(())
+}
 
-    // Get tag for the commit.
-    let mut cmd = Command::new("git");
-    cmd.args(vec!["describe", "--abbrev=0", "--tags", prev_tag_commit]);
-    let output = cmd.output().await?;
+// Checks if the remote has the tag. This needs the network, so it uses the
+// git CLI.
+pub async fn remote_has_tag(remote: &str, tag: &str) -> Result<bool> {
+    let output = Command::new("git")
+        .args(["ls-remote", "--tags", remote, &format!("refs/tags/{}", tag)])
+        .output()
+        .await?;
     if !output.status.success() {
         bail!(
-            "error getting previous tag: {}",
-            String::from_utf8_lossy(&output.stdout).to_string()
+            "error listing tags of remote {}: {}",
+            remote,
+            String::from_utf8_lossy(&output.stderr).trim()
         );
     }
-    let prev_tag = String::from_utf8_lossy(&output.stdout).to_string();
-    Ok(String::from(prev_tag.trim()))
+    Ok(!output.stdout.is_empty())
 }
 
-// Get formatted git log.
-pub async fn get_all_git_log() -> Result<String> {
-    let mut cmd = Command::new("git");
-    cmd.args(vec!["log", "--format=%h: %B"]);
-    let output = cmd.output().await?;
+// Pushes the tag to the remote with the git CLI.
+pub async fn push_tag(remote: &str, tag: &str) -> Result<()> {
+    let output = Command::new("git")
+        .args(["push", remote, &format!("refs/tags/{}", tag)])
+        .output()
+        .await?;
     if !output.status.success() {
         bail!(
-            "error getting git log: {}",
-            String::from_utf8_lossy(&output.stdout).to_string()
+            "error pushing tag {} to {}: {}",
+            tag,
+            remote,
+            String::from_utf8_lossy(&output.stderr).trim()
         );
     }
-    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    Ok(())
 }
 
-pub async fn get_changelog() -> Result<String> {
-    // Get previous tag.
-    let prev_tag = get_previous_tag().await?;
-    let latest_tag = get_latest_tag().await?;
+// Parses a size like `500MB`, `2G` or `1024`. Units are powers of 1024.
+pub fn parse_size(size: &str) -> Result<u64> {
+    let size = size.trim();
+    let split = size
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(size.len());
+    let (num, unit) = size.split_at(split);
+    let num: f64 = match num.parse() {
+        Ok(num) => num,
+        Err(_) => bail!("invalid size: {}", size),
+    };
+    let multiplier: u64 = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" | "KIB" => 1 << 10,
+        "M" | "MB" | "MIB" => 1 << 20,
+        "G" | "GB" | "GIB" => 1 << 30,
+        "T" | "TB" | "TIB" => 1 << 40,
+        _ => bail!("invalid size unit in {}", size),
+    };
+    Ok((num * multiplier as f64) as u64)
+}
 
-    let mut cmd = Command::new("git");
-    cmd.args(vec![
-        "log",
-        "--format=%h: %B",
-        &format!("{}..{}", prev_tag, latest_tag),
-    ]);
-    let output = cmd.output().await?;
+// Formats the size in bytes with the largest unit which fits, e.g. `1.5MB`.
+pub fn format_size(size: u64) -> String {
+    let units = ["B", "KB", "MB", "GB", "TB"];
+    let mut value = size as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < units.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        return format!("{}B", size);
+    }
+    format!("{:.1}{}", value, units[unit])
+}
+
+// Checks if the binary is in PATH, or exists if it's a path.
+pub fn find_binary(bin: &str) -> bool {
+    if bin.contains('/') {
+        return Utf8Path::new(bin).is_file();
+    }
+    env::var_os("PATH")
+        .map(|paths| env::split_paths(&paths).any(|dir| dir.join(bin).is_file()))
+        .unwrap_or(false)
+}
+
+// Checks out the given ref with the git CLI, so that the work tree is
+// updated the same way as a manual checkout.
+pub async fn checkout(rev: &str) -> Result<()> {
+    let output = Command::new("git")
+        .args(["checkout", "--quiet", rev])
+        .output()
+        .await?;
     if !output.status.success() {
         bail!(
-            "error getting changelog: {}",
-            String::from_utf8_lossy(&output.stdout).to_string()
+            "error checking out {}: {}",
+            rev,
+            String::from_utf8_lossy(&output.stderr).trim()
         );
     }
-    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    Ok(())
+}
+
+// Gets the archive settings of the build, which override the release's.
+pub fn build_compression(release: &Release, build: &Build) -> Compression {
+    build
+        .compression
+        .clone()
+        .or_else(|| release.compression.clone())
+        .unwrap_or_default()
+}
+
+// Gets the file name of the build's archive from its rendered name. Names
+// can contain dots (e.g. a templated version), so the extension is appended
+// instead of being set.
+pub fn archive_name(release: &Release, build: &Build, name: &str) -> String {
+    let ext = match build_compression(release, build).format.unwrap_or_default() {
+        ArchiveFormat::Zip => "zip",
+        ArchiveFormat::TarGz => "tar.gz",
+        ArchiveFormat::TarZst => "tar.zst",
+    };
+    format!("{}.{}", name, ext)
+}
+
+// Files of the repo root the standard preset includes in the archives.
+const STANDARD_PRESET: &[&str] = &["LICENSE*", "README*", "CHANGELOG*"];
+
+// Gets the files included in the build's archive along with its binary, with
+// their paths in the archive: the ones of its preset, which are skipped if
+// they don't exist, its `additional_files` and the release's third party
+// notices.
+pub fn additional_files(release: &Release, build: &Build) -> Result<Vec<(String, String)>> {
+    let mut files = vec![];
+    if build.preset.or(release.preset) == Some(ArchivePreset::Standard) {
+        let options = glob::MatchOptions {
+            case_sensitive: false,
+            ..Default::default()
+        };
+        for pattern in STANDARD_PRESET {
+            for path in glob::glob_with(pattern, options)?.flatten() {
+                if path.is_file() {
+                    files.push(path.to_string_lossy().to_string());
+                }
+            }
+        }
+    }
+    if let Some(patterns) = &build.additional_files {
+        for file in expand_globs(patterns)? {
+            if !files.contains(&file) {
+                files.push(file);
+            }
+        }
+    }
+    let mut files: Vec<(String, String)> = files
+        .into_iter()
+        .map(|file| {
+            let name = entry_name(&file);
+            (file, name)
+        })
+        .collect();
+    // The notices are at the root of the archive.
+    if let Some(cfg) = &release.licenses {
+        let notices = notices_path(release, cfg);
+        let name = Utf8Path::new(&notices).file_name().unwrap_or_default();
+        files.push((notices.clone(), name.to_string()));
+    }
+    Ok(files)
+}
+
+// Gets the path of the third party notices the license command writes.
+pub fn notices_path(release: &Release, cfg: &Licenses) -> String {
+    match &cfg.output {
+        Some(output) => output.clone(),
+        None => Utf8Path::new(&release.dist_folder)
+            .join("THIRD_PARTY_NOTICES")
+            .to_string(),
+    }
 }
 
-// Creates an zip archive with the file given.
-pub async fn archive_file(filename: String, dist: String, name: String) -> Result<String> {
+// Creates an archive with the binary and the additional files given, in the
+// format and with the compression level configured. The archive is named
+// `archive`, from `archive_name`.
+pub async fn archive_file(
+    filename: String,
+    additional_files: Vec<(String, String)>,
+    dist: String,
+    archive: String,
+    compression: Compression,
+) -> Result<String> {
     let path: Result<String> = task::spawn_blocking(move || {
-        let mut f = fs::File::open(&filename)?;
-        let mut zip_path = Utf8Path::new(&dist).join(name);
-        zip_path.set_extension("zip");
-        let zip_file = fs::File::create(&zip_path)?;
-        let mut zip = zip::ZipWriter::new(zip_file);
-        // // Get only filename for the archive.
-        let fpath = Utf8Path::new(&filename);
-        let fname = fpath.file_name().unwrap();
-
-        let options = zip::write::FileOptions::default()
-            .compression_method(zip::CompressionMethod::Deflated)
-            .unix_permissions(0o744);
-        zip.start_file(fname, options)?;
-        io::copy(&mut f, &mut zip)?;
-        Ok(zip_path.to_string())
+        let archive_path = Utf8Path::new(&dist).join(archive);
+        let archive_file = fs::File::create(&archive_path)?;
+        // The binary is at the root of the archive.
+        let fname = Utf8Path::new(&filename).file_name().unwrap().to_string();
+        let mut entries = vec![(filename, fname, 0o744)];
+        for (file, name) in additional_files {
+            entries.push((file, name, 0o644));
+        }
+
+        match compression.format.unwrap_or_default() {
+            ArchiveFormat::Zip => {
+                let mut zip = zip::ZipWriter::new(archive_file);
+                for (path, name, mode) in entries {
+                    let options = zip::write::FileOptions::default()
+                        .compression_method(zip::CompressionMethod::Deflated)
+                        .compression_level(compression.level)
+                        .unix_permissions(mode);
+                    zip.start_file(name, options)?;
+                    io::copy(&mut fs::File::open(&path)?, &mut zip)?;
+                }
+                zip.finish()?;
+            }
+            ArchiveFormat::TarGz => {
+                let level = match compression.level {
+                    Some(level) => flate2::Compression::new(level.clamp(0, 9) as u32),
+                    None => flate2::Compression::default(),
+                };
+                let mut gz = flate2::write::GzEncoder::new(archive_file, level);
+                write_tar(&mut gz, &entries)?;
+                gz.finish()?;
+            }
+            ArchiveFormat::TarZst => {
+                // Level 0 is zstd's default level.
+                let mut zst = zstd::Encoder::new(archive_file, compression.level.unwrap_or(0))?;
+                let threads = compression.threads.unwrap_or_else(|| {
+                    std::thread::available_parallelism().map_or(1, |n| n.get() as u32)
+                });
+                if threads > 1 {
+                    zst.multithread(threads)?;
+                }
+                write_tar(&mut zst, &entries)?;
+                zst.finish()?;
+            }
+        }
+        Ok(archive_path.to_string())
     })
     .await?;
     path
 }
+
+// Gets the path of the file in the archive. Relative paths are kept, e.g.
+// `completions/rlsr.bash`, and absolute ones are put at the root.
+fn entry_name(path: &str) -> String {
+    let path = Utf8Path::new(path);
+    if path.is_absolute() || path.as_str().contains("..") {
+        return path.file_name().unwrap_or_default().to_string();
+    }
+    path.as_str().trim_start_matches("./").to_string()
+}
+
+// Writes a tar stream with the entries (path, name in the archive and mode),
+// in the ustar format. The entries' owner and mtime are zeroed so that
+// archives of the same files are identical.
+fn write_tar<W: Write>(w: &mut W, entries: &[(String, String, u32)]) -> Result<()> {
+    for (path, name, mode) in entries {
+        let mut f = fs::File::open(path)?;
+        write_tar_entry(w, name, *mode, &mut f)?;
+    }
+    // The archive ends with two zeroed blocks.
+    w.write_all(&[0u8; 1024])?;
+    Ok(())
+}
+
+fn write_tar_entry<W: Write>(w: &mut W, path: &str, mode: u32, f: &mut fs::File) -> Result<()> {
+    // Paths longer than 100 bytes are split into a prefix of up to 155
+    // bytes and the name at a `/`.
+    let (prefix, name) = match path.len() {
+        0..=100 => ("", path),
+        _ => match path
+            .match_indices('/')
+            .map(|(i, _)| i)
+            .find(|i| *i <= 155 && path.len() - i - 1 <= 100)
+        {
+            Some(i) => (&path[..i], &path[i + 1..]),
+            None => bail!("file name {} is too long for a tar archive", path),
+        },
+    };
+    let size = f.metadata()?.len();
+    if size > 0o77777777777 {
+        bail!("{} is too big for a tar archive", path);
+    }
+    let mut header = [0u8; 512];
+    header[..name.len()].copy_from_slice(name.as_bytes());
+    header[345..345 + prefix.len()].copy_from_slice(prefix.as_bytes());
+    header[100..108].copy_from_slice(format!("{:07o}\0", mode).as_bytes());
+    header[108..116].copy_from_slice(b"0000000\0");
+    header[116..124].copy_from_slice(b"0000000\0");
+    header[124..136].copy_from_slice(format!("{:011o}\0", size).as_bytes());
+    header[136..148].copy_from_slice(b"00000000000\0");
+    header[156] = b'0';
+    header[257..263].copy_from_slice(b"ustar\0");
+    header[263..265].copy_from_slice(b"00");
+    // The checksum is computed with its own field filled with spaces.
+    header[148..156].copy_from_slice(b"        ");
+    let sum: u32 = header.iter().map(|b| *b as u32).sum();
+    header[148..156].copy_from_slice(format!("{:06o}\0 ", sum).as_bytes());
+
+    w.write_all(&header)?;
+    io::copy(f, w)?;
+    // The entry is padded to 512 byte blocks.
+    let padding = (512 - size % 512) % 512;
+    w.write_all(&vec![0u8; padding as usize])?;
+    Ok(())
+}
+
+// Creates a tar.gz archive of the folder, with the folder's name as the
+// prefix of the entries. Files are added in order and executables keep their
+// mode, so that archives of the same folder are identical.
+pub async fn archive_dir(dir: String, archive_path: String) -> Result<()> {
+    task::spawn_blocking(move || {
+        let dir = Utf8Path::new(&dir);
+        let mut entries = vec![];
+        dir_entries(dir, dir, &mut entries)?;
+        entries.sort();
+        let archive_file = fs::File::create(&archive_path)
+            .wrap_err_with(|| format!("error creating {}", archive_path))?;
+        let mut gz = flate2::write::GzEncoder::new(archive_file, flate2::Compression::default());
+        write_tar(&mut gz, &entries)?;
+        gz.finish()?;
+        Ok(())
+    })
+    .await?
+}
+
+// Collects the files in the folder and its subfolders as tar entries.
+fn dir_entries(
+    root: &Utf8Path,
+    dir: &Utf8Path,
+    entries: &mut Vec<(String, String, u32)>,
+) -> Result<()> {
+    for entry in fs::read_dir(dir).wrap_err_with(|| format!("error reading {}", dir))? {
+        let entry = entry?;
+        let path = dir.join(entry.file_name().to_string_lossy().as_ref());
+        let meta = fs::metadata(&path)?;
+        if meta.is_dir() {
+            dir_entries(root, &path, entries)?;
+            continue;
+        }
+        #[cfg(unix)]
+        let mode = match std::os::unix::fs::PermissionsExt::mode(&meta.permissions()) & 0o111 {
+            0 => 0o644,
+            _ => 0o755,
+        };
+        #[cfg(not(unix))]
+        let mode = 0o644;
+        let name = Utf8Path::new(root.file_name().unwrap_or_default())
+            .join(path.strip_prefix(root)?)
+            .to_string();
+        entries.push((path.to_string(), name, mode));
+    }
+    Ok(())
+}
+
+// Gets the hex encoded sha256 checksum of the given file.
+pub async fn sha256_file(filename: String) -> Result<String> {
+    let sum: Result<String> = task::spawn_blocking(move || {
+        let mut f = fs::File::open(&filename)?;
+        let mut hasher = Sha256::new();
+        io::copy(&mut f, &mut hasher)?;
+        Ok(hasher
+            .finalize()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect())
+    })
+    .await?;
+    sum
+}
+
+// Opens the given notes in `$EDITOR` (`vi` if it's not set) and returns the
+// edited notes.
+pub async fn edit_notes(notes: &str, name: &str) -> Result<String> {
+    let path = env::temp_dir().join(format!("rlsr-{}-notes.md", name.replace('/', "-")));
+    tokio::fs::write(&path, notes).await?;
+
+    let editor = env::var("EDITOR").unwrap_or_else(|_| String::from("vi"));
+    // Run with sh so that editors with args (e.g. `code --wait`) work.
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(format!("{} \"$1\"", editor))
+        .arg("rlsr")
+        .arg(&path)
+        .status()
+        .await?;
+    if !status.success() {
+        bail!("editor {} exited with {}", editor, status);
+    }
+
+    let edited = tokio::fs::read_to_string(&path).await?;
+    tokio::fs::remove_file(&path).await?;
+    Ok(edited)
+}
+
+// If the user can be asked for a confirmation, i.e. stdin is a terminal and
+// it's not a CI run.
+pub fn interactive() -> bool {
+    io::stdin().is_terminal() && env::var("CI").map_or(true, |v| v.is_empty() || v == "false")
+}
+
+// Asks the user for a yes/no confirmation on stdin. Anything other than `y` or
+// `yes` (including EOF) is a no.
+pub async fn confirm(prompt: &str) -> Result<bool> {
+    let prompt = prompt.to_string();
+    let answer: Result<String> = task::spawn_blocking(move || {
+        print!("{} [y/N]: ", prompt);
+        io::Write::flush(&mut io::stdout())?;
+        let mut answer = String::new();
+        io::stdin().read_line(&mut answer)?;
+        Ok(answer)
+    })
+    .await?;
+    Ok(matches!(
+        answer?.trim().to_lowercase().as_str(),
+        "y" | "yes"
+    ))
+}
+
+// Gets the files matching the globs, in order and without duplicates. Globs
+// which don't match any file are warned about.
+pub fn expand_globs(patterns: &[String]) -> Result<Vec<String>> {
+    let mut files: Vec<String> = vec![];
+    for pattern in patterns {
+        let mut matched = false;
+        let paths = glob::glob(pattern).wrap_err_with(|| format!("invalid glob: {}", pattern))?;
+        for path in paths {
+            let path = path?;
+            if !path.is_file() {
+                continue;
+            }
+            matched = true;
+            let path = path.to_string_lossy().to_string();
+            if !files.contains(&path) {
+                files.push(path);
+            }
+        }
+        if !matched {
+            warn!("no files match {}", pattern);
+        }
+    }
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_quoted_arguments() {
+        let args = split_command(r#"cargo build --features "a b" --config 'x = 1' a\ b"#).unwrap();
+        assert_eq!(
+            args,
+            [
+                "cargo",
+                "build",
+                "--features",
+                "a b",
+                "--config",
+                "x = 1",
+                "a b"
+            ]
+        );
+        let wrapper = ["nix".to_string(), "develop".to_string(), "-c".to_string()];
+        assert_eq!(
+            wrap_args(&wrapper, &args)[..5],
+            ["nix", "develop", "-c", "cargo", "build"]
+        );
+        assert_eq!(wrap_args(&[], &args), args);
+        assert!(split_command("echo \"unterminated").is_err());
+    }
+
+    #[test]
+    fn parses_sizes() {
+        assert_eq!(parse_size("1024").unwrap(), 1024);
+        assert_eq!(parse_size("2K").unwrap(), 2048);
+        assert_eq!(parse_size("500MB").unwrap(), 500 << 20);
+        assert_eq!(parse_size("1.5 GiB").unwrap(), 3 << 29);
+        assert_eq!(parse_size(" 1tb ").unwrap(), 1 << 40);
+        assert!(parse_size("MB").is_err());
+        assert!(parse_size("10PB").is_err());
+    }
+
+    // Writes a tar entry for a file with the path, returning the header.
+    fn tar_header(path: &str) -> Result<Vec<u8>> {
+        let file = env::temp_dir().join(format!("rlsr-tar-test-{}", path.len()));
+        fs::write(&file, b"rlsr")?;
+        let mut out = vec![];
+        let res = write_tar_entry(&mut out, path, 0o644, &mut fs::File::open(&file)?);
+        fs::remove_file(&file)?;
+        res?;
+        assert_eq!(out.len(), 1024);
+        Ok(out[..512].to_vec())
+    }
+
+    fn field(header: &[u8], range: std::ops::Range<usize>) -> &str {
+        let field = &header[range];
+        let end = field.iter().position(|b| *b == 0).unwrap_or(field.len());
+        std::str::from_utf8(&field[..end]).unwrap()
+    }
+
+    #[test]
+    fn writes_short_tar_names() {
+        let header = tar_header("rlsr/bin/rlsr").unwrap();
+        assert_eq!(field(&header, 0..100), "rlsr/bin/rlsr");
+        assert_eq!(field(&header, 345..500), "");
+        assert_eq!(field(&header, 124..136), "00000000004");
+        assert_eq!(field(&header, 257..263), "ustar");
+    }
+
+    #[test]
+    fn splits_long_tar_names() {
+        let dir = format!("{}/{}", "a".repeat(80), "b".repeat(40));
+        let name = format!("{}.txt", "c".repeat(90));
+        let header = tar_header(&format!("{}/{}", dir, name)).unwrap();
+        assert_eq!(field(&header, 0..100), name);
+        assert_eq!(field(&header, 345..500), dir);
+    }
+
+    #[test]
+    fn fails_on_unsplittable_tar_names() {
+        assert!(tar_header(&"a".repeat(101)).is_err());
+        assert!(tar_header(&format!("{}/{}", "a".repeat(10), "b".repeat(101))).is_err());
+    }
+}
@@ -0,0 +1,52 @@
+use crate::config::{Release, Vendor};
+use crate::templating::{tag_context, Templates};
+use crate::utils::{archive_dir, group_command, spawn_tracked};
+use camino::Utf8Path;
+use eyre::{bail, Context, Result};
+use log::info;
+use minijinja::context;
+use tokio::fs;
+
+// Runs the vendor command and archives the folder it vendored the sources
+// into to `vendor.tar.gz` in the dist folder, so that packagers can build the
+// release offline. Returns the archive's path.
+pub async fn vendor_archive(
+    release: &Release,
+    cfg: &Vendor,
+    templates: &Templates,
+    tag: &str,
+) -> Result<String> {
+    let dir = cfg.dir.as_deref().unwrap_or("vendor");
+    let ctx = context! { dir => dir, ..tag_context(tag) };
+    let cmd = cfg
+        .command
+        .as_deref()
+        .unwrap_or("cargo vendor --locked {{ dir }}");
+    let cmd = templates
+        .render(cmd, ctx.clone())
+        .wrap_err_with(|| "error rendering vendor command")?;
+
+    info!("vendoring sources: {}", cmd);
+    let (mut child, _group) = spawn_tracked(group_command("sh").arg("-c").arg(&cmd))
+        .wrap_err_with(|| format!("error executing vendor command: {}", cmd))?;
+    let status = child
+        .wait()
+        .await
+        .wrap_err_with(|| format!("error executing vendor command: {}", cmd))?;
+    if !status.success() {
+        bail!("vendor command `{}` failed with {}", cmd, status);
+    }
+    if !Utf8Path::new(dir).is_dir() {
+        bail!("vendor command `{}` didn't create {}", cmd, dir);
+    }
+
+    let name = match &cfg.name {
+        Some(name) => templates.render(name, ctx)?,
+        None => "vendor.tar.gz".to_string(),
+    };
+    fs::create_dir_all(&release.dist_folder).await?;
+    let path = Utf8Path::new(&release.dist_folder).join(name);
+    archive_dir(dir.to_string(), path.to_string()).await?;
+    info!("archived vendored sources to {}", path);
+    Ok(path.to_string())
+}
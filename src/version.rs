@@ -0,0 +1,207 @@
+use crate::config::{Version, VersionScheme};
+use crate::templating::tag_version;
+use chrono::{Datelike, NaiveDate, Utc};
+use eyre::{bail, Context, Result};
+use regex::Regex;
+
+// Calendar versions are `YYYY.MM.MICRO` with an optional prerelease suffix.
+const CALVER_PATTERN: &str = r"^(\d{4})\.(\d{1,2})\.(\d+)(?:-(?P<prerelease>.+))?$";
+
+pub enum Part {
+    Major,
+    Minor,
+    Patch,
+}
+
+// Checks that the tag follows the configured version scheme.
+pub fn validate_tag(cfg: &Version, tag: &str) -> Result<()> {
+    let scheme = cfg.scheme.clone().unwrap_or(VersionScheme::Semver);
+    let version = tag_version(tag);
+    let valid = match scheme {
+        VersionScheme::Semver => semver::Version::parse(version).is_ok(),
+        VersionScheme::Calver => match calver_regex().captures(version) {
+            Some(caps) => (1..=12).contains(&caps[2].parse::<u32>().unwrap_or(0)),
+            None => false,
+        },
+        VersionScheme::Regex => custom_regex(cfg)?.is_match(tag),
+    };
+    if !valid {
+        bail!(
+            "tag {} doesn't follow the {} version scheme",
+            tag,
+            scheme_name(&scheme)
+        );
+    }
+    Ok(())
+}
+
+// Checks if the tag is a prerelease according to the configured version
+// scheme. For the `regex` scheme, a tag is a prerelease if the `prerelease`
+// named group matches.
+pub fn is_prerelease(cfg: &Version, tag: &str) -> Result<bool> {
+    let version = tag_version(tag);
+    let prerelease = match cfg.scheme.clone().unwrap_or(VersionScheme::Semver) {
+        VersionScheme::Semver => semver::Version::parse(version)
+            .map(|v| !v.pre.is_empty())
+            .unwrap_or(false),
+        VersionScheme::Calver => calver_regex()
+            .captures(version)
+            .is_some_and(|caps| caps.name("prerelease").is_some()),
+        VersionScheme::Regex => custom_regex(cfg)?
+            .captures(tag)
+            .and_then(|caps| caps.name("prerelease"))
+            .is_some_and(|m| !m.as_str().is_empty()),
+    };
+    Ok(prerelease)
+}
+
+// Increments the given part of the version. Calendar versions always move to
+// the current month, bumping the micro part if the version is already from
+// the current month.
+pub fn increment(scheme: &VersionScheme, version: &str, part: &Part) -> Result<String> {
+    increment_on(scheme, version, part, Utc::now().date_naive())
+}
+
+// Increments the version as of the date, which calendar versions move to.
+fn increment_on(
+    scheme: &VersionScheme,
+    version: &str,
+    part: &Part,
+    today: NaiveDate,
+) -> Result<String> {
+    let prefix = if version.starts_with('v') { "v" } else { "" };
+    let version = version.trim_start_matches('v');
+    match scheme {
+        VersionScheme::Semver => {
+            let mut v = semver::Version::parse(version)
+                .wrap_err_with(|| format!("{} isn't a semver version", version))?;
+            match part {
+                Part::Major => {
+                    v.major += 1;
+                    v.minor = 0;
+                    v.patch = 0;
+                }
+                Part::Minor => {
+                    v.minor += 1;
+                    v.patch = 0;
+                }
+                Part::Patch => v.patch += 1,
+            }
+            v.pre = semver::Prerelease::EMPTY;
+            v.build = semver::BuildMetadata::EMPTY;
+            Ok(format!("{}{}", prefix, v))
+        }
+        VersionScheme::Calver => {
+            let caps = match calver_regex().captures(version) {
+                Some(caps) => caps,
+                None => bail!("{} isn't a calver version", version),
+            };
+            let same_month = caps[1].parse::<i32>().ok() == Some(today.year())
+                && caps[2].parse::<u32>().ok() == Some(today.month());
+            let micro = if same_month {
+                caps[3].parse::<u64>().unwrap_or(0) + 1
+            } else {
+                0
+            };
+            Ok(format!(
+                "{}{}.{:02}.{}",
+                prefix,
+                today.year(),
+                today.month(),
+                micro
+            ))
+        }
+        VersionScheme::Regex => {
+            bail!("incrementing versions isn't supported with the regex version scheme")
+        }
+    }
+}
+
+fn scheme_name(scheme: &VersionScheme) -> &'static str {
+    match scheme {
+        VersionScheme::Semver => "semver",
+        VersionScheme::Calver => "calver",
+        VersionScheme::Regex => "regex",
+    }
+}
+
+fn calver_regex() -> Regex {
+    Regex::new(CALVER_PATTERN).unwrap()
+}
+
+fn custom_regex(cfg: &Version) -> Result<Regex> {
+    let pattern = match &cfg.pattern {
+        Some(pattern) => pattern,
+        None => bail!("version.pattern is required with the regex version scheme"),
+    };
+    Regex::new(pattern).wrap_err_with(|| format!("invalid version pattern: {}", pattern))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scheme(scheme: VersionScheme, pattern: Option<&str>) -> Version {
+        Version {
+            scheme: Some(scheme),
+            pattern: pattern.map(String::from),
+        }
+    }
+
+    #[test]
+    fn increments_semver() {
+        let semver = VersionScheme::Semver;
+        assert_eq!(
+            increment(&semver, "v1.2.3", &Part::Major).unwrap(),
+            "v2.0.0"
+        );
+        assert_eq!(
+            increment(&semver, "v1.2.3", &Part::Minor).unwrap(),
+            "v1.3.0"
+        );
+        assert_eq!(increment(&semver, "1.2.3", &Part::Patch).unwrap(), "1.2.4");
+        assert_eq!(
+            increment(&semver, "v1.2.3-rc.1+abc", &Part::Patch).unwrap(),
+            "v1.2.4"
+        );
+        assert!(increment(&semver, "v1.2", &Part::Patch).is_err());
+    }
+
+    #[test]
+    fn increments_calver() {
+        let calver = VersionScheme::Calver;
+        let today = NaiveDate::from_ymd_opt(2024, 5, 20).unwrap();
+        assert_eq!(
+            increment_on(&calver, "v2024.05.4", &Part::Patch, today).unwrap(),
+            "v2024.05.5"
+        );
+        assert_eq!(
+            increment_on(&calver, "2024.04.4", &Part::Patch, today).unwrap(),
+            "2024.05.0"
+        );
+        assert_eq!(
+            increment_on(&calver, "v2023.05.1-beta", &Part::Patch, today).unwrap(),
+            "v2024.05.0"
+        );
+        assert!(increment_on(&calver, "1.2.3", &Part::Patch, today).is_err());
+        assert!(increment(&VersionScheme::Regex, "v1", &Part::Patch).is_err());
+    }
+
+    #[test]
+    fn validates_tags() {
+        let semver = scheme(VersionScheme::Semver, None);
+        assert!(validate_tag(&semver, "v1.2.3").is_ok());
+        assert!(validate_tag(&semver, "v1.2.3-rc.1").is_ok());
+        assert!(validate_tag(&semver, "v1.2").is_err());
+
+        let calver = scheme(VersionScheme::Calver, None);
+        assert!(validate_tag(&calver, "v2024.05.1").is_ok());
+        assert!(validate_tag(&calver, "2024.5.0-beta").is_ok());
+        assert!(validate_tag(&calver, "v2024.13.0").is_err());
+
+        let regex = scheme(VersionScheme::Regex, Some(r"^release-\d+$"));
+        assert!(validate_tag(&regex, "release-42").is_ok());
+        assert!(validate_tag(&regex, "v42").is_err());
+        assert!(validate_tag(&scheme(VersionScheme::Regex, None), "v42").is_err());
+    }
+}
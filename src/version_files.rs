@@ -0,0 +1,95 @@
+use crate::config::{VersionFiles, VersionFilesAfter};
+use crate::templating::{tag_context, tag_version, Templates};
+use eyre::{bail, Context, Result};
+use log::info;
+use regex::Regex;
+use tokio::{fs, process::Command};
+
+// Rewrites the version in the files before building. Every pattern has to
+// match, and the file has to have the new version afterwards. Returns the
+// original contents of the changed files so that they can be reverted.
+pub async fn apply(
+    cfg: &VersionFiles,
+    templates: &Templates,
+    tag: &str,
+) -> Result<Vec<(String, String)>> {
+    let mut originals = vec![];
+    for file in &cfg.files {
+        let pattern = Regex::new(&file.pattern).wrap_err_with(|| {
+            format!(
+                "invalid version pattern for {}: {}",
+                file.path, file.pattern
+            )
+        })?;
+        let replace = templates
+            .render(&file.replace, tag_context(tag))
+            .wrap_err_with(|| format!("error rendering version for {}", file.path))?;
+        let contents = fs::read_to_string(&file.path)
+            .await
+            .wrap_err_with(|| format!("error reading version file {}", file.path))?;
+        if !pattern.is_match(&contents) {
+            bail!(
+                "version pattern {} doesn't match anything in {}",
+                file.pattern,
+                file.path
+            );
+        }
+
+        let updated = pattern.replace_all(&contents, replace.as_str()).to_string();
+        if updated == contents {
+            info!("version in {} is already {}", file.path, tag_version(tag));
+            continue;
+        }
+        fs::write(&file.path, &updated)
+            .await
+            .wrap_err_with(|| format!("error writing version file {}", file.path))?;
+        let written = fs::read_to_string(&file.path).await?;
+        if !written.contains(tag_version(tag)) {
+            fs::write(&file.path, &contents).await?;
+            bail!(
+                "version {} isn't in {} after rewriting it",
+                tag_version(tag),
+                file.path
+            );
+        }
+        info!("set version {} in {}", tag_version(tag), file.path);
+        originals.push((file.path.clone(), contents));
+    }
+    Ok(originals)
+}
+
+// Reverts or commits the rewritten files after building, or leaves them as
+// they are.
+pub async fn finish(cfg: &VersionFiles, originals: Vec<(String, String)>, tag: &str) -> Result<()> {
+    match cfg.after {
+        Some(VersionFilesAfter::Revert) => {
+            for (path, contents) in originals {
+                info!("reverting version in {}", path);
+                fs::write(&path, contents)
+                    .await
+                    .wrap_err_with(|| format!("error reverting version file {}", path))?;
+            }
+        }
+        Some(VersionFilesAfter::Commit) => {
+            let paths: Vec<String> = originals.into_iter().map(|(path, _)| path).collect();
+            if paths.is_empty() {
+                return Ok(());
+            }
+            let message = format!("chore: set version to {}", tag_version(tag));
+            info!("committing version files: {}", paths.join(", "));
+            let output = Command::new("git")
+                .args(["commit", "-m", &message, "--"])
+                .args(&paths)
+                .output()
+                .await?;
+            if !output.status.success() {
+                bail!(
+                    "error committing version files: {}",
+                    String::from_utf8_lossy(&output.stderr).trim()
+                );
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
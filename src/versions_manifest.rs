@@ -0,0 +1,165 @@
+use crate::config::Release;
+use crate::release_provider::{AssetNames, ProviderError, PublishResult, ReleaseProvider};
+use crate::utils::sha256_file;
+use async_trait::async_trait;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use eyre::{bail, Context, Result};
+use log::{debug, info};
+use serde_json::{json, Value};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+/// VersionsManifest keeps a JSON manifest of all the released versions with
+/// download urls and checksums for every archive in a github repo. The
+/// manifest can be served from github pages and consumed by asdf/mise plugins
+/// or install scripts.
+pub struct VersionsManifest {
+    ghtoken: String,
+    asset_names: AssetNames,
+}
+
+impl VersionsManifest {
+    pub fn new(ghtoken: String, asset_names: AssetNames) -> Self {
+        VersionsManifest {
+            ghtoken,
+            asset_names,
+        }
+    }
+
+    // Builds the manifest entry for the given tag with the download url and
+    // checksum of every archive.
+    async fn version_entry(
+        release: &Release,
+        archives: Vec<String>,
+        asset_names: &AssetNames,
+        latest_tag: &str,
+    ) -> Result<Value> {
+        let gh = match &release.targets.github {
+            Some(gh) => gh,
+            None => bail!("versions manifest needs the github target for download urls"),
+        };
+
+        let mut assets = serde_json::Map::new();
+        for archive in archives {
+            let filename = asset_names.get(&archive);
+            let sha256 = sha256_file(archive.clone()).await?;
+            let url = format!(
+                "https://github.com/{}/{}/releases/download/{}/{}",
+                gh.owner, gh.repo, latest_tag, filename
+            );
+            assets.insert(filename, json!({ "url": url, "sha256": sha256 }));
+        }
+
+        Ok(json!({ "assets": assets }))
+    }
+}
+
+#[allow(clippy::needless_arbitrary_self_type)]
+#[async_trait]
+impl ReleaseProvider for VersionsManifest {
+    fn name(self: &Self) -> String {
+        "versions_manifest".to_string()
+    }
+
+    async fn publish(
+        self: &Self,
+        release: &Release,
+        all_archives: Arc<Mutex<Vec<String>>>,
+        latest_tag: String,
+        _changelog: String,
+    ) -> Result<PublishResult> {
+        let manifest = match &release.targets.versions_manifest {
+            Some(manifest) => manifest,
+            None => bail!("versions manifest config can't be empty"),
+        };
+        if self.ghtoken.is_empty() {
+            bail!("GITHUB_TOKEN is blank, skipping updating versions manifest");
+        }
+
+        let entry = Self::version_entry(
+            release,
+            all_archives.lock().await.to_vec(),
+            &self.asset_names,
+            &latest_tag,
+        )
+        .await?;
+
+        let ghclient = octocrab::Octocrab::builder()
+            .personal_token(self.ghtoken.clone())
+            .build()?;
+        let route = format!(
+            "/repos/{}/{}/contents/{}",
+            manifest.owner, manifest.repo, manifest.path
+        );
+
+        // Fetch the existing manifest, if any.
+        let res = ghclient
+            ._get(
+                ghclient.absolute_url(&route)?,
+                Some(&[("ref", &manifest.branch)]),
+            )
+            .await?;
+        let (mut current, sha) = if res.status() == reqwest::StatusCode::NOT_FOUND {
+            (json!({ "versions": {} }), None)
+        } else if res.status().is_success() {
+            let file: Value = res.json().await?;
+            let content = file["content"]
+                .as_str()
+                .unwrap_or_default()
+                .replace('\n', "");
+            let content = STANDARD
+                .decode(content)
+                .wrap_err_with(|| "error decoding versions manifest")?;
+            let current: Value = serde_json::from_slice(&content)
+                .wrap_err_with(|| format!("error parsing versions manifest {}", manifest.path))?;
+            (current, file["sha"].as_str().map(String::from))
+        } else {
+            return Err(ProviderError::from_status(
+                res.status(),
+                format!("error fetching versions manifest, status: {}", res.status()),
+            ));
+        };
+
+        current["latest"] = json!(latest_tag);
+        if !current["versions"].is_object() {
+            current["versions"] = json!({});
+        }
+        current["versions"][&latest_tag] = entry;
+
+        debug!("updated versions manifest: {}", current);
+        let mut body = json!({
+            "message": format!("Update versions manifest for {}", latest_tag),
+            "content": STANDARD.encode(serde_json::to_string_pretty(&current)?),
+            "branch": manifest.branch,
+        });
+        if let Some(sha) = sha {
+            body["sha"] = json!(sha);
+        }
+
+        let res = ghclient
+            ._put(ghclient.absolute_url(&route)?, Some(&body))
+            .await?;
+        let status = res.status();
+        if !status.is_success() {
+            return Err(ProviderError::from_status(
+                status,
+                format!(
+                    "error updating versions manifest, status: {}, error: {}",
+                    status,
+                    res.text().await?
+                ),
+            ));
+        }
+
+        info!(
+            "updated versions manifest at {}/{}/{}",
+            manifest.owner, manifest.repo, manifest.path
+        );
+        let mut result = PublishResult::new("versions_manifest");
+        result.release_url = Some(format!(
+            "https://github.com/{}/{}/blob/{}/{}",
+            manifest.owner, manifest.repo, manifest.branch, manifest.path
+        ));
+        Ok(result)
+    }
+}
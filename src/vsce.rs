@@ -0,0 +1,154 @@
+use crate::config::Release;
+use crate::release_provider::{ProviderError, PublishResult, ReleaseProvider};
+use crate::templating::tag_version;
+use crate::utils::{expand_globs, group_command, spawn_tracked};
+use async_trait::async_trait;
+use eyre::{bail, Context, Result};
+use log::{debug, info};
+use serde::Deserialize;
+use std::env;
+use std::io::Read;
+use std::process::Stdio;
+use std::sync::Arc;
+use tokio::{sync::Mutex, task};
+
+/// Vsce publishes a packaged VS Code extension to the marketplace with `vsce`
+/// and to Open VSX with `ovsx`, after checking that the extension's version
+/// is the tag's.
+pub struct Vsce {
+    prerelease: bool,
+}
+
+// Fields of the extension's `package.json` which identify it.
+#[derive(Deserialize)]
+struct Manifest {
+    name: String,
+    publisher: String,
+    version: String,
+}
+
+impl Vsce {
+    pub fn new(prerelease: bool) -> Self {
+        Vsce { prerelease }
+    }
+
+    // Publishes the vsix with the CLI, which reads the token from `token_env`.
+    async fn publish_with(&self, cli: &str, token_env: &str, vsix: &str) -> Result<()> {
+        if env::var(token_env).unwrap_or_default().is_empty() {
+            return Err(ProviderError::Auth(format!(
+                "{} is blank, can't publish with {}",
+                token_env, cli
+            ))
+            .into());
+        }
+        let mut cmd = group_command(cli);
+        match cli {
+            "vsce" => cmd.args(["publish", "--packagePath", vsix]),
+            _ => cmd.args(["publish", vsix]),
+        };
+        if self.prerelease {
+            cmd.arg("--pre-release");
+        }
+        info!("publishing {} with {}", vsix, cli);
+        let (child, _group) = spawn_tracked(cmd.stdout(Stdio::piped()).stderr(Stdio::piped()))
+            .wrap_err_with(|| format!("error executing {} publish", cli))?;
+        let output = child
+            .wait_with_output()
+            .await
+            .wrap_err_with(|| format!("error executing {} publish", cli))?;
+        debug!(
+            "{} publish output: {}",
+            cli,
+            String::from_utf8_lossy(&output.stdout)
+        );
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(ProviderError::from_output(
+                &stderr,
+                format!("error executing {} publish: {}", cli, stderr.trim()),
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[allow(clippy::needless_arbitrary_self_type)]
+#[async_trait]
+impl ReleaseProvider for Vsce {
+    fn name(self: &Self) -> String {
+        "vsce".to_string()
+    }
+
+    async fn publish(
+        self: &Self,
+        release: &Release,
+        all_archives: Arc<Mutex<Vec<String>>>,
+        latest_tag: String,
+        _changelog: String,
+    ) -> Result<PublishResult> {
+        let cfg = match &release.targets.vsce {
+            Some(cfg) => cfg,
+            None => bail!("vsce config can't be empty"),
+        };
+        let vsixes = match &cfg.vsix {
+            Some(vsix) => expand_globs(std::slice::from_ref(vsix))?,
+            None => all_archives
+                .lock()
+                .await
+                .iter()
+                .filter(|a| a.ends_with(".vsix"))
+                .cloned()
+                .collect(),
+        };
+        let vsix = match vsixes.as_slice() {
+            [vsix] => vsix.clone(),
+            [] => bail!("no .vsix to publish"),
+            _ => bail!("found {} .vsix files, set `vsix` to pick one", vsixes.len()),
+        };
+
+        let manifest = read_manifest(vsix.clone()).await?;
+        let version = tag_version(&latest_tag);
+        if manifest.version != version {
+            bail!(
+                "version of {} is {}, but the tag is {}",
+                vsix,
+                manifest.version,
+                latest_tag
+            );
+        }
+
+        let mut result = PublishResult::new("vsce");
+        if cfg.marketplace.unwrap_or(true) {
+            self.publish_with("vsce", "VSCE_PAT", &vsix).await?;
+            result.release_url = Some(format!(
+                "https://marketplace.visualstudio.com/items?itemName={}.{}",
+                manifest.publisher, manifest.name
+            ));
+        }
+        if cfg.open_vsx.unwrap_or(true) {
+            self.publish_with("ovsx", "OVSX_PAT", &vsix).await?;
+            result.release_url.get_or_insert(format!(
+                "https://open-vsx.org/extension/{}/{}",
+                manifest.publisher, manifest.name
+            ));
+        }
+        Ok(result)
+    }
+}
+
+// Reads the extension's `package.json` from the vsix, which is a zip.
+async fn read_manifest(vsix: String) -> Result<Manifest> {
+    task::spawn_blocking(move || {
+        let file =
+            std::fs::File::open(&vsix).wrap_err_with(|| format!("error opening {}", vsix))?;
+        let mut zip =
+            zip::ZipArchive::new(file).wrap_err_with(|| format!("error reading {}", vsix))?;
+        let mut contents = String::new();
+        zip.by_name("extension/package.json")
+            .wrap_err_with(|| format!("{} doesn't have a package.json", vsix))?
+            .read_to_string(&mut contents)?;
+        serde_json::from_str(&contents)
+            .wrap_err_with(|| format!("error parsing the package.json of {}", vsix))
+    })
+    .await?
+}
@@ -0,0 +1,62 @@
+mod common;
+
+use common::{commit, init_repo};
+use rlsr::config::Config;
+use rlsr::{backfill, Opts};
+use std::fs;
+
+// Backfills both tags of a repo whose HEAD is ahead of them. The first tag
+// has no previous tag, so its changelog is every commit up to it.
+#[tokio::test]
+async fn backfills_the_first_tag() {
+    let dir = init_repo("backfill");
+    commit(&dir, "a", "feat: first", Some("v0.1.0"));
+    commit(&dir, "b", "fix: second", Some("v0.2.0"));
+    commit(&dir, "c", "chore: after the tags", None);
+    // The payloads are saved in the ignored dist folder.
+    let out = dir.join("dist");
+    fs::create_dir_all(&out).unwrap();
+    // Backfill finds the repo from the current dir, and this is the only
+    // test in this binary.
+    std::env::set_current_dir(&dir).unwrap();
+
+    let cfg: Config = serde_yaml::from_str(&format!(
+        r#"
+releases:
+  - name: app
+    dist_folder: dist
+    targets:
+      custom:
+        - name: save
+          command: cat > "{}/$(git describe --tags --exact-match).json"
+    builds: []
+"#,
+        out.display()
+    ))
+    .unwrap();
+    backfill(
+        cfg,
+        Opts {
+            yes: true,
+            ..Default::default()
+        },
+        "v0.1.0..v0.2.0",
+    )
+    .await
+    .unwrap();
+
+    let changelog = |tag: &str| {
+        let payload = fs::read_to_string(out.join(format!("{}.json", tag))).unwrap();
+        let payload: serde_json::Value = serde_json::from_str(&payload).unwrap();
+        payload["changelog"].as_str().unwrap().to_string()
+    };
+    let first = changelog("v0.1.0");
+    assert!(first.contains("feat: first"), "{}", first);
+    assert!(!first.contains("fix: second"), "{}", first);
+    assert!(!first.contains("chore: after the tags"), "{}", first);
+    let second = changelog("v0.2.0");
+    assert!(second.contains("fix: second"), "{}", second);
+    assert!(!second.contains("feat: first"), "{}", second);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
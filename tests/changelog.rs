@@ -0,0 +1,65 @@
+mod common;
+
+use common::{commit, init_repo};
+use rlsr::config::Release;
+use rlsr::{Opts, ReleasePipeline};
+use std::fs;
+
+// Publishes the first of two tags with `--to-tag`, while HEAD is ahead of
+// both. The changelog of a tag without a previous tag has the commits up to
+// it, not the commits up to HEAD.
+#[tokio::test]
+async fn first_tag_changelog_ends_at_the_tag() {
+    let dir = init_repo("changelog");
+    commit(&dir, "a", "feat: first", Some("v0.1.0"));
+    commit(&dir, "b", "fix: second", Some("v0.2.0"));
+    commit(&dir, "c", "chore: after the tags", None);
+    // The stages find the repo from the current dir, and this is the only
+    // test in this binary.
+    std::env::set_current_dir(&dir).unwrap();
+
+    let release: Release = serde_yaml::from_str(
+        r#"
+name: app
+dist_folder: dist
+targets:
+  custom:
+    - name: save
+      command: cat > payload.json
+builds: []
+"#,
+    )
+    .unwrap();
+    let changelog = |tag: &str| {
+        let release = release.clone();
+        let tag = tag.to_string();
+        async move {
+            let stages = ReleasePipeline::new()
+                .with_release(release.clone())
+                .with_opts(Opts {
+                    publish: true,
+                    yes: true,
+                    to_tag: Some(tag),
+                    ..Default::default()
+                })
+                .prepare()
+                .await
+                .unwrap();
+            stages.publish(&release, vec![]).await.unwrap();
+            let payload: serde_json::Value =
+                serde_json::from_str(&fs::read_to_string("payload.json").unwrap()).unwrap();
+            payload["changelog"].as_str().unwrap().to_string()
+        }
+    };
+
+    let first = changelog("v0.1.0").await;
+    assert!(first.contains("feat: first"), "{}", first);
+    assert!(!first.contains("fix: second"), "{}", first);
+    assert!(!first.contains("chore: after the tags"), "{}", first);
+
+    let second = changelog("v0.2.0").await;
+    assert!(second.contains("fix: second"), "{}", second);
+    assert!(!second.contains("feat: first"), "{}", second);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,36 @@
+// Helpers for the integration tests. Each test binary uses some of them.
+#![allow(dead_code)]
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+pub fn git(dir: &Path, args: &[&str]) {
+    let status = Command::new("git")
+        .args(["-c", "user.name=rlsr", "-c", "user.email=rlsr@example.com"])
+        .args(args)
+        .current_dir(dir)
+        .status()
+        .unwrap();
+    assert!(status.success(), "git {:?} failed", args);
+}
+
+// Makes an empty repo in the temp dir, ignoring the dist folder.
+pub fn init_repo(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("rlsr-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join(".gitignore"), "dist/\n").unwrap();
+    git(&dir, &["init", "-q"]);
+    dir
+}
+
+// Commits a file with the message and tags the commit if a tag is given.
+pub fn commit(dir: &Path, file: &str, message: &str, tag: Option<&str>) {
+    fs::write(dir.join(file), message).unwrap();
+    git(dir, &["add", "-A"]);
+    git(dir, &["commit", "-q", "-m", message]);
+    if let Some(tag) = tag {
+        git(dir, &["tag", "-a", "-m", tag, tag]);
+    }
+}
@@ -0,0 +1,72 @@
+mod common;
+
+use common::{git, init_repo};
+use rlsr::config::Release;
+use rlsr::{Opts, ReleasePipeline};
+use std::fs;
+
+// Runs the build, checksum and publish stages one by one on a tagged repo,
+// publishing to a custom target which saves its payload.
+#[tokio::test]
+async fn runs_stages_separately() {
+    let dir = init_repo("pipeline");
+    fs::write(dir.join("app"), "#!/bin/sh\necho app\n").unwrap();
+    git(&dir, &["add", "app"]);
+    git(&dir, &["commit", "-q", "-m", "feat: add app"]);
+    git(&dir, &["tag", "-a", "-m", "v0.1.0", "v0.1.0"]);
+    // The stages find the repo from the current dir, and this is the only
+    // test in this binary.
+    std::env::set_current_dir(&dir).unwrap();
+
+    let release: Release = serde_yaml::from_str(
+        r#"
+name: app
+dist_folder: dist
+targets:
+  custom:
+    - name: save
+      command: cat > payload.json
+builds:
+  - command: "true"
+    bin_name: app
+    artifact: app
+    name: "app-{{ tag }}"
+"#,
+    )
+    .unwrap();
+    let stages = ReleasePipeline::new()
+        .with_release(release.clone())
+        .with_opts(Opts {
+            publish: true,
+            yes: true,
+            ..Default::default()
+        })
+        .prepare()
+        .await
+        .unwrap();
+
+    let archives = stages.build(&release).await.unwrap();
+    assert_eq!(archives, vec!["dist/app-v0.1.0.zip".to_string()]);
+
+    let checksums = stages.checksum(&archives).await.unwrap();
+    assert_eq!(checksums.len(), 1);
+    assert_eq!(checksums[0].path, archives[0]);
+    assert_eq!(checksums[0].sha256.len(), 64);
+
+    let results = stages.publish(&release, archives).await.unwrap();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].provider, "save");
+    let written: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string("dist/artifacts.json").unwrap()).unwrap();
+    assert_eq!(written[0]["provider"], "save");
+    let payload: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string("payload.json").unwrap()).unwrap();
+    assert_eq!(payload["tag"], "v0.1.0");
+    assert_eq!(payload["artifacts"][0]["name"], "app-v0.1.0.zip");
+    assert_eq!(
+        payload["artifacts"][0]["sha256"],
+        checksums[0].sha256.as_str()
+    );
+
+    fs::remove_dir_all(&dir).unwrap();
+}